@@ -1,9 +1,180 @@
 CREATE TABLE IF NOT EXISTS pdf (
     id SERIAL PRIMARY KEY,
     public_key TEXT NOT NULL,
-    private_key TEXT NOT NULL,
+    private_key TEXT,
     file_path TEXT NOT NULL,
     record_num TEXT NOT NULL,
     created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-    description TEXT
+    description TEXT,
+    key_alg TEXT NOT NULL DEFAULT 'RSA-OAEP-256',
+    key_threshold SMALLINT,
+    sender_id TEXT,
+    expires_at TIMESTAMP,
+    consumed_at TIMESTAMP,
+    file_key TEXT
+);
+
+-- Columns added since the table was first created, for databases set up before them
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS key_threshold SMALLINT;
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS sender_id TEXT;
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS key_alg TEXT NOT NULL DEFAULT 'RSA-OAEP-256';
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP;
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS consumed_at TIMESTAMP;
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS file_key TEXT;
+-- When TX received the upload, as signed by the sender
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS tx_received_at TIMESTAMPTZ;
+-- Whether the package that used the key was signed, only signed packages join the case number TX detected
+ALTER TABLE pdf ADD COLUMN IF NOT EXISTS package_signed BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE pdf ALTER COLUMN private_key DROP NOT NULL;
+
+-- Keys issued before single use and expiry: stored or sealed packages used theirs up, the others expire
+-- after the default rx.key_expiry.ttl_secs so the janitor purges them if no package arrives
+UPDATE pdf SET consumed_at = COALESCE(created_at, CURRENT_TIMESTAMP)
+WHERE consumed_at IS NULL AND description IN ('Received', 'Sealed');
+UPDATE pdf SET expires_at = CURRENT_TIMESTAMP + make_interval(secs => 900)
+WHERE expires_at IS NULL AND consumed_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS pdf_record_num_idx ON pdf (record_num);
+CREATE INDEX IF NOT EXISTS pdf_description_idx ON pdf (description);
+
+CREATE TABLE IF NOT EXISTS court_case (
+    id SERIAL PRIMARY KEY,
+    case_number TEXT NOT NULL UNIQUE,
+    court TEXT,
+    parties TEXT,
+    status TEXT NOT NULL DEFAULT 'Open',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Cases opened before created_at was required
+UPDATE court_case SET created_at = CURRENT_TIMESTAMP WHERE created_at IS NULL;
+ALTER TABLE court_case ALTER COLUMN created_at SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS court_case_created_at_idx ON court_case (created_at, id);
+
+CREATE TABLE IF NOT EXISTS document (
+    id SERIAL PRIMARY KEY,
+    case_id INTEGER NOT NULL REFERENCES court_case(id),
+    record_num TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    title TEXT,
+    subject TEXT,
+    author TEXT,
+    keywords TEXT,
+    file_size BIGINT,
+    sha256 TEXT,
+    package_hash TEXT,
+    stored_at TIMESTAMP,
+    creator TEXT,
+    producer TEXT,
+    creation_date TIMESTAMPTZ,
+    mod_date TIMESTAMPTZ,
+    page_count INTEGER,
+    pdf_version TEXT,
+    encrypted BOOLEAN,
+    xmp JSONB,
+    content TEXT,
+    detected_case_number TEXT,
+    search TSVECTOR GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', coalesce(title, '')), 'A') ||
+        setweight(to_tsvector('english', coalesce(subject, '') || ' ' || coalesce(keywords, '')), 'B') ||
+        setweight(to_tsvector('english', coalesce(author, '')), 'C') ||
+        setweight(to_tsvector('english', coalesce(content, '')), 'D')
+    ) STORED
+);
+
+-- Columns added since the table was first created, for databases set up before them
+ALTER TABLE document ADD COLUMN IF NOT EXISTS title TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS subject TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS author TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS keywords TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS file_size BIGINT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS sha256 TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS creator TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS producer TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS creation_date TIMESTAMPTZ;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS mod_date TIMESTAMPTZ;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS page_count INTEGER;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS pdf_version TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS encrypted BOOLEAN;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS xmp JSONB;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS content TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS search TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', coalesce(title, '')), 'A') ||
+    setweight(to_tsvector('english', coalesce(subject, '') || ' ' || coalesce(keywords, '')), 'B') ||
+    setweight(to_tsvector('english', coalesce(author, '')), 'C') ||
+    setweight(to_tsvector('english', coalesce(content, '')), 'D')
+) STORED;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS package_hash TEXT;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS stored_at TIMESTAMP;
+ALTER TABLE document ADD COLUMN IF NOT EXISTS detected_case_number TEXT;
+
+CREATE INDEX IF NOT EXISTS document_search_idx ON document USING GIN (search);
+CREATE INDEX IF NOT EXISTS document_case_id_idx ON document (case_id);
+-- PDFs received before cases existed, each filed under a case of its own
+INSERT INTO court_case (case_number, created_at)
+SELECT p.record_num, COALESCE(p.created_at, CURRENT_TIMESTAMP) FROM pdf p
+WHERE NOT EXISTS (SELECT 1 FROM document d WHERE d.record_num = p.record_num)
+ON CONFLICT (case_number) DO NOTHING;
+
+INSERT INTO document (case_id, record_num, created_at)
+SELECT c.id, p.record_num, p.created_at FROM pdf p
+JOIN court_case c ON c.case_number = p.record_num
+WHERE NOT EXISTS (SELECT 1 FROM document d WHERE d.record_num = p.record_num);
+
+CREATE TABLE IF NOT EXISTS juror (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS key_share (
+    id SERIAL PRIMARY KEY,
+    record_num TEXT NOT NULL,
+    juror_id INTEGER NOT NULL REFERENCES juror(id),
+    encrypted_share TEXT NOT NULL,
+    share_hash TEXT NOT NULL,
+    UNIQUE (record_num, juror_id)
+);
+
+CREATE TABLE IF NOT EXISTS key_pool (
+    id SERIAL PRIMARY KEY,
+    public_key TEXT NOT NULL,
+    private_key TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS audit_event (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL,
+    action TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    case_number TEXT,
+    pdf_id TEXT,
+    detail JSONB NOT NULL,
+    prev_hash TEXT NOT NULL,
+    hash TEXT NOT NULL UNIQUE
 );
+
+-- KEK whose derived key MACs the row, NULL for rows hashed with plain SHA-256 before keyed rows
+ALTER TABLE audit_event ADD COLUMN IF NOT EXISTS kek_id TEXT;
+
+CREATE INDEX IF NOT EXISTS audit_event_case_number_idx ON audit_event (case_number);
+CREATE INDEX IF NOT EXISTS audit_event_pdf_id_idx ON audit_event (pdf_id);
+-- Case numbers returned by case listings, for the custody report
+CREATE INDEX IF NOT EXISTS audit_event_cases_idx ON audit_event USING GIN ((detail -> 'cases'));
+
+CREATE OR REPLACE FUNCTION audit_event_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_event is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER audit_event_no_update
+    BEFORE UPDATE OR DELETE ON audit_event
+    FOR EACH ROW EXECUTE FUNCTION audit_event_append_only();
+
+CREATE OR REPLACE TRIGGER audit_event_no_truncate
+    BEFORE TRUNCATE ON audit_event
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_event_append_only();
@@ -12,9 +12,58 @@ pub struct Usuario {
 pub struct Pdf {
     pub id: i32,
     pub public_key: String,
-    pub private_key: String,
+    pub private_key: Option<String>,
     pub file_path: String,
     pub record_num: String,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub description: Option<String>,
+    pub key_alg: String,
+    pub key_threshold: Option<i16>,
+    pub sender_id: Option<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub consumed_at: Option<chrono::NaiveDateTime>,
+    pub file_key: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, sqlx::FromRow)]
+pub struct CourtCase {
+    pub id: i32,
+    pub case_number: String,
+    pub court: Option<String>,
+    pub parties: Option<String>,
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, sqlx::FromRow)]
+pub struct Document {
+    pub id: i32,
+    pub case_id: i32,
+    pub record_num: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub title: Option<String>,
+    pub subject: Option<String>,
+    pub author: Option<String>,
+    pub keywords: Option<String>,
+    pub file_size: Option<i64>,
+    pub sha256: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    pub creation_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub mod_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub page_count: Option<i32>,
+    pub pdf_version: Option<String>,
+    pub encrypted: Option<bool>,
+    pub xmp: Option<sqlx::types::JsonValue>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, sqlx::FromRow)]
+pub struct Juror {
+    pub id: i32,
+    pub name: String,
+    pub public_key: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
 }
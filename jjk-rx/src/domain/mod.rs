@@ -1,43 +1,778 @@
-use crate::prelude::*;
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct RxPayload {
-    pub pdf_id: String,
-    pub pkg: EncryptedPackage,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct EncryptedPackage {
-    pub encrypted_session_key_b64: String,
-    pub encrypted_data_b64: String,
-    pub nonce_b64: String,
-    pub hash_b64: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct RxKeyResponse {
-    pub pdf_id: String,
-    pub pub_key: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct PdfData {
-    pub title: String,
-    pub subject: String,
-    pub author: String,
-    pub keywords: String,
-    pub file: Vec<u8>,
-}
-
-#[derive(Serialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct CaseSummary {
-    pub case_code: String,
-    pub file_path: String,
-    pub description: Option<String>,
-    pub created_at: Option<chrono::NaiveDateTime>,
-}
+use crate::prelude::*;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RxPayload {
+    pub pdf_id: String,
+    pub pkg: EncryptedPackage,
+}
+
+/// CBOR form of `RxPayload`, with raw bytes where the JSON form has base64.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BinaryRxPayload {
+    pub pdf_id: String,
+    pub pkg: BinaryPackage,
+}
+
+/// Content type of the CBOR envelope, the alternative to the JSON `RxPayload`.
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Encoding of an envelope, and of the `PdfData` inside it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireFormat {
+    Json,
+    Cbor,
+}
+
+impl WireFormat {
+    pub fn decode_pdf(self, plaintext: &[u8]) -> Result<PdfData> {
+        match self {
+            Self::Json => Ok(serde_json::from_slice(plaintext)?),
+            Self::Cbor => ciborium::from_reader(plaintext).map_err(|e| anyhow!("{}", e)),
+        }
+    }
+}
+
+/// Envelope version produced by the current TX, which binds the compression to the ciphertext and signature.
+pub const ENVELOPE_VERSION: u8 = 4;
+/// Envelope version of signed packages bound to their header, before the compression was bound too.
+pub const SIGNED_ENVELOPE_VERSION: u8 = 3;
+/// Envelope version of RSA-OAEP packages sent before the ciphertext was bound to its header.
+pub const OAEP_ENVELOPE_VERSION: u8 = 2;
+/// Envelope version sent by TX before the version and algorithm were declared.
+pub const LEGACY_ENVELOPE_VERSION: u8 = 1;
+/// Session key wrapped with RSA-OAEP (SHA-256).
+pub const ALG_RSA_OAEP_256: &str = "RSA-OAEP-256";
+/// Session key derived with HKDF-SHA256 from an X25519 exchange between an ephemeral TX key and the case key.
+pub const ALG_X25519_HKDF_SHA256: &str = "X25519-HKDF-SHA256";
+/// Session key wrapped with RSA PKCS#1 v1.5, only accepted from legacy envelopes.
+pub const ALG_RSA1_5: &str = "RSA1_5";
+
+/// Plaintext compressed with zstd before encryption.
+pub const COMPRESSION_ZSTD: &str = "zstd";
+/// Plaintext compressed with raw deflate before encryption.
+pub const COMPRESSION_DEFLATE: &str = "deflate";
+
+/// Bytes authenticated as AES-GCM associated data, binding the ciphertext to its PDF ID and envelope header,
+/// and from version 4 on to its compression. Envelopes older than version 3 were encrypted without any.
+pub fn associated_data(pdf_id: &str, version: u8, alg: &str, compression: Option<&str>) -> Vec<u8> {
+    match version {
+        version if version < SIGNED_ENVELOPE_VERSION => Vec::new(),
+        SIGNED_ENVELOPE_VERSION => format!("jjk:v{}:{}:{}", version, alg, pdf_id).into_bytes(),
+        _ => format!("jjk:v{}:{}:{}:{}", version, alg, pdf_id, compression.unwrap_or("none")).into_bytes(),
+    }
+}
+
+/// Bytes the sender signs: the package hash bound to its PDF ID and envelope header, the time TX received
+/// the upload when the package carries it, and from version 4 on its compression.
+pub fn signed_message(
+    pdf_id: &str,
+    version: u8,
+    alg: &str,
+    hash_b64: &str,
+    received_at: Option<&str>,
+    compression: Option<&str>,
+) -> Vec<u8> {
+    let mut msg = match received_at {
+        Some(received_at) => format!("jjk-sig:v{}:{}:{}:{}:{}", version, alg, pdf_id, hash_b64, received_at),
+        None => format!("jjk-sig:v{}:{}:{}:{}", version, alg, pdf_id, hash_b64),
+    };
+    if version > SIGNED_ENVELOPE_VERSION {
+        msg.push(':');
+        msg.push_str(compression.unwrap_or("none"));
+    }
+
+    msg.into_bytes()
+}
+
+/// Headers of a signed key request: the sender ID, the Unix time of the request and the base64 signature
+/// over `key_request_message`.
+pub const KEY_REQUEST_SENDER_HEADER: &str = "X-JJK-Sender";
+pub const KEY_REQUEST_TIMESTAMP_HEADER: &str = "X-JJK-Timestamp";
+pub const KEY_REQUEST_SIGNATURE_HEADER: &str = "X-JJK-Signature";
+/// How far the time of a signed key request may be from RX's clock.
+pub const KEY_REQUEST_MAX_SKEW_SECS: i64 = 300;
+
+/// Bytes the sender signs to request a key, binding the case or group it files the PDF under.
+pub fn key_request_message(timestamp: i64, alg: &str, case: Option<&str>, group: Option<&str>) -> Vec<u8> {
+    format!("jjk-key:{}:{}:{}:{}", timestamp, alg, case.unwrap_or_default(), group.unwrap_or_default()).into_bytes()
+}
+
+/// Content type of the chunked stream format, the alternative to the JSON `RxPayload`.
+pub const STREAM_CONTENT_TYPE: &str = "application/vnd.jjk.stream";
+
+/// Header of a chunked stream. It is followed by `flag: u8 | len: u32 | ciphertext` frames, encrypted with
+/// AES-256-GCM STREAM (big-endian 32-bit counter, `flag` 1 on the final chunk). The first chunk holds the
+/// `PdfInfo` JSON, the rest hold the document. `hash_b64` covers the plaintext of every chunk in order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamHeader {
+    pub pdf_id: String,
+    pub version: u8,
+    pub alg: String,
+    #[serde(default)]
+    pub encrypted_session_key_b64: String,
+    #[serde(default)]
+    pub ephemeral_public_key_b64: Option<String>,
+    /// 7 byte STREAM nonce prefix.
+    pub nonce_prefix_b64: String,
+    pub hash_b64: String,
+    pub sender_id: String,
+    pub signature_b64: String,
+    /// When TX received the upload (RFC 3339), covered by the signature. Missing from older TX versions.
+    #[serde(default)]
+    pub received_at: Option<String>,
+}
+
+fn legacy_version() -> u8 {
+    LEGACY_ENVELOPE_VERSION
+}
+
+fn legacy_alg() -> String {
+    ALG_RSA1_5.to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedPackage {
+    #[serde(default = "legacy_version")]
+    pub version: u8,
+    #[serde(default = "legacy_alg")]
+    pub alg: String,
+    pub encrypted_session_key_b64: String,
+    /// Ephemeral X25519 public key, only set for key agreement envelopes.
+    #[serde(default)]
+    pub ephemeral_public_key_b64: Option<String>,
+    pub encrypted_data_b64: String,
+    pub nonce_b64: String,
+    pub hash_b64: String,
+    /// Missing only on legacy envelopes, which predate sender signatures.
+    #[serde(default)]
+    pub sender_id: Option<String>,
+    #[serde(default)]
+    pub signature_b64: Option<String>,
+    /// How the plaintext was compressed before encryption, absent when it wasn't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// When TX received the upload (RFC 3339), covered by the signature. Missing from older TX versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<String>,
+}
+
+impl EncryptedPackage {
+    /// Decodes the base64 fields, giving the same package as the CBOR envelope carries.
+    pub fn decode(&self) -> Result<BinaryPackage> {
+        let decode = |field: &str, value: &str| b64.decode(value)
+            .map_err(|e| anyhow!("Failed to decode {}: {}", field, e));
+
+        Ok(BinaryPackage {
+            version: self.version,
+            alg: self.alg.clone(),
+            encrypted_session_key: decode("session key", &self.encrypted_session_key_b64)?,
+            ephemeral_public_key: self.ephemeral_public_key_b64.as_deref()
+                .map(|epk| decode("ephemeral public key", epk))
+                .transpose()?,
+            encrypted_data: decode("encrypted data", &self.encrypted_data_b64)?,
+            nonce: decode("nonce", &self.nonce_b64)?,
+            hash: decode("hash", &self.hash_b64)?,
+            sender_id: self.sender_id.clone(),
+            signature: self.signature_b64.as_deref()
+                .map(|signature| decode("signature", signature))
+                .transpose()?,
+            compression: self.compression.clone(),
+            received_at: self.received_at.clone(),
+        })
+    }
+}
+
+/// `EncryptedPackage` with its fields as raw bytes. Only versioned envelopes are accepted in CBOR.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BinaryPackage {
+    pub version: u8,
+    pub alg: String,
+    #[serde(with = "serde_bytes")]
+    pub encrypted_session_key: Vec<u8>,
+    #[serde(default, with = "serde_bytes")]
+    pub ephemeral_public_key: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub encrypted_data: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub hash: Vec<u8>,
+    #[serde(default)]
+    pub sender_id: Option<String>,
+    #[serde(default, with = "serde_bytes")]
+    pub signature: Option<Vec<u8>>,
+    #[serde(default)]
+    pub compression: Option<String>,
+    #[serde(default)]
+    pub received_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RxKeyResponse {
+    pub pdf_id: String,
+    pub pub_key: String,
+    pub alg: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct KeyRequest {
+    /// Key algorithm TX wants for the case, RSA-OAEP when absent.
+    pub alg: Option<String>,
+    /// Case number to file the PDF under, the case is opened if it doesn't exist yet. Needs a signed request.
+    pub case: Option<String>,
+    /// PDF ID of an earlier document whose case this one joins, e.g. other exhibits of the same upload.
+    /// Needs a request signed by the sender of that document.
+    pub group: Option<String>,
+}
+
+/// Document metadata carried in the first chunk of a stream, and next to the file in `PdfData`.
+/// Older TX versions only send the four text fields, as "Unknown" when the PDF doesn't have them.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfInfo {
+    pub title: Option<String>,
+    pub subject: Option<String>,
+    pub author: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    pub creation_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub mod_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub page_count: Option<i32>,
+    pub pdf_version: Option<String>,
+    pub encrypted: Option<bool>,
+    /// Simple XMP properties keyed by prefixed name, e.g. `dc:title`.
+    #[serde(default)]
+    pub xmp: BTreeMap<String, String>,
+    /// Case number TX found in the file name, metadata or first page.
+    pub case_number: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfData {
+    #[serde(flatten)]
+    pub info: PdfInfo,
+    /// A byte string in CBOR, an array of numbers in JSON.
+    #[serde(with = "serde_bytes")]
+    pub file: Vec<u8>,
+}
+
+/// What RX keeps about a received document besides the file itself.
+#[derive(Debug)]
+pub struct DocumentMetadata {
+    pub info: PdfInfo,
+    pub file_size: i64,
+    /// Hex SHA-256 of the PDF as received, the same as `sha256sum` prints.
+    pub sha256: String,
+    /// Base64 SHA-256 of the package plaintext, as signed by TX (`hash_b64`).
+    pub package_hash: String,
+    /// Text of the pages, indexed for search. `None` when it couldn't be extracted.
+    pub content: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CaseSummary {
+    pub case_number: String,
+    pub court: Option<String>,
+    pub parties: Option<String>,
+    pub status: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub documents: Vec<DocumentSummary>,
+}
+
+/// One received PDF of a case, `case_code` being its PDF ID.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSummary {
+    pub case_code: String,
+    pub title: Option<String>,
+    pub subject: Option<String>,
+    pub author: Option<String>,
+    pub keywords: Option<String>,
+    pub file_size: Option<i64>,
+    pub sha256: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    pub creation_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub mod_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub page_count: Option<i32>,
+    pub pdf_version: Option<String>,
+    pub encrypted: Option<bool>,
+    pub xmp: Option<BTreeMap<String, String>>,
+    /// Case number TX detected in the PDF, whether or not the document could be filed under it.
+    pub detected_case_number: Option<String>,
+    pub sender_id: Option<String>,
+    pub file_path: String,
+    pub description: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Filters and paging of `GET /cases`. With a document filter, cases are listed with the documents that match,
+/// and left out when none do.
+#[derive(Deserialize, Debug)]
+pub struct CaseFilter {
+    /// Part of the author name, case-insensitive.
+    pub author: Option<String>,
+    /// One of the comma or semicolon separated keywords, case-insensitive.
+    pub keyword: Option<String>,
+    /// Document state as kept in `pdf.description`, e.g. `Received` or `Sealed`.
+    pub status: Option<String>,
+    /// Cases opened on or after this day.
+    pub from: Option<chrono::NaiveDate>,
+    /// Cases opened on or before this day.
+    pub to: Option<chrono::NaiveDate>,
+    #[serde(default)]
+    pub sort: CaseSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// `nextCursor` of the previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CaseSort {
+    #[default]
+    CreatedAt,
+    CaseNumber,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Position after the last case of a page: its sort value and ID, as an opaque URL-safe token.
+/// Only valid for the sort and order it was issued for.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CaseCursor {
+    pub sort: CaseSort,
+    pub order: SortOrder,
+    pub value: String,
+    pub id: i32,
+}
+
+impl CaseCursor {
+    pub fn encode(&self) -> Result<String> {
+        Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(self)?))
+    }
+
+    pub fn decode(token: &str) -> Result<Self> {
+        Ok(serde_json::from_slice(&URL_SAFE_NO_PAD.decode(token)?)?)
+    }
+}
+
+/// One page of `GET /cases`. `total` counts every case matching the filters, `nextCursor` is `None` on the last page.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CasePage {
+    pub cases: Vec<CaseSummary>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    /// Web search syntax: words, "quoted phrases", `or` and `-excluded` words.
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+/// A document matching a search, best matches first.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub case_number: String,
+    pub court: Option<String>,
+    pub status: String,
+    pub case_code: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub rank: f32,
+    /// Matching passages, with the matched words wrapped in `<mark>` and passages separated by ` ... `.
+    pub snippet: String,
+}
+
+/// Details of a case, registered ahead of its documents or filled in afterwards.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CaseRegistration {
+    pub case_number: String,
+    pub court: Option<String>,
+    pub parties: Option<String>,
+    pub status: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JurorRegistration {
+    pub name: String,
+    pub public_key: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingShare {
+    pub case_code: String,
+    pub threshold: i16,
+    pub encrypted_share_b64: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareSubmission {
+    pub case_code: String,
+    pub juror: String,
+    pub share_b64: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareStatus {
+    pub case_code: String,
+    pub submitted: usize,
+    pub threshold: i16,
+    pub quorum_reached: bool,
+}
+
+pub const AUDIT_KEY_ISSUED: &str = "key_issued";
+pub const AUDIT_PACKAGE_RECEIVED: &str = "package_received";
+pub const AUDIT_PACKAGE_OPENED: &str = "package_opened";
+pub const AUDIT_CASE_REGISTERED: &str = "case_registered";
+pub const AUDIT_CASES_LISTED: &str = "cases_listed";
+pub const AUDIT_CASES_SEARCHED: &str = "cases_searched";
+pub const AUDIT_CASE_DOWNLOADED: &str = "case_downloaded";
+pub const AUDIT_DOCUMENT_DOWNLOADED: &str = "document_downloaded";
+pub const AUDIT_CUSTODY_REPORTED: &str = "custody_reported";
+
+/// `prev_hash` of the first audit row.
+pub const AUDIT_GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Something worth answering for later: who did what to which case.
+#[derive(Debug)]
+pub struct AuditEvent {
+    pub action: String,
+    /// Authenticated sender, requesting user or client address.
+    pub actor: String,
+    pub case_number: Option<String>,
+    pub pdf_id: Option<String>,
+    pub detail: serde_json::Value,
+}
+
+impl AuditEvent {
+    /// What the hash of a row covers, including the hash of the row before it. The content is a JSON array,
+    /// with object keys sorted, so it reads back the same from the `jsonb` column.
+    pub fn content(&self, prev_hash: &str, created_at: &chrono::DateTime<chrono::Utc>) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&(
+            prev_hash,
+            created_at.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            &self.action,
+            &self.actor,
+            &self.case_number,
+            &self.pdf_id,
+            &self.detail,
+        ))?)
+    }
+
+    /// Hex SHA-256 of the row, as rows were hashed before they were keyed with `Keyring::audit_mac`.
+    pub fn hash(&self, prev_hash: &str, created_at: &chrono::DateTime<chrono::Utc>) -> Result<String> {
+        Ok(format!("{:x}", Sha256::digest(self.content(prev_hash, created_at)?)))
+    }
+}
+
+/// Outcome of checking the audit chain from its first row.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditVerification {
+    pub valid: bool,
+    /// Rows checked, up to and including the first broken one.
+    pub checked: i64,
+    pub first_broken: Option<BrokenLink>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BrokenLink {
+    pub id: i64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Pdf,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CustodyQuery {
+    #[serde(default)]
+    pub format: ReportFormat,
+}
+
+/// How a case's documents reached RX and every access to them since, for court use.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CustodyReport {
+    pub case_number: String,
+    pub court: Option<String>,
+    pub parties: Option<String>,
+    pub status: String,
+    pub opened_at: chrono::NaiveDateTime,
+    pub generated_at: chrono::DateTime<chrono::Utc>,
+    pub generated_by: String,
+    pub documents: Vec<DocumentCustody>,
+    /// Audit events of the case and its documents, oldest first, including listings and searches that showed the case.
+    pub events: Vec<CustodyEvent>,
+    /// State of the whole audit chain when the report was generated.
+    pub audit_chain: AuditVerification,
+}
+
+/// Path of one document from TX to storage. Times are those of the RX database.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentCustody {
+    pub case_code: String,
+    pub title: Option<String>,
+    /// When TX received the upload, as signed by the sender. `None` for unsigned packages and older TX versions.
+    pub tx_received_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When TX, holding the upload, was issued the document's key.
+    pub key_issued_at: Option<chrono::NaiveDateTime>,
+    /// When the package reached RX and claimed the key.
+    pub received_at: Option<chrono::NaiveDateTime>,
+    /// When the decrypted PDF was stored, after the jury quorum for sealed packages.
+    pub stored_at: Option<chrono::NaiveDateTime>,
+    pub sender_id: Option<String>,
+    /// Base64 SHA-256 of the package plaintext, as signed by TX.
+    pub package_hash: Option<String>,
+    /// Hex SHA-256 of the stored PDF.
+    pub sha256: Option<String>,
+    pub file_size: Option<i64>,
+    pub verification: CustodyVerification,
+    /// Packages RX turned away for the document, e.g. failing authentication or decryption.
+    pub rejected_packages: usize,
+    pub storage_location: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CustodyVerification {
+    /// Signed by a trusted sender, decrypted and matching its hash.
+    Verified,
+    /// A legacy envelope, decrypted and matching its hash but not signed.
+    Unsigned,
+    /// Received and kept encrypted until the jury quorum.
+    Sealed,
+    /// Only packages RX turned away so far.
+    Failed,
+    /// No package received yet.
+    Pending,
+}
+
+impl CustodyVerification {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Verified => "Signature and hash verified",
+            Self::Unsigned => "Hash verified, unsigned legacy envelope",
+            Self::Sealed => "Sealed until the jury quorum",
+            Self::Failed => "Failed, every package received was rejected",
+            Self::Pending => "Awaiting package",
+        }
+    }
+}
+
+/// An audit row as it appears in a custody report, `hash` tying it to the chain.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CustodyEvent {
+    pub id: i64,
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub action: String,
+    pub actor: String,
+    pub pdf_id: Option<String>,
+    pub detail: serde_json::Value,
+    pub hash: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn associated_data_by_version() {
+        assert!(associated_data("pdf-1", OAEP_ENVELOPE_VERSION, ALG_RSA_OAEP_256, None).is_empty());
+        assert_eq!(
+            associated_data("pdf-1", SIGNED_ENVELOPE_VERSION, ALG_RSA_OAEP_256, Some(COMPRESSION_ZSTD)),
+            b"jjk:v3:RSA-OAEP-256:pdf-1",
+        );
+        assert_eq!(
+            associated_data("pdf-1", ENVELOPE_VERSION, ALG_RSA_OAEP_256, Some(COMPRESSION_ZSTD)),
+            b"jjk:v4:RSA-OAEP-256:pdf-1:zstd",
+        );
+        assert_eq!(associated_data("pdf-1", ENVELOPE_VERSION, ALG_RSA_OAEP_256, None), b"jjk:v4:RSA-OAEP-256:pdf-1:none");
+    }
+
+    #[test]
+    fn signed_message_by_version() {
+        assert_eq!(
+            signed_message("pdf-1", SIGNED_ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, "aGFzaA==", None, Some(COMPRESSION_ZSTD)),
+            b"jjk-sig:v3:X25519-HKDF-SHA256:pdf-1:aGFzaA==",
+        );
+        assert_eq!(
+            signed_message("pdf-1", ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, "aGFzaA==", Some("2026-01-02T03:04:05.000Z"), None),
+            b"jjk-sig:v4:X25519-HKDF-SHA256:pdf-1:aGFzaA==:2026-01-02T03:04:05.000Z:none",
+        );
+        assert_ne!(
+            signed_message("pdf-1", ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, "aGFzaA==", None, Some(COMPRESSION_ZSTD)),
+            signed_message("pdf-1", ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, "aGFzaA==", None, Some(COMPRESSION_DEFLATE)),
+        );
+    }
+
+    #[test]
+    fn key_request_message_binds_case_and_group() {
+        assert_eq!(key_request_message(1700000000, ALG_RSA_OAEP_256, Some("C-1"), None), b"jjk-key:1700000000:RSA-OAEP-256:C-1:");
+        assert_eq!(key_request_message(1700000000, ALG_RSA_OAEP_256, None, Some("pdf-1")), b"jjk-key:1700000000:RSA-OAEP-256::pdf-1");
+    }
+
+    #[test]
+    fn case_cursor_round_trip() {
+        let cursor = CaseCursor {
+            sort: CaseSort::CaseNumber,
+            order: SortOrder::Asc,
+            value: "C-2024/17".to_string(),
+            id: 42,
+        };
+
+        let token = cursor.encode().unwrap();
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+
+        let decoded = CaseCursor::decode(&token).unwrap();
+        assert_eq!(decoded.sort, CaseSort::CaseNumber);
+        assert_eq!(decoded.order, SortOrder::Asc);
+        assert_eq!(decoded.value, "C-2024/17");
+        assert_eq!(decoded.id, 42);
+    }
+
+    #[test]
+    fn malformed_cursors_fail() {
+        assert!(CaseCursor::decode("not a cursor").is_err());
+        assert!(CaseCursor::decode(&URL_SAFE_NO_PAD.encode(b"{\"sort\":\"caseNumber\"}")).is_err());
+        // Cursors issued before the order was part of them
+        assert!(CaseCursor::decode(&URL_SAFE_NO_PAD.encode(b"{\"sort\":\"caseNumber\",\"value\":\"C-1\",\"id\":1}")).is_err());
+    }
+
+    #[test]
+    fn pdf_data_round_trip_in_both_formats() {
+        let data: PdfData = serde_json::from_value(serde_json::json!({
+            "title": "Judgment",
+            "pageCount": 3,
+            "caseNumber": "C-1",
+            "file": [37, 80, 68, 70],
+        }))
+        .unwrap();
+
+        let json = serde_json::to_vec(&data).unwrap();
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&data, &mut cbor).unwrap();
+
+        for (format, bytes) in [(WireFormat::Json, json), (WireFormat::Cbor, cbor)] {
+            let decoded = format.decode_pdf(&bytes).unwrap();
+            assert_eq!(decoded.file, b"%PDF");
+            assert_eq!(decoded.info.title.as_deref(), Some("Judgment"));
+            assert_eq!(decoded.info.page_count, Some(3));
+            assert_eq!(decoded.info.case_number.as_deref(), Some("C-1"));
+        }
+
+        assert!(WireFormat::Cbor.decode_pdf(b"{\"file\": []}").is_err());
+    }
+
+    #[test]
+    fn json_package_decodes_to_the_cbor_form() {
+        let pkg: EncryptedPackage = serde_json::from_value(serde_json::json!({
+            "version": ENVELOPE_VERSION,
+            "alg": ALG_X25519_HKDF_SHA256,
+            "encryptedSessionKeyB64": "",
+            "ephemeralPublicKeyB64": b64.encode([1u8; 32]),
+            "encryptedDataB64": b64.encode(b"ciphertext"),
+            "nonceB64": b64.encode([2u8; 12]),
+            "hashB64": b64.encode([3u8; 32]),
+            "senderId": "jjk-tx",
+            "signatureB64": b64.encode([4u8; 64]),
+            "compression": COMPRESSION_ZSTD,
+        }))
+        .unwrap();
+
+        let binary = pkg.decode().unwrap();
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&binary, &mut cbor).unwrap();
+        let binary: BinaryPackage = ciborium::from_reader(cbor.as_slice()).unwrap();
+
+        assert_eq!(binary.encrypted_data, b"ciphertext");
+        assert_eq!(binary.ephemeral_public_key, Some(vec![1u8; 32]));
+        assert_eq!(binary.nonce, [2u8; 12]);
+        assert_eq!(binary.signature, Some(vec![4u8; 64]));
+        assert_eq!(binary.compression.as_deref(), Some(COMPRESSION_ZSTD));
+        assert_eq!(binary.received_at, None);
+
+        let mut invalid = pkg;
+        invalid.nonce_b64 = "not base64!".to_string();
+        assert!(invalid.decode().is_err());
+    }
+
+    #[test]
+    fn legacy_json_package_defaults() {
+        let pkg: EncryptedPackage = serde_json::from_value(serde_json::json!({
+            "encryptedSessionKeyB64": "",
+            "encryptedDataB64": "",
+            "nonceB64": "",
+            "hashB64": "",
+        }))
+        .unwrap();
+
+        assert_eq!(pkg.version, LEGACY_ENVELOPE_VERSION);
+        assert_eq!(pkg.alg, ALG_RSA1_5);
+    }
+
+    fn received(actor: &str, detail: serde_json::Value) -> AuditEvent {
+        AuditEvent {
+            action: AUDIT_PACKAGE_RECEIVED.to_string(),
+            actor: actor.to_string(),
+            case_number: Some("C-1".to_string()),
+            pdf_id: Some("pdf-1".to_string()),
+            detail,
+        }
+    }
+
+    #[test]
+    fn audit_hash_covers_every_field_and_the_previous_row() {
+        let created_at = chrono::DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap();
+        let event = received("jjk-tx", serde_json::json!({ "status": 200, "format": "cbor" }));
+        let hash = event.hash(AUDIT_GENESIS_HASH, &created_at).unwrap();
+
+        // Object keys are sorted, so the detail hashes the same as it reads back from jsonb
+        let reordered = received("jjk-tx", serde_json::json!({ "format": "cbor", "status": 200 }));
+        assert_eq!(reordered.hash(AUDIT_GENESIS_HASH, &created_at).unwrap(), hash);
+
+        let later = created_at + chrono::Duration::microseconds(1);
+        assert_ne!(event.hash(AUDIT_GENESIS_HASH, &later).unwrap(), hash);
+        assert_ne!(event.hash(&hash, &created_at).unwrap(), hash);
+
+        let other_actor = received("someone else", serde_json::json!({ "status": 200, "format": "cbor" }));
+        assert_ne!(other_actor.hash(AUDIT_GENESIS_HASH, &created_at).unwrap(), hash);
+    }
+}
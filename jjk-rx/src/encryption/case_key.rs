@@ -0,0 +1,71 @@
+use crate::prelude::*;
+use crate::domain::{ALG_RSA_OAEP_256, ALG_X25519_HKDF_SHA256};
+use super::Decrypter;
+use rsa::pkcs8::{EncodePrivateKey, DecodePrivateKey};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Private key issued for a single case, in one of the supported key agreement algorithms.
+pub enum CaseKey {
+    Rsa(Box<RsaPrivateKey>),
+    X25519(StaticSecret),
+}
+
+impl CaseKey {
+    /// Generates a key for `alg`, returning it along with the public key handed to TX.
+    pub fn generate(alg: &str) -> Result<(Self, String)> {
+        match alg {
+            ALG_RSA_OAEP_256 => {
+                let (private_key, public_key_pem) = Decrypter::generate_keys()?;
+                Ok((Self::Rsa(Box::new(private_key)), public_key_pem))
+            }
+            ALG_X25519_HKDF_SHA256 => {
+                let secret = StaticSecret::random_from_rng(OsRng);
+                let public_key = PublicKey::from(&secret);
+                Ok((Self::X25519(secret), b64.encode(public_key.as_bytes())))
+            }
+            other => Err(anyhow!("Unsupported key algorithm '{}'", other)),
+        }
+    }
+
+    pub fn alg(&self) -> &'static str {
+        match self {
+            Self::Rsa(_) => ALG_RSA_OAEP_256,
+            Self::X25519(_) => ALG_X25519_HKDF_SHA256,
+        }
+    }
+
+    /// Binary encoding of the key: PKCS#8 DER for RSA, the raw scalar for X25519.
+    pub fn to_der(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::Rsa(private_key) => Ok(private_key.to_pkcs8_der()?.as_bytes().to_vec()),
+            Self::X25519(secret) => Ok(secret.to_bytes().to_vec()),
+        }
+    }
+
+    pub fn from_der(alg: &str, der: &[u8]) -> Result<Self> {
+        match alg {
+            ALG_RSA_OAEP_256 => Ok(Self::Rsa(Box::new(RsaPrivateKey::from_pkcs8_der(der)?))),
+            ALG_X25519_HKDF_SHA256 => {
+                let bytes: [u8; 32] = der.try_into()
+                    .map_err(|_| anyhow!("X25519 key must be 32 bytes"))?;
+                Ok(Self::X25519(StaticSecret::from(bytes)))
+            }
+            other => Err(anyhow!("Unsupported key algorithm '{}'", other)),
+        }
+    }
+
+    /// Text form kept in the `pdf` table: PKCS#8 PEM for RSA, base64 for X25519.
+    pub fn to_stored(&self) -> Result<String> {
+        match self {
+            Self::Rsa(private_key) => Ok(private_key.to_pkcs8_pem(LineEnding::LF)?.to_string()),
+            Self::X25519(secret) => Ok(b64.encode(secret.to_bytes())),
+        }
+    }
+
+    pub fn from_stored(alg: &str, stored: &str) -> Result<Self> {
+        match alg {
+            ALG_RSA_OAEP_256 => Ok(Self::Rsa(Box::new(RsaPrivateKey::from_pkcs8_pem(stored)?))),
+            _ => Self::from_der(alg, &b64.decode(stored)?),
+        }
+    }
+}
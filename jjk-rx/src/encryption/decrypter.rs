@@ -1,54 +1,313 @@
-use crate::prelude::*;
-
-pub struct Decrypter;
-
-impl Decrypter {
-    pub fn generate_keys() -> Result<(RsaPrivateKey, String)> {
-        let mut rng = OsRng;
-        let bits = 2048;
-        let private_key = RsaPrivateKey::new(&mut rng, bits)?;
-        let public_key = RsaPublicKey::from(&private_key);
-        let public_key_pem = public_key.to_public_key_pem(LineEnding::LF)?;
-        Ok((private_key, public_key_pem))
-    }
-
-    pub fn decrypt_hybrid(
-        private_key: &RsaPrivateKey,
-        encrypted_session_key_b64: &str,
-        encrypted_data_b64: &str,
-        nonce_b64: &str,
-    ) -> Result<Vec<u8>> {
-        let enc_session_key = b64.decode(encrypted_session_key_b64)
-            .map_err(|e| anyhow!("Failed to decode session key: {}", e))?;
-        let enc_data = b64.decode(encrypted_data_b64)
-            .map_err(|e| anyhow!("Failed to decode encrypted data: {}", e))?;
-        let nonce_bytes = b64.decode(nonce_b64)
-            .map_err(|e| anyhow!("Failed to decode nonce: {}", e))?;
-        let session_key = private_key.decrypt(Pkcs1v15Encrypt, &enc_session_key)
-            .map_err(|e| anyhow!("RSA Decryption failed: {}", e))?;
-
-        let cipher = Aes256Gcm::new_from_slice(&session_key)
-            .map_err(|e| anyhow!("Invalid AES key length: {}", e))?;
-        
-        if nonce_bytes.len() != 12 {
-            return Err(anyhow!("Invalid nonce length"));
-        }
-        let nonce = aes_gcm::Nonce::from_slice(&nonce_bytes);
-        
-        let plaintext = cipher.decrypt(nonce, enc_data.as_ref())
-            .map_err(|e| anyhow!("AES Decryption failed: {}", e))?;
-
-        Ok(plaintext)
-    }
-
-    pub fn verify_hash(data: &[u8], hash_b64: &str) -> Result<bool> {
-        let expected_hash = b64.decode(hash_b64)
-            .map_err(|e| anyhow!("Failed to decode hash: {}", e))?;
-            
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let actual_hash = hasher.finalize();
-        
-        Ok(actual_hash.as_slice() == expected_hash.as_slice())
-    }
-}
+use crate::prelude::*;
+use crate::domain::{
+    BinaryPackage,
+    ENVELOPE_VERSION, LEGACY_ENVELOPE_VERSION, OAEP_ENVELOPE_VERSION, SIGNED_ENVELOPE_VERSION,
+    ALG_RSA_OAEP_256, ALG_RSA1_5, ALG_X25519_HKDF_SHA256,
+    COMPRESSION_DEFLATE, COMPRESSION_ZSTD,
+};
+use super::CaseKey;
+use hkdf::Hkdf;
+use std::io::Read;
+use x25519_dalek::PublicKey;
+
+/// How the AES session key of a package is established with the case key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyWrap {
+    RsaOaepSha256,
+    RsaPkcs1v15,
+    X25519HkdfSha256,
+}
+
+impl KeyWrap {
+    /// Resolves the wrapping declared by an envelope, rejecting unknown version/algorithm pairs.
+    pub fn for_envelope(version: u8, alg: &str) -> Result<Self> {
+        match (version, alg) {
+            (ENVELOPE_VERSION | SIGNED_ENVELOPE_VERSION | OAEP_ENVELOPE_VERSION, ALG_RSA_OAEP_256) => Ok(Self::RsaOaepSha256),
+            (ENVELOPE_VERSION | SIGNED_ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256) => Ok(Self::X25519HkdfSha256),
+            (LEGACY_ENVELOPE_VERSION, ALG_RSA1_5) => Ok(Self::RsaPkcs1v15),
+            _ => Err(anyhow!("Unsupported envelope version {} with algorithm '{}'", version, alg)),
+        }
+    }
+
+    pub fn is_legacy(self) -> bool {
+        self == Self::RsaPkcs1v15
+    }
+}
+
+pub struct Decrypter;
+
+impl Decrypter {
+    pub fn generate_keys() -> Result<(RsaPrivateKey, String)> {
+        let mut rng = OsRng;
+        let bits = 2048;
+        let private_key = RsaPrivateKey::new(&mut rng, bits)?;
+        let public_key = RsaPublicKey::from(&private_key);
+        let public_key_pem = public_key.to_public_key_pem(LineEnding::LF)?;
+        Ok((private_key, public_key_pem))
+    }
+
+    /// HKDF-SHA256 over the X25519 shared secret, salted with both public keys and bound to `info`.
+    pub fn derive_session_key(
+        shared_secret: &[u8],
+        ephemeral_public_key: &[u8],
+        recipient_public_key: &[u8],
+        info: &[u8],
+    ) -> [u8; 32] {
+        let salt = [ephemeral_public_key, recipient_public_key].concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret);
+
+        let mut session_key = [0u8; 32];
+        hkdf.expand(info, &mut session_key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        session_key
+    }
+
+    /// Recovers the AES session key of a package, either unwrapping it or deriving it from the ephemeral key.
+    /// `aad` is the HKDF info for key agreement.
+    pub fn session_key(
+        case_key: &CaseKey,
+        key_wrap: KeyWrap,
+        encrypted_session_key: &[u8],
+        ephemeral_public_key: Option<&[u8]>,
+        aad: &[u8],
+    ) -> Result<Aes256Gcm> {
+        let session_key = match (case_key, key_wrap) {
+            (CaseKey::Rsa(private_key), KeyWrap::RsaOaepSha256 | KeyWrap::RsaPkcs1v15) => {
+                let unwrapped = match key_wrap {
+                    KeyWrap::RsaPkcs1v15 => private_key.decrypt(Pkcs1v15Encrypt, encrypted_session_key),
+                    _ => private_key.decrypt(Oaep::new::<Sha256>(), encrypted_session_key),
+                };
+
+                // Implicit rejection: a session key that fails to unwrap is replaced by a random one,
+                // so it fails at the tag check exactly like a tampered ciphertext or header would
+                match unwrapped {
+                    Ok(key) if key.len() == 32 => key,
+                    _ => Aes256Gcm::generate_key(&mut OsRng).to_vec(),
+                }
+            }
+            (CaseKey::X25519(secret), KeyWrap::X25519HkdfSha256) => {
+                let ephemeral_public_key: [u8; 32] = ephemeral_public_key
+                    .ok_or_else(|| anyhow!("Missing ephemeral public key"))?
+                    .try_into()
+                    .map_err(|_| anyhow!("Ephemeral public key must be 32 bytes"))?;
+                let ephemeral_public_key = PublicKey::from(ephemeral_public_key);
+
+                let shared_secret = secret.diffie_hellman(&ephemeral_public_key);
+                if !shared_secret.was_contributory() {
+                    return Err(anyhow!("Ephemeral public key is a low order point"));
+                }
+
+                Self::derive_session_key(
+                    shared_secret.as_bytes(),
+                    ephemeral_public_key.as_bytes(),
+                    PublicKey::from(secret).as_bytes(),
+                    aad,
+                ).to_vec()
+            }
+            _ => return Err(anyhow!("Envelope key wrap {:?} does not match the case key ({})", key_wrap, case_key.alg())),
+        };
+
+        Aes256Gcm::new_from_slice(&session_key)
+            .map_err(|e| anyhow!("Invalid AES key length: {}", e))
+    }
+
+    /// Recovers the session key and decrypts the package data, authenticating `aad` along with it.
+    /// Compressed packages are decompressed up to `max_len`, the hash covers the data before compression.
+    /// Legacy envelopes carry no associated data and must pass an empty `aad`.
+    pub fn decrypt_hybrid(
+        case_key: &CaseKey,
+        key_wrap: KeyWrap,
+        pkg: &BinaryPackage,
+        aad: &[u8],
+        max_len: usize,
+    ) -> Result<Vec<u8>> {
+        if pkg.nonce.len() != 12 {
+            return Err(anyhow!("Invalid nonce length"));
+        }
+        let nonce = aes_gcm::Nonce::from_slice(&pkg.nonce);
+
+        let cipher = Self::session_key(
+            case_key,
+            key_wrap,
+            &pkg.encrypted_session_key,
+            pkg.ephemeral_public_key.as_deref(),
+            aad,
+        )?;
+
+        let plaintext = cipher.decrypt(nonce, Payload { msg: &pkg.encrypted_data, aad })
+            .map_err(|_| anyhow!("Package authentication failed: it is not bound to this PDF ID, envelope version and algorithm"))?;
+
+        Self::decompress(plaintext, pkg.compression.as_deref(), max_len)
+    }
+
+    /// Undoes the compression declared by a package, refusing to inflate past `max_len`.
+    pub fn decompress(data: Vec<u8>, compression: Option<&str>, max_len: usize) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match compression {
+            None => return Ok(data),
+            Some(COMPRESSION_ZSTD) => Box::new(zstd::stream::read::Decoder::new(data.as_slice())?),
+            Some(COMPRESSION_DEFLATE) => Box::new(flate2::read::DeflateDecoder::new(data.as_slice())),
+            Some(other) => return Err(anyhow!("Unsupported compression '{}'", other)),
+        };
+
+        // Read one byte past the cap to tell a payload of exactly the cap from a larger one
+        let mut decompressed = Vec::with_capacity(data.len() * 2);
+        decoder.take(max_len as u64 + 1).read_to_end(&mut decompressed)
+            .map_err(|e| anyhow!("Failed to decompress package: {}", e))?;
+
+        if decompressed.len() > max_len {
+            return Err(anyhow!("Package decompresses to more than {} bytes", max_len));
+        }
+
+        Ok(decompressed)
+    }
+
+    pub fn verify_hash(data: &[u8], expected_hash: &[u8]) -> bool {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        let actual_hash = hasher.finalize();
+
+        actual_hash.as_slice() == expected_hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::associated_data;
+    use x25519_dalek::StaticSecret;
+
+    const PDF_ID: &str = "pdf-1";
+    const MAX_LEN: usize = 1024 * 1024;
+
+    /// Encrypts `plaintext` for the case key the way TX does, as an X25519 envelope of `version`.
+    fn package(case_public_b64: &str, version: u8, plaintext: &[u8], compression: Option<&str>) -> BinaryPackage {
+        let recipient: [u8; 32] = b64.decode(case_public_b64).unwrap().try_into().unwrap();
+        let recipient = PublicKey::from(recipient);
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+
+        let aad = associated_data(PDF_ID, version, ALG_X25519_HKDF_SHA256, compression);
+        let session_key = Decrypter::derive_session_key(
+            ephemeral.diffie_hellman(&recipient).as_bytes(),
+            ephemeral_public.as_bytes(),
+            recipient.as_bytes(),
+            &aad,
+        );
+
+        let data = match compression {
+            Some(COMPRESSION_ZSTD) => zstd::encode_all(plaintext, 0).unwrap(),
+            _ => plaintext.to_vec(),
+        };
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let encrypted_data = Aes256Gcm::new_from_slice(&session_key).unwrap()
+            .encrypt(&nonce, Payload { msg: &data, aad: &aad })
+            .unwrap();
+
+        BinaryPackage {
+            version,
+            alg: ALG_X25519_HKDF_SHA256.to_string(),
+            encrypted_session_key: Vec::new(),
+            ephemeral_public_key: Some(ephemeral_public.as_bytes().to_vec()),
+            encrypted_data,
+            nonce: nonce.to_vec(),
+            hash: Sha256::digest(plaintext).to_vec(),
+            sender_id: None,
+            signature: None,
+            compression: compression.map(str::to_string),
+            received_at: None,
+        }
+    }
+
+    fn open(case_key: &CaseKey, pdf_id: &str, pkg: &BinaryPackage) -> Result<Vec<u8>> {
+        let key_wrap = KeyWrap::for_envelope(pkg.version, &pkg.alg)?;
+        let aad = associated_data(pdf_id, pkg.version, &pkg.alg, pkg.compression.as_deref());
+        Decrypter::decrypt_hybrid(case_key, key_wrap, pkg, &aad, MAX_LEN)
+    }
+
+    #[test]
+    fn package_round_trip() {
+        let (case_key, public_key) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let plaintext = b"%PDF-1.7 the same words again and again and again and again".repeat(10);
+
+        for compression in [None, Some(COMPRESSION_ZSTD)] {
+            let pkg = package(&public_key, ENVELOPE_VERSION, &plaintext, compression);
+            let opened = open(&case_key, PDF_ID, &pkg).unwrap();
+
+            assert_eq!(opened, plaintext);
+            assert!(Decrypter::verify_hash(&opened, &pkg.hash));
+        }
+    }
+
+    #[test]
+    fn tampered_package_fails() {
+        let (case_key, public_key) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let mut pkg = package(&public_key, ENVELOPE_VERSION, b"%PDF-1.7", None);
+        pkg.encrypted_data[0] ^= 1;
+
+        assert!(open(&case_key, PDF_ID, &pkg).is_err());
+    }
+
+    #[test]
+    fn package_is_bound_to_its_pdf_id_and_case_key() {
+        let (case_key, public_key) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let (other_key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let pkg = package(&public_key, ENVELOPE_VERSION, b"%PDF-1.7", None);
+
+        assert!(open(&case_key, "pdf-2", &pkg).is_err());
+        assert!(open(&other_key, PDF_ID, &pkg).is_err());
+    }
+
+    #[test]
+    fn compression_is_bound_from_version_4() {
+        let (case_key, public_key) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let plaintext = b"%PDF-1.7 ".repeat(100);
+
+        // Fails authentication before anything is decompressed
+        let mut pkg = package(&public_key, ENVELOPE_VERSION, &plaintext, Some(COMPRESSION_ZSTD));
+        pkg.compression = Some(COMPRESSION_DEFLATE.to_string());
+        assert!(open(&case_key, PDF_ID, &pkg).unwrap_err().to_string().contains("authentication failed"));
+
+        let mut pkg = package(&public_key, ENVELOPE_VERSION, &plaintext, None);
+        pkg.compression = Some(COMPRESSION_ZSTD.to_string());
+        assert!(open(&case_key, PDF_ID, &pkg).unwrap_err().to_string().contains("authentication failed"));
+    }
+
+    #[test]
+    fn version_3_packages_still_open() {
+        let (case_key, public_key) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let plaintext = b"%PDF-1.7 ".repeat(100);
+        let pkg = package(&public_key, SIGNED_ENVELOPE_VERSION, &plaintext, Some(COMPRESSION_ZSTD));
+
+        assert_eq!(open(&case_key, PDF_ID, &pkg).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn decompression_round_trip_up_to_the_limit() {
+        let plaintext = vec![7u8; 4096];
+
+        let zstd = zstd::encode_all(plaintext.as_slice(), 0).unwrap();
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &plaintext).unwrap();
+        let deflate = encoder.finish().unwrap();
+
+        for (compressed, compression) in [(zstd, COMPRESSION_ZSTD), (deflate, COMPRESSION_DEFLATE)] {
+            assert_eq!(Decrypter::decompress(compressed.clone(), Some(compression), 4096).unwrap(), plaintext);
+            assert!(Decrypter::decompress(compressed, Some(compression), 4095).is_err());
+        }
+
+        assert_eq!(Decrypter::decompress(plaintext.clone(), None, 0).unwrap(), plaintext);
+        assert!(Decrypter::decompress(plaintext, Some("brotli"), 4096).is_err());
+    }
+
+    #[test]
+    fn envelope_versions() {
+        assert_eq!(KeyWrap::for_envelope(ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256).unwrap(), KeyWrap::X25519HkdfSha256);
+        assert_eq!(KeyWrap::for_envelope(SIGNED_ENVELOPE_VERSION, ALG_RSA_OAEP_256).unwrap(), KeyWrap::RsaOaepSha256);
+        assert_eq!(KeyWrap::for_envelope(OAEP_ENVELOPE_VERSION, ALG_RSA_OAEP_256).unwrap(), KeyWrap::RsaOaepSha256);
+        assert_eq!(KeyWrap::for_envelope(LEGACY_ENVELOPE_VERSION, ALG_RSA1_5).unwrap(), KeyWrap::RsaPkcs1v15);
+
+        assert!(KeyWrap::for_envelope(OAEP_ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256).is_err());
+        assert!(KeyWrap::for_envelope(ENVELOPE_VERSION, ALG_RSA1_5).is_err());
+        assert!(KeyWrap::for_envelope(ENVELOPE_VERSION + 1, ALG_RSA_OAEP_256).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use crate::prelude::*;
+use crate::settings::KekSettings;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use std::io::Write;
+use std::path::PathBuf;
+
+const WRAP_PREFIX: &str = "kek2:";
+/// Values wrapped before they were bound to their record, their AAD names only the KEK.
+const LEGACY_WRAP_PREFIX: &str = "kek:";
+
+struct Kek {
+    id: String,
+    cipher: Aes256Gcm,
+    /// Derived from the KEK, MACs audit rows. Kept apart from the KEK so a MAC says nothing about it.
+    audit_key: [u8; 32],
+}
+
+/// Key-encryption keys protecting the private keys stored in Postgres.
+/// New keys are wrapped with the current KEK, previous KEKs are kept to unwrap rows not yet rotated.
+pub struct Keyring {
+    current: Kek,
+    previous: Vec<Kek>,
+    /// Rejects values that aren't wrapped and bound to their record, see `KekSettings::require_bound`.
+    require_bound: bool,
+}
+
+impl Keyring {
+    /// Loads the keyring from the environment variable named in the settings, falling back to the key file.
+    /// A key file with a fresh KEK is created if neither exists.
+    pub fn load(settings: &KekSettings) -> Result<Self> {
+        let mut keyring = Self::read(settings)?;
+        keyring.require_bound = settings.require_bound;
+
+        Ok(keyring)
+    }
+
+    fn read(settings: &KekSettings) -> Result<Self> {
+        if let Ok(keyring) = std::env::var(&settings.env) {
+            debug!("Loaded KEK keyring from ${}", settings.env);
+            return Self::parse(&keyring);
+        }
+
+        let path = PathBuf::from(&settings.path);
+        if path.exists() {
+            debug!("Loaded KEK keyring from '{}'", path.display());
+            return Self::parse(&std::fs::read_to_string(&path)?);
+        }
+
+        let id = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+        let key = Aes256Gcm::generate_key(&mut OsRng);
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Readable by RX's user only
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&path)?.write_all(format!("{}={}\n", id, b64.encode(key)).as_bytes())?;
+
+        warn!("Generated a new KEK '{}' at '{}'", id, path.display());
+        Self::parse(&std::fs::read_to_string(&path)?)
+    }
+
+    /// Parses `id=base64key` entries separated by newlines or commas. The first entry is the current KEK.
+    pub fn parse(keyring: &str) -> Result<Self> {
+        let mut keks = keyring
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+            .map(|entry| {
+                let (id, key_b64) = entry.split_once('=')
+                    .ok_or_else(|| anyhow!("KEK entries must look like `id=base64key`"))?;
+
+                let id = id.trim();
+                if id.is_empty() || id.contains(':') {
+                    return Err(anyhow!("Invalid KEK id '{}'", id));
+                }
+
+                let key = b64.decode(key_b64.trim())
+                    .map_err(|e| anyhow!("KEK '{}' is not valid base64: {}", id, e))?;
+                let cipher = Aes256Gcm::new_from_slice(&key)
+                    .map_err(|_| anyhow!("KEK '{}' must be 32 bytes", id))?;
+
+                let mut audit_key = [0u8; 32];
+                Hkdf::<Sha256>::new(None, &key)
+                    .expand(b"jjk-audit", &mut audit_key)
+                    .map_err(|e| anyhow!("HKDF error: {}", e))?;
+
+                Ok(Kek { id: id.to_string(), cipher, audit_key })
+            })
+            .collect::<Result<Vec<Kek>>>()?
+            .into_iter();
+
+        let current = keks.next().ok_or_else(|| anyhow!("KEK keyring is empty"))?;
+
+        Ok(Self { current, previous: keks.collect(), require_bound: false })
+    }
+
+    pub fn current_id(&self) -> &str {
+        &self.current.id
+    }
+
+    /// Encrypts a stored key with the current KEK: `kek2:{id}:{base64(nonce || ciphertext)}`.
+    /// The value is bound to the `column` and `record` it is stored in, so it can't be moved to another row.
+    pub fn wrap(&self, private_key: &str, column: &str, record: &str) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = Self::associated_data(&self.current.id, column, record);
+
+        let ciphertext = self.current.cipher
+            .encrypt(&nonce, Payload { msg: private_key.as_bytes(), aad: aad.as_bytes() })
+            .map_err(|e| anyhow!("AES error: {}", e))?;
+
+        let mut wrapped = nonce.to_vec();
+        wrapped.extend_from_slice(&ciphertext);
+
+        Ok(format!("{}{}:{}", WRAP_PREFIX, self.current.id, b64.encode(wrapped)))
+    }
+
+    /// Decrypts a value written by `wrap` for the same `column` and `record`, with whichever KEK it names.
+    /// Unless `require_bound` is set, values without a prefix predate envelope encryption and are returned as-is,
+    /// and values with the `kek:` prefix predate the record binding.
+    pub fn unwrap(&self, stored: &str, column: &str, record: &str) -> Result<String> {
+        let (id, wrapped_b64, aad) = match (Self::split(stored), stored.strip_prefix(LEGACY_WRAP_PREFIX)) {
+            (Some((id, wrapped_b64)), _) => (id, wrapped_b64, Self::associated_data(id, column, record)),
+            (None, _) if self.require_bound => {
+                return Err(anyhow!("Value of {} for {} isn't wrapped with a KEK bound to it", column, record));
+            }
+            (None, Some(legacy)) => {
+                let (id, wrapped_b64) = legacy.split_once(':')
+                    .ok_or_else(|| anyhow!("Malformed wrapped value of {} for {}", column, record))?;
+                (id, wrapped_b64, format!("jjk-kek:{}", id))
+            }
+            (None, None) => return Ok(stored.to_string()),
+        };
+
+        let kek = self.kek(id).ok_or_else(|| anyhow!("Private key is wrapped with unknown KEK '{}'", id))?;
+
+        let wrapped = b64.decode(wrapped_b64)
+            .map_err(|e| anyhow!("Failed to decode wrapped private key: {}", e))?;
+
+        if wrapped.len() < 12 {
+            return Err(anyhow!("Wrapped private key is truncated"));
+        }
+        let (nonce_bytes, ciphertext) = wrapped.split_at(12);
+
+        let private_key = kek.cipher
+            .decrypt(aes_gcm::Nonce::from_slice(nonce_bytes), Payload { msg: ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| anyhow!("KEK '{}' failed to unwrap private key", id))?;
+
+        Ok(String::from_utf8(private_key)?)
+    }
+
+    /// Hex HMAC-SHA256 of an audit row, with the audit key of the KEK it names. Rows keep the KEK ID,
+    /// so they stay verifiable after a rotation as long as the previous KEK remains in the keyring.
+    pub fn audit_mac(&self, kek_id: &str, content: &[u8]) -> Result<String> {
+        let kek = self.kek(kek_id).ok_or_else(|| anyhow!("Audit row is keyed with unknown KEK '{}'", kek_id))?;
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&kek.audit_key)
+            .map_err(|e| anyhow!("HMAC error: {}", e))?;
+        mac.update(content);
+
+        Ok(format!("{:x}", mac.finalize().into_bytes()))
+    }
+
+    /// Whether a stored value is not yet wrapped with the current KEK and bound to its record.
+    pub fn needs_rewrap(&self, stored: &str) -> bool {
+        Self::split(stored).is_none_or(|(id, _)| id != self.current.id)
+    }
+
+    fn kek(&self, id: &str) -> Option<&Kek> {
+        std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|kek| kek.id == id)
+    }
+
+    fn split(stored: &str) -> Option<(&str, &str)> {
+        stored.strip_prefix(WRAP_PREFIX)?.split_once(':')
+    }
+
+    fn associated_data(id: &str, column: &str, record: &str) -> String {
+        format!("jjk-kek:{}:{}:{}", id, column, record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYRING: &str = "k2=AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=\nk1=AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    /// A value as wrapped before the record binding, AAD naming only the KEK.
+    fn legacy_wrap(keyring: &Keyring, value: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = format!("jjk-kek:{}", keyring.current.id);
+        let ciphertext = keyring.current.cipher
+            .encrypt(&nonce, Payload { msg: value.as_bytes(), aad: aad.as_bytes() })
+            .unwrap();
+
+        let mut wrapped = nonce.to_vec();
+        wrapped.extend_from_slice(&ciphertext);
+        format!("{}{}:{}", LEGACY_WRAP_PREFIX, keyring.current.id, b64.encode(wrapped))
+    }
+
+    #[test]
+    fn wrap_round_trip() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        let wrapped = keyring.wrap("secret", "pdf.private_key", "pdf-1").unwrap();
+
+        assert!(wrapped.starts_with("kek2:k2:"));
+        assert!(!keyring.needs_rewrap(&wrapped));
+        assert_eq!(keyring.unwrap(&wrapped, "pdf.private_key", "pdf-1").unwrap(), "secret");
+    }
+
+    #[test]
+    fn wrapped_value_is_bound_to_its_record_and_column() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        let wrapped = keyring.wrap("secret", "pdf.private_key", "pdf-1").unwrap();
+
+        assert!(keyring.unwrap(&wrapped, "pdf.private_key", "pdf-2").is_err());
+        assert!(keyring.unwrap(&wrapped, "pdf.file_key", "pdf-1").is_err());
+    }
+
+    #[test]
+    fn tampered_value_fails() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        let wrapped = keyring.wrap("secret", "pdf.private_key", "pdf-1").unwrap();
+
+        let (prefix, body) = wrapped.rsplit_once(':').unwrap();
+        let mut bytes = b64.decode(body).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        let tampered = format!("{}:{}", prefix, b64.encode(bytes));
+
+        assert!(keyring.unwrap(&tampered, "pdf.private_key", "pdf-1").is_err());
+    }
+
+    #[test]
+    fn previous_kek_unwraps_after_rotation() {
+        let old = Keyring::parse("k1=AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=").unwrap();
+        let wrapped = old.wrap("secret", "pdf.file_key", "pdf-1").unwrap();
+
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        assert!(keyring.needs_rewrap(&wrapped));
+        assert_eq!(keyring.unwrap(&wrapped, "pdf.file_key", "pdf-1").unwrap(), "secret");
+
+        let unknown = Keyring::parse("k3=AwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwM=").unwrap();
+        assert!(unknown.unwrap(&wrapped, "pdf.file_key", "pdf-1").is_err());
+    }
+
+    #[test]
+    fn legacy_and_plaintext_values_until_bound_is_required() {
+        let mut keyring = Keyring::parse(KEYRING).unwrap();
+        let legacy = legacy_wrap(&keyring, "secret");
+
+        assert!(keyring.needs_rewrap(&legacy));
+        assert!(keyring.needs_rewrap("plaintext"));
+        assert_eq!(keyring.unwrap(&legacy, "pdf.private_key", "pdf-1").unwrap(), "secret");
+        assert_eq!(keyring.unwrap("plaintext", "pdf.private_key", "pdf-1").unwrap(), "plaintext");
+
+        keyring.require_bound = true;
+        assert!(keyring.unwrap(&legacy, "pdf.private_key", "pdf-1").is_err());
+        assert!(keyring.unwrap("plaintext", "pdf.private_key", "pdf-1").is_err());
+    }
+
+    #[test]
+    fn audit_mac_depends_on_the_kek() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+
+        let mac = keyring.audit_mac("k2", b"row").unwrap();
+        assert_eq!(mac, keyring.audit_mac("k2", b"row").unwrap());
+        assert_ne!(mac, keyring.audit_mac("k1", b"row").unwrap());
+        assert_ne!(mac, keyring.audit_mac("k2", b"other row").unwrap());
+        assert!(keyring.audit_mac("k3", b"row").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_keyrings() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("k1").is_err());
+        assert!(Keyring::parse("k:1=AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=").is_err());
+        assert!(Keyring::parse("k1=AQEB").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::prelude::*;
+use crate::settings::KeyPoolSettings;
+use crate::storage::{Database, KeyIssue};
+use crate::domain::ALG_RSA_OAEP_256;
+use super::CaseKey;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Pre-generated RSA case keys, persisted in Postgres and topped up by a background task,
+/// so `get_public_key` doesn't pay for RSA key generation on the request path.
+#[derive(Clone)]
+pub struct KeyPool {
+    db: Database,
+    size: u32,
+    refill_interval: Duration,
+    wake: Arc<Notify>,
+}
+
+impl KeyPool {
+    pub fn new(db: Database, settings: &KeyPoolSettings) -> Self {
+        Self {
+            db,
+            size: settings.size,
+            refill_interval: Duration::from_secs(settings.refill_interval_secs.max(1)),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Starts the refill task. Does nothing when the pool is disabled.
+    pub fn spawn_refill(&self) {
+        if self.size == 0 {
+            return;
+        }
+
+        let pool = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = pool.refill().await {
+                    error!("Key pool refill failed: {}", e);
+                }
+
+                // Top up again once a key is taken, or on the interval if another instance took it
+                let _ = tokio::time::timeout(pool.refill_interval, pool.wake.notified()).await;
+            }
+        });
+    }
+
+    async fn refill(&self) -> Result<()> {
+        let depth = self.db.key_pool_depth().await?;
+        let missing = (self.size as i64 - depth).max(0);
+
+        for _ in 0..missing {
+            let (private_key, public_key_pem) = generate(ALG_RSA_OAEP_256).await?;
+            self.db.insert_pooled_key(&private_key, &public_key_pem).await?;
+        }
+
+        if missing > 0 {
+            debug!("Key pool refilled with {} keys", missing);
+        }
+
+        Ok(())
+    }
+
+    /// Starts issuing a case key for `alg` and hands out the key, taken from the pool as part of the issue when
+    /// possible. When the pool is empty or doesn't cover `alg`, the key is generated off the async runtime before
+    /// the issue begins, so no database connection is held meanwhile.
+    pub async fn begin_issue(&self, alg: &str) -> Result<(KeyIssue, CaseKey, String)> {
+        if alg == ALG_RSA_OAEP_256 && self.size > 0 {
+            let mut issue = self.db.begin_issue().await?;
+            let pooled = issue.take_pooled_key().await?;
+            self.wake.notify_one();
+
+            match pooled {
+                Some((private_key, public_key_pem)) => return Ok((issue, private_key, public_key_pem)),
+                // Dropping the issue gives its connection back before the key is generated
+                None => info!("Key pool is empty, generating key on demand"),
+            }
+        }
+
+        let (private_key, public_key_pem) = generate(alg).await?;
+
+        Ok((self.db.begin_issue().await?, private_key, public_key_pem))
+    }
+
+    pub async fn depth(&self) -> Result<i64> {
+        self.db.key_pool_depth().await
+    }
+}
+
+async fn generate(alg: &str) -> Result<(CaseKey, String)> {
+    let alg = alg.to_string();
+    tokio::task::spawn_blocking(move || CaseKey::generate(&alg)).await?
+}
@@ -1,2 +1,12 @@
-pub mod decrypter;
-pub use decrypter::Decrypter;
+pub mod case_key;
+pub mod decrypter;
+pub mod kek;
+pub mod key_pool;
+pub mod stream;
+pub mod verifier;
+pub use case_key::CaseKey;
+pub use decrypter::{Decrypter, KeyWrap};
+pub use kek::Keyring;
+pub use key_pool::KeyPool;
+pub use stream::{StreamOpener, read_header};
+pub use verifier::Verifier;
@@ -0,0 +1,274 @@
+use crate::prelude::*;
+use crate::domain::{StreamHeader, associated_data};
+use super::{CaseKey, Decrypter, KeyWrap};
+use aes_gcm::aead::stream::DecryptorBE32;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Largest header accepted, it only carries keys, hashes and a signature.
+pub const MAX_HEADER_LEN: usize = 64 * 1024;
+/// Largest ciphertext frame accepted: a 1 MiB chunk plus its tag.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024 + 16;
+
+const FLAG_NEXT: u8 = 0;
+const FLAG_LAST: u8 = 1;
+
+/// Reads the length-prefixed JSON header that opens a stream, returning it along with its raw JSON bytes.
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(StreamHeader, Vec<u8>)> {
+    let header_len = reader.read_u32().await? as usize;
+    if header_len > MAX_HEADER_LEN {
+        return Err(anyhow!("Stream header of {} bytes exceeds the {} byte limit", header_len, MAX_HEADER_LEN));
+    }
+
+    let mut raw_header = vec![0u8; header_len];
+    reader.read_exact(&mut raw_header).await?;
+
+    let header = serde_json::from_slice::<StreamHeader>(&raw_header)
+        .map_err(|e| anyhow!("Invalid stream header: {}", e))?;
+
+    Ok((header, raw_header))
+}
+
+/// Decrypts a stream one chunk at a time, so the document never has to be held in memory.
+pub struct StreamOpener<R> {
+    reader: R,
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    aad: Vec<u8>,
+    hasher: Sha256,
+    expected_hash: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> StreamOpener<R> {
+    pub fn new(case_key: &CaseKey, key_wrap: KeyWrap, header: &StreamHeader, reader: R) -> Result<Self> {
+        // Streams are never compressed
+        let aad = associated_data(&header.pdf_id, header.version, &header.alg, None);
+
+        let encrypted_session_key = b64.decode(&header.encrypted_session_key_b64)
+            .map_err(|e| anyhow!("Failed to decode session key: {}", e))?;
+        let ephemeral_public_key = header.ephemeral_public_key_b64.as_deref()
+            .map(|epk| b64.decode(epk).map_err(|e| anyhow!("Failed to decode ephemeral public key: {}", e)))
+            .transpose()?;
+
+        let cipher = Decrypter::session_key(
+            case_key,
+            key_wrap,
+            &encrypted_session_key,
+            ephemeral_public_key.as_deref(),
+            &aad,
+        )?;
+
+        let nonce_prefix = b64.decode(&header.nonce_prefix_b64)
+            .map_err(|e| anyhow!("Failed to decode nonce prefix: {}", e))?;
+        if nonce_prefix.len() != 7 {
+            return Err(anyhow!("Invalid nonce prefix length"));
+        }
+
+        let expected_hash = b64.decode(&header.hash_b64)
+            .map_err(|e| anyhow!("Failed to decode hash: {}", e))?;
+
+        Ok(Self {
+            reader,
+            decryptor: Some(DecryptorBE32::from_aead(cipher, nonce_prefix.as_slice().into())),
+            aad,
+            hasher: Sha256::new(),
+            expected_hash,
+        })
+    }
+
+    /// Returns the next plaintext chunk, or `None` after the final one.
+    /// The hash is verified when the final chunk is read, so a tampered stream fails there at the latest.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.decryptor.is_none() {
+            return Ok(None);
+        }
+
+        let flag = self.reader.read_u8().await
+            .map_err(|_| anyhow!("Stream ended before its final chunk"))?;
+        let frame_len = self.reader.read_u32().await? as usize;
+        if frame_len > MAX_FRAME_LEN {
+            return Err(anyhow!("Chunk of {} bytes exceeds the {} byte limit", frame_len, MAX_FRAME_LEN));
+        }
+
+        let mut ciphertext = vec![0u8; frame_len];
+        self.reader.read_exact(&mut ciphertext).await?;
+        let payload = Payload { msg: &ciphertext, aad: &self.aad };
+
+        let chunk = match flag {
+            FLAG_NEXT => self.decryptor.as_mut()
+                .expect("checked above")
+                .decrypt_next(payload),
+            FLAG_LAST => self.decryptor.take()
+                .expect("checked above")
+                .decrypt_last(payload),
+            other => return Err(anyhow!("Invalid chunk flag {}", other)),
+        }
+        .map_err(|_| anyhow!("Package authentication failed: it is not bound to this PDF ID, envelope version and algorithm"))?;
+
+        self.hasher.update(&chunk);
+
+        if self.decryptor.is_none() {
+            if self.reader.read_u8().await.is_ok() {
+                return Err(anyhow!("Unexpected data after the final chunk"));
+            }
+
+            let actual_hash = std::mem::take(&mut self.hasher).finalize();
+            if actual_hash.as_slice() != self.expected_hash.as_slice() {
+                return Err(anyhow!("Integrity check failed (Hash mismatch)"));
+            }
+        }
+
+        Ok(Some(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{ALG_X25519_HKDF_SHA256, ENVELOPE_VERSION};
+    use aes_gcm::aead::stream::EncryptorBE32;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    fn header(chunks: &[&[u8]]) -> StreamHeader {
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let mut hasher = Sha256::new();
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+
+        StreamHeader {
+            pdf_id: "pdf-1".to_string(),
+            version: ENVELOPE_VERSION,
+            alg: ALG_X25519_HKDF_SHA256.to_string(),
+            encrypted_session_key_b64: String::new(),
+            ephemeral_public_key_b64: Some(b64.encode(PublicKey::from(&ephemeral).as_bytes())),
+            nonce_prefix_b64: b64.encode([3u8; 7]),
+            hash_b64: b64.encode(hasher.finalize()),
+            sender_id: "jjk-tx-test".to_string(),
+            signature_b64: String::new(),
+            received_at: None,
+        }
+    }
+
+    /// Frames the chunks the way TX does, each as `flag | len | ciphertext`.
+    fn frames(case_key: &CaseKey, header: &StreamHeader, chunks: &[&[u8]]) -> Vec<Vec<u8>> {
+        let aad = associated_data(&header.pdf_id, header.version, &header.alg, None);
+        let ephemeral_public_key = b64.decode(header.ephemeral_public_key_b64.as_deref().unwrap()).unwrap();
+        let cipher = Decrypter::session_key(case_key, KeyWrap::X25519HkdfSha256, &[], Some(&ephemeral_public_key), &aad).unwrap();
+        let mut encryptor = EncryptorBE32::from_aead(cipher, [3u8; 7].as_slice().into());
+
+        let (last, rest) = chunks.split_last().unwrap();
+        let mut ciphertexts: Vec<(u8, Vec<u8>)> = rest.iter()
+            .map(|chunk| (FLAG_NEXT, encryptor.encrypt_next(Payload { msg: chunk, aad: &aad }).unwrap()))
+            .collect();
+        ciphertexts.push((FLAG_LAST, encryptor.encrypt_last(Payload { msg: last, aad: &aad }).unwrap()));
+
+        ciphertexts.into_iter()
+            .map(|(flag, ciphertext)| {
+                let mut frame = vec![flag];
+                frame.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+                frame.extend_from_slice(&ciphertext);
+                frame
+            })
+            .collect()
+    }
+
+    fn body(header: &StreamHeader, frames: &[Vec<u8>]) -> Vec<u8> {
+        let raw_header = serde_json::to_vec(header).unwrap();
+        let mut body = (raw_header.len() as u32).to_be_bytes().to_vec();
+        body.extend_from_slice(&raw_header);
+        body.extend(frames.concat());
+        body
+    }
+
+    /// Reads the stream back, returning its chunks or the first error.
+    async fn open(case_key: &CaseKey, body: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut reader = body;
+        let (header, _) = read_header(&mut reader).await?;
+        let mut opener = StreamOpener::new(case_key, KeyWrap::X25519HkdfSha256, &header, reader)?;
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = opener.next_chunk().await? {
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+
+    const CHUNKS: [&[u8]; 3] = [b"{\"title\":null}", b"%PDF-1.7 first", b"second %%EOF"];
+
+    #[tokio::test]
+    async fn stream_round_trip() {
+        let (case_key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let header = header(&CHUNKS);
+        let frames = frames(&case_key, &header, &CHUNKS);
+
+        assert_eq!(open(&case_key, &body(&header, &frames)).await.unwrap(), CHUNKS);
+    }
+
+    #[tokio::test]
+    async fn truncated_stream_fails() {
+        let (case_key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let header = header(&CHUNKS);
+        let frames = frames(&case_key, &header, &CHUNKS);
+
+        let err = open(&case_key, &body(&header, &frames[..2])).await.unwrap_err();
+        assert!(err.to_string().contains("ended before its final chunk"));
+    }
+
+    #[tokio::test]
+    async fn reordered_or_tampered_chunks_fail() {
+        let (case_key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let header = header(&CHUNKS);
+        let mut frames = frames(&case_key, &header, &CHUNKS);
+
+        frames.swap(0, 1);
+        assert!(open(&case_key, &body(&header, &frames)).await.is_err());
+        frames.swap(0, 1);
+
+        // A final chunk passed off as an intermediate one
+        let mut last_as_next = frames.clone();
+        last_as_next[2][0] = FLAG_NEXT;
+        assert!(open(&case_key, &body(&header, &last_as_next)).await.is_err());
+
+        *frames[1].last_mut().unwrap() ^= 1;
+        assert!(open(&case_key, &body(&header, &frames)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn trailing_data_and_hash_mismatch_fail() {
+        let (case_key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let header = header(&CHUNKS);
+        let frames = frames(&case_key, &header, &CHUNKS);
+
+        let mut trailing = body(&header, &frames);
+        trailing.push(0);
+        assert!(open(&case_key, &trailing).await.unwrap_err().to_string().contains("after the final chunk"));
+
+        let mut other_hash = header.clone();
+        other_hash.hash_b64 = b64.encode(Sha256::digest(b"other"));
+        assert!(open(&case_key, &body(&other_hash, &frames)).await.unwrap_err().to_string().contains("Hash mismatch"));
+    }
+
+    #[tokio::test]
+    async fn stream_is_bound_to_its_pdf_id() {
+        let (case_key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let header = header(&CHUNKS);
+        let frames = frames(&case_key, &header, &CHUNKS);
+
+        let mut other_id = header.clone();
+        other_id.pdf_id = "pdf-2".to_string();
+        assert!(open(&case_key, &body(&other_id, &frames)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_refused() {
+        let mut reader: &[u8] = &(MAX_HEADER_LEN as u32 + 1).to_be_bytes();
+        assert!(read_header(&mut reader).await.is_err());
+
+        let (case_key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let header = header(&CHUNKS);
+        let mut frame = vec![FLAG_LAST];
+        frame.extend_from_slice(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+
+        let err = open(&case_key, &body(&header, &[frame])).await.unwrap_err();
+        assert!(err.to_string().contains("exceeds"));
+    }
+}
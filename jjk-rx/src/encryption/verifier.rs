@@ -0,0 +1,78 @@
+use crate::prelude::*;
+use crate::settings::TrustedSender;
+use ed25519_dalek::{Signature, VerifyingKey};
+
+pub struct Verifier;
+
+impl Verifier {
+    /// Checks a package signature over `signed_message` against the trusted sender it claims to come from.
+    /// Returns the ID of the authenticated sender.
+    pub fn verify_sender(
+        trusted_senders: &[TrustedSender],
+        sender_id: Option<&str>,
+        signature: Option<&[u8]>,
+        signed_message: &[u8],
+    ) -> Result<String> {
+        let (sender_id, signature) = match (sender_id, signature) {
+            (Some(sender_id), Some(signature)) => (sender_id, signature),
+            _ => return Err(anyhow!("Package is not signed")),
+        };
+
+        let sender = trusted_senders
+            .iter()
+            .find(|sender| sender.id == sender_id)
+            .ok_or_else(|| anyhow!("Sender '{}' is not trusted", sender_id))?;
+
+        let public_key_bytes: [u8; 32] = b64.decode(&sender.public_key)
+            .map_err(|e| anyhow!("Failed to decode public key of '{}': {}", sender.id, e))?
+            .try_into()
+            .map_err(|_| anyhow!("Public key of '{}' is not an Ed25519 key", sender.id))?;
+        let public_key = VerifyingKey::from_bytes(&public_key_bytes)?;
+
+        let signature = Signature::from_slice(signature)?;
+
+        public_key.verify_strict(signed_message, &signature)
+            .map_err(|_| anyhow!("Signature of '{}' does not match the package", sender.id))?;
+
+        Ok(sender.id.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn trusted(signing_key: &SigningKey) -> Vec<TrustedSender> {
+        vec![TrustedSender {
+            id: "jjk-tx".to_string(),
+            public_key: b64.encode(signing_key.verifying_key().to_bytes()),
+        }]
+    }
+
+    #[test]
+    fn trusted_signature_verifies() {
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let signature = signing_key.sign(b"message").to_bytes();
+
+        let sender = Verifier::verify_sender(&trusted(&signing_key), Some("jjk-tx"), Some(&signature), b"message").unwrap();
+        assert_eq!(sender, "jjk-tx");
+    }
+
+    #[test]
+    fn untrusted_unsigned_or_mismatched_signatures_fail() {
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let senders = trusted(&signing_key);
+        let signature = signing_key.sign(b"message").to_bytes();
+
+        assert!(Verifier::verify_sender(&senders, Some("jjk-tx"), Some(&signature), b"other message").is_err());
+        assert!(Verifier::verify_sender(&senders, Some("someone"), Some(&signature), b"message").is_err());
+        assert!(Verifier::verify_sender(&senders, Some("jjk-tx"), None, b"message").is_err());
+        assert!(Verifier::verify_sender(&senders, None, Some(&signature), b"message").is_err());
+        assert!(Verifier::verify_sender(&senders, Some("jjk-tx"), Some(&signature[..32]), b"message").is_err());
+
+        // Signed by another key under a trusted ID
+        let impostor = SigningKey::from_bytes(&[8u8; 32]).sign(b"message").to_bytes();
+        assert!(Verifier::verify_sender(&senders, Some("jjk-tx"), Some(&impostor), b"message").is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod sealer;
+pub mod vault;
+
+pub use sealer::KeySealer;
+pub use vault::ShareVault;
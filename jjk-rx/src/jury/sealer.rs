@@ -0,0 +1,144 @@
+use crate::prelude::*;
+use crate::encryption::CaseKey;
+use rsa::pkcs8::DecodePublicKey;
+use sharks::{Sharks, Share};
+
+/// Seals per-case private keys under a random case key that only a quorum of jurors can rebuild.
+pub struct KeySealer;
+
+impl KeySealer {
+    /// Encrypts the private key under a fresh case key and splits that case key into one Shamir share per juror.
+    /// Returns the sealed key (base64 `nonce || ciphertext`) and the raw shares.
+    pub fn seal(case_private_key: &CaseKey, threshold: u8, jurors: usize) -> Result<(String, Vec<Vec<u8>>)> {
+        if threshold == 0 || jurors < threshold as usize || jurors > u8::MAX as usize {
+            return Err(anyhow!("Cannot split a key among {} jurors with threshold {}", jurors, threshold));
+        }
+
+        let mut rng = OsRng;
+
+        let case_key = Aes256Gcm::generate_key(&mut rng);
+        let cipher = Aes256Gcm::new(&case_key);
+        let nonce = Aes256Gcm::generate_nonce(&mut rng);
+
+        let private_key_der = case_private_key.to_der()?;
+        let ciphertext = cipher.encrypt(&nonce, private_key_der.as_slice())
+            .map_err(|e| anyhow!("AES error: {}", e))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+
+        let shares = Sharks(threshold)
+            .dealer_rng(case_key.as_slice(), &mut rng)
+            .take(jurors)
+            .map(|share| Vec::from(&share))
+            .collect();
+
+        Ok((b64.encode(sealed), shares))
+    }
+
+    /// Rebuilds the case key from the submitted shares and decrypts the sealed private key.
+    pub fn unseal(sealed_b64: &str, alg: &str, threshold: u8, shares: &[Vec<u8>]) -> Result<CaseKey> {
+        let shares = shares
+            .iter()
+            .map(|bytes| Share::try_from(bytes.as_slice()).map_err(|e| anyhow!("Invalid share: {}", e)))
+            .collect::<Result<Vec<Share>>>()?;
+
+        let case_key = Sharks(threshold).recover(&shares)
+            .map_err(|e| anyhow!("Failed to recover case key: {}", e))?;
+
+        let sealed = b64.decode(sealed_b64)
+            .map_err(|e| anyhow!("Failed to decode sealed key: {}", e))?;
+
+        if sealed.len() < 12 {
+            return Err(anyhow!("Sealed key is truncated"));
+        }
+        let (nonce_bytes, ciphertext) = sealed.split_at(12);
+
+        let cipher = Aes256Gcm::new_from_slice(&case_key)
+            .map_err(|e| anyhow!("Invalid case key length: {}", e))?;
+        let private_key_der = cipher.decrypt(aes_gcm::Nonce::from_slice(nonce_bytes), ciphertext)
+            .map_err(|_| anyhow!("Recovered case key does not open the sealed private key"))?;
+
+        CaseKey::from_der(alg, &private_key_der)
+    }
+
+    /// Encrypts a share for a juror with their registered RSA public key (OAEP, SHA-256).
+    pub fn encrypt_share(juror_public_key_pem: &str, share: &[u8]) -> Result<String> {
+        let juror_key = RsaPublicKey::from_public_key_pem(juror_public_key_pem)?;
+
+        let encrypted = juror_key.encrypt(&mut OsRng, Oaep::new::<Sha256>(), share)
+            .map_err(|e| anyhow!("RSA error: {}", e))?;
+
+        Ok(b64.encode(encrypted))
+    }
+
+    /// Fingerprint stored alongside each issued share, used to check juror submissions.
+    pub fn share_hash(share: &[u8]) -> String {
+        b64.encode(Sha256::digest(share))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ALG_X25519_HKDF_SHA256;
+
+    fn case_key() -> CaseKey {
+        CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap().0
+    }
+
+    #[test]
+    fn any_quorum_unseals() {
+        let key = case_key();
+        let (sealed, shares) = KeySealer::seal(&key, 2, 3).unwrap();
+        assert_eq!(shares.len(), 3);
+
+        for quorum in [[0, 1], [1, 2], [2, 0]] {
+            let submitted: Vec<Vec<u8>> = quorum.iter().map(|i| shares[*i].clone()).collect();
+            let unsealed = KeySealer::unseal(&sealed, ALG_X25519_HKDF_SHA256, 2, &submitted).unwrap();
+            assert_eq!(unsealed.to_der().unwrap(), key.to_der().unwrap());
+        }
+    }
+
+    #[test]
+    fn fewer_shares_than_the_threshold_fail() {
+        let (sealed, shares) = KeySealer::seal(&case_key(), 3, 5).unwrap();
+
+        assert!(KeySealer::unseal(&sealed, ALG_X25519_HKDF_SHA256, 3, &shares[..2]).is_err());
+    }
+
+    #[test]
+    fn tampered_share_or_sealed_key_fails() {
+        let (sealed, shares) = KeySealer::seal(&case_key(), 2, 2).unwrap();
+
+        let mut tampered = shares.clone();
+        *tampered[1].last_mut().unwrap() ^= 1;
+        assert!(KeySealer::unseal(&sealed, ALG_X25519_HKDF_SHA256, 2, &tampered).is_err());
+
+        // Shares of another case rebuild another case key
+        let (_, other_shares) = KeySealer::seal(&case_key(), 2, 2).unwrap();
+        assert!(KeySealer::unseal(&sealed, ALG_X25519_HKDF_SHA256, 2, &other_shares).is_err());
+
+        let mut sealed_bytes = b64.decode(&sealed).unwrap();
+        *sealed_bytes.last_mut().unwrap() ^= 1;
+        assert!(KeySealer::unseal(&b64.encode(sealed_bytes), ALG_X25519_HKDF_SHA256, 2, &shares).is_err());
+        assert!(KeySealer::unseal(&b64.encode([0u8; 8]), ALG_X25519_HKDF_SHA256, 2, &shares).is_err());
+    }
+
+    #[test]
+    fn impossible_splits_are_refused() {
+        let key = case_key();
+
+        assert!(KeySealer::seal(&key, 0, 3).is_err());
+        assert!(KeySealer::seal(&key, 4, 3).is_err());
+        assert!(KeySealer::seal(&key, 2, 256).is_err());
+    }
+
+    #[test]
+    fn share_hash_tells_shares_apart() {
+        let (_, shares) = KeySealer::seal(&case_key(), 2, 2).unwrap();
+
+        assert_eq!(KeySealer::share_hash(&shares[0]), KeySealer::share_hash(&shares[0]));
+        assert_ne!(KeySealer::share_hash(&shares[0]), KeySealer::share_hash(&shares[1]));
+    }
+}
@@ -0,0 +1,32 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Juror shares submitted so far, keyed by PDF ID and juror ID.
+/// Kept in memory only, so the database never holds enough material to rebuild a sealed key.
+#[derive(Default)]
+pub struct ShareVault {
+    shares: Mutex<HashMap<String, HashMap<i32, Vec<u8>>>>,
+}
+
+impl ShareVault {
+    /// Stores a juror's share for a case, replacing any earlier submission from the same juror.
+    /// Returns how many distinct jurors have submitted for the case.
+    pub fn submit(&self, pdf_id: &str, juror_id: i32, share: Vec<u8>) -> usize {
+        let mut shares = self.shares.lock().unwrap();
+        let case_shares = shares.entry(pdf_id.to_string()).or_default();
+        case_shares.insert(juror_id, share);
+        case_shares.len()
+    }
+
+    pub fn shares(&self, pdf_id: &str) -> Vec<Vec<u8>> {
+        self.shares.lock().unwrap()
+            .get(pdf_id)
+            .map(|case_shares| case_shares.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drops the shares of a case once its package has been opened.
+    pub fn forget(&self, pdf_id: &str) {
+        self.shares.lock().unwrap().remove(pdf_id);
+    }
+}
@@ -6,3 +6,5 @@ pub mod encryption;
 pub mod storage;
 pub mod domain;
 pub mod db;
+pub mod jury;
+pub mod pdf;
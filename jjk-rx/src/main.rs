@@ -1,15 +1,15 @@
+use std::sync::Arc;
 use tracing_subscriber::fmt::format::FmtSpan;
 
-mod prelude;
-mod domain;
-mod encryption;
-mod storage;
-mod routes;
-mod db; 
-
-use prelude::*;
-use storage::Database;
-use routes::handlers;
+use jjk_rx::{
+    prelude::*,
+    settings::get_settings,
+    storage::{Database, FileStore, KeyStore, build_key_store, spawn_janitor},
+    encryption::{KeyPool, Keyring},
+    jury::ShareVault,
+    routes::{audit, handlers, jury, metrics, stream},
+    domain::{CBOR_CONTENT_TYPE, STREAM_CONTENT_TYPE},
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -22,22 +22,106 @@ async fn main() -> std::io::Result<()> {
         .with_span_events(FmtSpan::CLOSE)
         .init();
 
+    let settings = get_settings().map_err(std::io::Error::other)?;
+
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env or env vars");
-    let db = Database::connect(&database_url).await.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let keyring = Arc::new(Keyring::load(&settings.rx.kek).map_err(std::io::Error::other)?);
+    info!("Private keys and file keys are wrapped with KEK '{}'", keyring.current_id());
+    let db = Database::connect(&database_url, keyring.clone()).await.map_err(std::io::Error::other)?;
+    let key_store = build_key_store(&settings.rx.key_store, &db, keyring.clone()).map_err(std::io::Error::other)?;
+    info!("Private keys are kept in the '{}' key store", settings.rx.key_store.backend);
+    let files_data = web::Data::new(FileStore::new(keyring, "./out"));
+
+    match std::env::args().nth(1).as_deref() {
+        Some("rotate-kek") => return rotate_kek(&db, key_store.as_ref()).await,
+        Some("verify-audit") => return verify_audit(&db, settings.rx.audit.unkeyed_until).await,
+        Some("encrypt-files") => return encrypt_files(&db, &files_data).await,
+        _ => {}
+    }
+
+    let key_pool = KeyPool::new(db.clone(), &settings.rx.key_pool);
+    key_pool.spawn_refill();
+    spawn_janitor(db.clone(), key_store.clone(), settings.rx.key_expiry.janitor_interval_secs);
+    let key_store_data = web::Data::from(key_store);
+    let key_pool_data = web::Data::new(key_pool);
     let db_data = web::Data::new(db);
+    let vault_data = web::Data::new(ShareVault::default());
+
+    let host = settings.rx.host.clone();
+    let port = settings.rx.port;
+
+    if settings.rx.jury.enabled {
+        info!("Jury sealing enabled, {} juror shares required per case", settings.rx.jury.threshold);
+    }
+
+    let max_package_bytes = settings.rx.max_package_bytes;
+    let settings_data = web::Data::new(settings);
 
-    info!("Server listening on 0.0.0.0:8081");
+    info!("Server listening on {}:{}", host, port);
     info!("Endpoints: /public_key (GET), /receive (POST)");
 
     HttpServer::new(move || {
         App::new()
             .app_data(db_data.clone())
+            .app_data(settings_data.clone())
+            .app_data(vault_data.clone())
+            .app_data(key_pool_data.clone())
+            .app_data(files_data.clone())
+            .app_data(key_store_data.clone())
+            .app_data(web::JsonConfig::default().limit(max_package_bytes))
+            .app_data(web::PayloadConfig::new(max_package_bytes))
             .route("/public_key", web::get().to(handlers::get_public_key))
+            .route("/receive", web::post()
+                .guard(actix_web::guard::Header("content-type", STREAM_CONTENT_TYPE))
+                .to(stream::receive_stream))
+            .route("/receive", web::post()
+                .guard(actix_web::guard::Header("content-type", CBOR_CONTENT_TYPE))
+                .to(handlers::receive_cbor))
             .route("/receive", web::post().to(handlers::receive_package))
             .route("/cases", web::get().to(handlers::list_cases))
-            .route("/download/{caseCode}", web::get().to(handlers::download_case))
+            .route("/cases", web::post().to(handlers::register_case))
+            .route("/cases/search", web::get().to(handlers::search_cases))
+            .route("/cases/{caseNumber}/documents/{pdfId}", web::get().to(handlers::download_document))
+            .route("/cases/{caseNumber}/custody", web::get().to(audit::custody_report))
+            .route("/download/{caseNumber}", web::get().to(handlers::download_case))
+            .route("/jurors", web::post().to(jury::register_juror))
+            .route("/jurors/{juror}/shares", web::get().to(jury::pending_shares))
+            .route("/shares", web::post().to(jury::submit_share))
+            .route("/metrics", web::get().to(metrics::metrics))
+            .route("/audit/verify", web::get().to(audit::verify_audit))
     })
-    .bind(("0.0.0.0", 8081))?
+    .bind((host, port))?
     .run()
     .await
 }
+
+/// `jjk-rx rotate-kek`: re-wraps every stored private key and file key with the current KEK and exits.
+async fn rotate_kek(db: &Database, key_store: &dyn KeyStore) -> std::io::Result<()> {
+    let rewrapped = db.rewrap_file_and_pool_keys().await.map_err(std::io::Error::other)?
+        + key_store.rewrap().await.map_err(std::io::Error::other)?;
+    info!("Re-wrapped {} keys with the current KEK", rewrapped);
+    Ok(())
+}
+
+/// `jjk-rx encrypt-files`: encrypts the documents stored before encryption at rest, removes their plaintext and exits.
+async fn encrypt_files(db: &Database, files: &FileStore) -> std::io::Result<()> {
+    let encrypted = files.encrypt_plaintext_files(db).await.map_err(std::io::Error::other)?;
+    info!("Encrypted {} documents stored in plaintext", encrypted);
+    Ok(())
+}
+
+/// `jjk-rx verify-audit`: checks the audit chain and exits with an error at the first broken link.
+async fn verify_audit(db: &Database, unkeyed_until: i64) -> std::io::Result<()> {
+    let verification = db.verify_audit_chain(unkeyed_until, None).await.map_err(std::io::Error::other)?;
+
+    match verification.first_broken {
+        None => {
+            info!("Audit chain intact, {} events checked", verification.checked);
+            Ok(())
+        }
+        Some(broken) => {
+            error!("Audit chain broken at event {}: {}", broken.id, broken.reason);
+            Err(std::io::Error::other(format!("audit chain broken at event {}", broken.id)))
+        }
+    }
+}
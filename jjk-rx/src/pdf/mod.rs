@@ -0,0 +1,6 @@
+pub mod report;
+pub mod text;
+pub mod watermark;
+pub use report::CustodyPdf;
+pub use text::TextExtractor;
+pub use watermark::{Stamp, Watermark};
@@ -0,0 +1,227 @@
+use crate::domain::{CustodyReport, DocumentCustody};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, Stream, StringFormat};
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const FONT_SIZE: f32 = 9.0;
+const LINE_HEIGHT: f32 = 12.0;
+/// Characters per line, Helvetica at 9 pt averaging a little under 5 pt per character.
+const LINE_CHARS: usize = 100;
+const LINES_PER_PAGE: usize = ((PAGE_HEIGHT - 2.0 * MARGIN) / LINE_HEIGHT) as usize - 2;
+
+enum Line {
+    Heading(String),
+    Text(String),
+    Blank,
+}
+
+pub struct CustodyPdf {}
+
+impl CustodyPdf {
+    /// Lays the report out as plain A4 pages: the case, each document's path to storage, then every audit event.
+    pub fn render(report: &CustodyReport) -> anyhow::Result<Vec<u8>> {
+        let lines = lines(report);
+        let pages: Vec<&[Line]> = lines.chunks(LINES_PER_PAGE).collect();
+
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let regular_id = document.add_object(font("Helvetica"));
+        let bold_id = document.add_object(font("Helvetica-Bold"));
+        let resources_id = document.add_object(dictionary! {
+            "Font" => dictionary! {
+                "F1" => regular_id,
+                "F2" => bold_id,
+            },
+        });
+
+        let mut kids: Vec<Object> = Vec::with_capacity(pages.len());
+        for (index, page) in pages.iter().enumerate() {
+            let footer = format!("Chain of custody - case {} - page {} of {}", report.case_number, index + 1, pages.len());
+            let content = page_content(page, &footer).encode()?;
+            let content_id = document.add_object(Stream::new(dictionary! {}, content));
+
+            kids.push(document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            }).into());
+        }
+
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH.into(), PAGE_HEIGHT.into()],
+        }));
+
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        let info_id = document.add_object(dictionary! {
+            "Title" => text(&format!("Chain of custody - case {}", report.case_number)),
+            "Producer" => text("jjk-rx"),
+        });
+        document.trailer.set("Root", catalog_id);
+        document.trailer.set("Info", info_id);
+        document.compress();
+
+        let mut rendered = Vec::new();
+        document.save_to(&mut rendered)?;
+        Ok(rendered)
+    }
+}
+
+fn lines(report: &CustodyReport) -> Vec<Line> {
+    let mut lines = vec![Line::Heading(format!("Chain of custody - case {}", report.case_number)), Line::Blank];
+
+    field(&mut lines, "Court", report.court.as_deref().unwrap_or("-"));
+    field(&mut lines, "Parties", report.parties.as_deref().unwrap_or("-"));
+    field(&mut lines, "Status", &report.status);
+    field(&mut lines, "Opened", &report.opened_at.format("%Y-%m-%d %H:%M:%S").to_string());
+    field(&mut lines, "Generated", &format!("{} by {}", report.generated_at.format("%Y-%m-%d %H:%M:%S UTC"), report.generated_by));
+
+    let chain = match &report.audit_chain.first_broken {
+        None => format!("intact, {} events checked", report.audit_chain.checked),
+        Some(broken) => format!("BROKEN at event {}: {}", broken.id, broken.reason),
+    };
+    field(&mut lines, "Audit chain", &chain);
+
+    for document in &report.documents {
+        lines.push(Line::Blank);
+        lines.push(Line::Heading(format!("Document {}", document.case_code)));
+        document_lines(&mut lines, document);
+    }
+
+    lines.push(Line::Blank);
+    lines.push(Line::Heading(format!("Events ({})", report.events.len())));
+
+    for event in &report.events {
+        let summary = format!(
+            "#{} {} {} by {}{}",
+            event.id,
+            event.at.format("%Y-%m-%d %H:%M:%S UTC"),
+            event.action,
+            event.actor,
+            event.pdf_id.as_deref().map(|pdf_id| format!(" on {}", pdf_id)).unwrap_or_default(),
+        );
+        lines.extend(wrap(&summary).into_iter().map(Line::Text));
+        lines.extend(wrap(&format!("    {}", event.detail)).into_iter().map(Line::Text));
+        lines.push(Line::Text(format!("    hash {}", event.hash)));
+    }
+
+    lines
+}
+
+fn document_lines(lines: &mut Vec<Line>, document: &DocumentCustody) {
+    let time = |time: Option<chrono::NaiveDateTime>| time.map_or("-".to_string(), |time| time.format("%Y-%m-%d %H:%M:%S").to_string());
+
+    field(lines, "Title", document.title.as_deref().unwrap_or("-"));
+    field(lines, "Received by TX (signed)", &document.tx_received_at.map_or("-".to_string(), |time| time.format("%Y-%m-%d %H:%M:%S%.3f UTC").to_string()));
+    field(lines, "Key issued to TX", &time(document.key_issued_at));
+    field(lines, "Received by RX", &time(document.received_at));
+    field(lines, "Stored", &time(document.stored_at));
+    field(lines, "Sender", document.sender_id.as_deref().unwrap_or("-"));
+    field(lines, "Verification", document.verification.describe());
+    field(lines, "Rejected packages", &document.rejected_packages.to_string());
+    field(lines, "Package SHA-256 (base64)", document.package_hash.as_deref().unwrap_or("-"));
+    field(lines, "PDF SHA-256", document.sha256.as_deref().unwrap_or("-"));
+    field(lines, "Size", &document.file_size.map_or("-".to_string(), |size| format!("{} bytes", size)));
+    field(lines, "Storage location", document.storage_location.as_deref().unwrap_or("-"));
+}
+
+fn field(lines: &mut Vec<Line>, name: &str, value: &str) {
+    lines.extend(wrap(&format!("{}: {}", name, value)).into_iter().map(Line::Text));
+}
+
+fn page_content(lines: &[Line], footer: &str) -> Content {
+    let mut operations = vec![
+        Operation::new("BT", vec![]),
+        Operation::new("TL", vec![LINE_HEIGHT.into()]),
+        Operation::new("Td", vec![MARGIN.into(), (PAGE_HEIGHT - MARGIN).into()]),
+    ];
+
+    for line in lines {
+        match line {
+            Line::Heading(heading) => {
+                operations.push(Operation::new("Tf", vec!["F2".into(), (FONT_SIZE + 2.0).into()]));
+                operations.push(Operation::new("Tj", vec![text(heading)]));
+            }
+            Line::Text(line) => {
+                operations.push(Operation::new("Tf", vec!["F1".into(), FONT_SIZE.into()]));
+                operations.push(Operation::new("Tj", vec![text(line)]));
+            }
+            Line::Blank => {}
+        }
+        operations.push(Operation::new("T*", vec![]));
+    }
+
+    operations.extend([
+        Operation::new("ET", vec![]),
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec!["F1".into(), 7.into()]),
+        Operation::new("g", vec![0.35.into()]),
+        Operation::new("Td", vec![MARGIN.into(), (MARGIN / 2.0).into()]),
+        Operation::new("Tj", vec![text(footer)]),
+        Operation::new("ET", vec![]),
+    ]);
+
+    Content { operations }
+}
+
+fn font(base_font: &str) -> lopdf::Dictionary {
+    dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => base_font,
+        "Encoding" => "WinAnsiEncoding",
+    }
+}
+
+/// A string in the fonts' WinAnsi encoding, which matches Latin-1 for accented letters. Anything else is `?`.
+fn text(text: &str) -> Object {
+    let bytes = text
+        .chars()
+        .map(|c| match c as u32 {
+            0x20..=0x7e | 0xa0..=0xff => c as u8,
+            _ => b'?',
+        })
+        .collect();
+
+    Object::String(bytes, StringFormat::Literal)
+}
+
+/// Splits a line at spaces to fit the page, breaking words longer than a line such as paths.
+/// Continuation lines keep the indentation of the first.
+fn wrap(line: &str) -> Vec<String> {
+    let body = line.trim_start_matches(' ');
+    let indent = &line[..line.len() - body.len()];
+    let width = LINE_CHARS.saturating_sub(indent.len()).max(1);
+
+    let mut wrapped = Vec::new();
+    let mut current = String::new();
+
+    for word in body.split(' ').filter(|word| !word.is_empty()) {
+        let mut word: Vec<char> = word.chars().collect();
+
+        if !current.is_empty() && current.chars().count() + 1 + word.len() > width {
+            wrapped.push(std::mem::take(&mut current));
+        }
+        while word.len() > width {
+            let rest = word.split_off(width);
+            wrapped.push(word.into_iter().collect());
+            word = rest;
+        }
+
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.extend(word);
+    }
+
+    wrapped.push(current);
+    wrapped.into_iter().map(|line| format!("{}{}", indent, line)).collect()
+}
@@ -0,0 +1,58 @@
+use crate::prelude::*;
+use lopdf::Document;
+
+/// Text kept per document for search, well under the 1 MB Postgres allows in a `tsvector`.
+pub const MAX_TEXT_BYTES: usize = 512 * 1024;
+
+pub struct TextExtractor {}
+
+impl TextExtractor {
+    /// Extracts the text of every page, with whitespace collapsed. Pages lopdf can't decode are skipped,
+    /// so scanned or oddly encoded documents just yield less text.
+    pub fn extract(pdf: &[u8]) -> anyhow::Result<String> {
+        let document = Document::load_mem(pdf)?;
+        if document.is_encrypted() {
+            return Err(anyhow!("Password-protected PDFs have no readable text"));
+        }
+
+        let mut text = String::new();
+        for page in document.get_pages().into_keys() {
+            match document.extract_text(&[page]) {
+                Ok(page_text) => {
+                    for word in page_text.split_whitespace() {
+                        // Postgres text can't hold NUL characters
+                        text.extend(word.chars().filter(|c| *c != '\0'));
+                        text.push(' ');
+                    }
+                }
+                Err(e) => debug!("No text extracted from page {}: {}", page, e),
+            }
+
+            if text.len() >= MAX_TEXT_BYTES {
+                break;
+            }
+        }
+
+        if text.len() > MAX_TEXT_BYTES {
+            let mut end = MAX_TEXT_BYTES;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+        }
+
+        Ok(text.trim_end().to_string())
+    }
+
+    /// Extracts the text of a received PDF off the async runtime. A document whose text can't be read is
+    /// still received, so failures are only logged.
+    pub async fn searchable_text(pdf_id: &str, pdf: Vec<u8>) -> Option<String> {
+        let extracted = tokio::task::spawn_blocking(move || Self::extract(&pdf)).await
+            .map_err(anyhow::Error::from)
+            .and_then(|text| text);
+
+        extracted
+            .inspect_err(|e| warn!("Failed to extract the text of {}: {}", pdf_id, e))
+            .ok()
+    }
+}
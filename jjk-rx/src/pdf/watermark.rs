@@ -0,0 +1,181 @@
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+const FONT_NAME: &str = "JJKStampFont";
+const STATE_NAME: &str = "JJKStampState";
+/// Page tree levels followed up for inherited attributes before giving up.
+const INHERIT_LIMIT: usize = 32;
+
+/// Who downloaded which case and when, printed on every page of a download.
+#[derive(Clone)]
+pub struct Stamp {
+    pub case_number: String,
+    pub requested_by: String,
+    pub at: DateTime<Utc>,
+}
+
+impl Stamp {
+    /// The stamp line. The standard font only encodes Latin text, so anything else is replaced by `?`.
+    pub fn text(&self) -> String {
+        format!(
+            "Case {} - downloaded by {} - {}",
+            self.case_number,
+            self.requested_by,
+            self.at.format("%Y-%m-%d %H:%M:%S UTC")
+        )
+        .chars()
+        .map(|c| if c == ' ' || c.is_ascii_graphic() { c } else { '?' })
+        .collect()
+    }
+}
+
+pub struct Watermark {}
+
+impl Watermark {
+    /// Overlays the stamp on every page: a footer line and a faint diagonal line across the middle.
+    /// The existing page content is left as is, wrapped so its graphics state can't leak into the overlay.
+    pub fn apply(pdf: &[u8], stamp: &Stamp) -> anyhow::Result<Vec<u8>> {
+        let mut document = Document::load_mem(pdf)?;
+        if document.is_encrypted() {
+            return Err(anyhow!("Password-protected PDFs cannot be stamped"));
+        }
+
+        let text = stamp.text();
+        let font_id = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => "WinAnsiEncoding",
+        });
+        let state_id = document.add_object(dictionary! {
+            "Type" => "ExtGState",
+            "ca" => 0.15,
+        });
+
+        let pages: Vec<ObjectId> = document.get_pages().into_values().collect();
+        for page_id in pages {
+            let overlay = overlay(&document, page_id, &text)?;
+            add_resources(&mut document, page_id, font_id, state_id)?;
+            wrap_contents(&mut document, page_id, overlay)?;
+        }
+
+        let mut stamped = Vec::with_capacity(pdf.len());
+        document.save_to(&mut stamped)?;
+        Ok(stamped)
+    }
+}
+
+/// Builds the overlay in the coordinates the page is displayed in, accounting for its crop box and rotation.
+fn overlay(document: &Document, page_id: ObjectId, text: &str) -> anyhow::Result<Vec<u8>> {
+    let bounds = inherited(document, page_id, b"CropBox")
+        .or_else(|| inherited(document, page_id, b"MediaBox"))
+        .and_then(|bounds| bounds.as_array().ok())
+        .map(|bounds| bounds.iter().filter_map(|n| n.as_float().ok()).collect::<Vec<f32>>())
+        .filter(|bounds| bounds.len() == 4)
+        .unwrap_or_else(|| vec![0.0, 0.0, 612.0, 792.0]);
+    let (x0, y0) = (bounds[0].min(bounds[2]), bounds[1].min(bounds[3]));
+    let (x1, y1) = (bounds[0].max(bounds[2]), bounds[1].max(bounds[3]));
+
+    let rotate = inherited(document, page_id, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+
+    // Maps the displayed page, origin at its bottom left corner, onto the page's own space
+    let (matrix, width, height) = match rotate {
+        90 => ([0.0, 1.0, -1.0, 0.0, x1, y0], y1 - y0, x1 - x0),
+        180 => ([-1.0, 0.0, 0.0, -1.0, x1, y1], x1 - x0, y1 - y0),
+        270 => ([0.0, -1.0, 1.0, 0.0, x0, y1], y1 - y0, x1 - x0),
+        _ => ([1.0, 0.0, 0.0, 1.0, x0, y0], x1 - x0, y1 - y0),
+    };
+
+    // Helvetica averages about half an em per character, close enough to center the diagonal line
+    let diagonal = (width * width + height * height).sqrt();
+    let size = (0.8 * diagonal / (0.5 * text.len() as f32)).clamp(10.0, 48.0);
+    let angle = height.atan2(width);
+    let (sin, cos) = angle.sin_cos();
+
+    let mut operations = vec![
+        Operation::new("Q", vec![]),
+        Operation::new("q", vec![]),
+        Operation::new("cm", matrix.iter().map(|&n| n.into()).collect()),
+        // Footer, kept within the margins every printer leaves blank
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![FONT_NAME.into(), 7.into()]),
+        Operation::new("g", vec![0.35.into()]),
+        Operation::new("Td", vec![18.into(), 10.into()]),
+        Operation::new("Tj", vec![Object::string_literal(text)]),
+        Operation::new("ET", vec![]),
+        // Diagonal, faint enough to leave the document readable
+        Operation::new("gs", vec![STATE_NAME.into()]),
+        Operation::new("cm", vec![cos.into(), sin.into(), (-sin).into(), cos.into(), (width / 2.0).into(), (height / 2.0).into()]),
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![FONT_NAME.into(), size.into()]),
+        Operation::new("g", vec![0.5.into()]),
+        Operation::new("Td", vec![(-0.25 * size * text.len() as f32).into(), (-size / 3.0).into()]),
+        Operation::new("Tj", vec![Object::string_literal(text)]),
+        Operation::new("ET", vec![]),
+        Operation::new("Q", vec![]),
+    ];
+    // The Q closing the page's own content comes first
+    if document.get_page_contents(page_id).is_empty() {
+        operations.remove(0);
+    }
+
+    Ok(Content { operations }.encode()?)
+}
+
+/// Gives the page its own resources, a copy of what it used so far plus the stamp font and graphics state,
+/// so resources shared with other pages are never modified.
+fn add_resources(document: &mut Document, page_id: ObjectId, font_id: ObjectId, state_id: ObjectId) -> anyhow::Result<()> {
+    let mut resources = inherited(document, page_id, b"Resources")
+        .and_then(|resources| resources.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+
+    for (key, name, id) in [(b"Font".as_slice(), FONT_NAME, font_id), (b"ExtGState", STATE_NAME, state_id)] {
+        let mut entries = resources.get(key)
+            .ok()
+            .and_then(|entries| document.dereference(entries).ok())
+            .and_then(|(_, entries)| entries.as_dict().ok())
+            .cloned()
+            .unwrap_or_default();
+        entries.set(name, Object::Reference(id));
+        resources.set(key, entries);
+    }
+
+    document.get_dictionary_mut(page_id)?.set("Resources", resources);
+    Ok(())
+}
+
+/// Puts the page's content between `q` and the overlay, which starts by restoring the state with `Q`.
+fn wrap_contents(document: &mut Document, page_id: ObjectId, overlay: Vec<u8>) -> anyhow::Result<()> {
+    let existing = document.get_page_contents(page_id);
+
+    let mut contents: Vec<Object> = Vec::with_capacity(existing.len() + 2);
+    if !existing.is_empty() {
+        let save_id = document.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+        contents.push(save_id.into());
+        contents.extend(existing.into_iter().map(Object::Reference));
+    }
+    contents.push(document.add_object(Stream::new(Dictionary::new(), overlay)).into());
+
+    document.get_dictionary_mut(page_id)?.set("Contents", contents);
+    Ok(())
+}
+
+/// A page attribute, looked up the page tree when the page doesn't set it itself.
+fn inherited<'a>(document: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = document.get_dictionary(page_id).ok()?;
+
+    for _ in 0..INHERIT_LIMIT {
+        if let Ok(value) = node.get(key) {
+            return document.dereference(value).ok().map(|(_, value)| value);
+        }
+        node = node.get(b"Parent").and_then(Object::as_reference).and_then(|id| document.get_dictionary(id)).ok()?;
+    }
+
+    None
+}
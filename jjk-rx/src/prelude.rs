@@ -1,13 +1,13 @@
-pub use actix_web::{web, App, HttpServer, HttpResponse, Responder};
-pub use anyhow::{Result, anyhow};
-pub use serde::{Serialize, Deserialize};
-pub use tracing::{info, error, debug};
-
-
-pub use uuid::Uuid;
-pub use rsa::{RsaPrivateKey, RsaPublicKey, Pkcs1v15Encrypt};
-pub use rsa::pkcs8::{EncodePublicKey, LineEnding};
-pub use aes_gcm::{Aes256Gcm, KeyInit, aead::Aead};
-pub use sha2::{Sha256, Digest};
-pub use base64::{Engine as _, engine::general_purpose::STANDARD as b64};
-pub use rand::rngs::OsRng;
+pub use actix_web::{web, App, HttpServer, HttpResponse, Responder};
+pub use anyhow::{Result, anyhow};
+pub use serde::{Serialize, Deserialize};
+pub use tracing::{info, warn, error, debug};
+
+
+pub use uuid::Uuid;
+pub use rsa::{RsaPrivateKey, RsaPublicKey, Pkcs1v15Encrypt, Oaep};
+pub use rsa::pkcs8::{EncodePublicKey, LineEnding};
+pub use aes_gcm::{Aes256Gcm, KeyInit, aead::{Aead, AeadCore, Payload}};
+pub use sha2::{Sha256, Digest};
+pub use base64::{Engine as _, engine::general_purpose::STANDARD as b64};
+pub use rand::rngs::OsRng;
@@ -0,0 +1,90 @@
+use crate::prelude::*;
+use crate::domain::{AuditEvent, CustodyQuery, ReportFormat, AUDIT_CUSTODY_REPORTED};
+use crate::pdf::CustodyPdf;
+use crate::settings::Settings;
+use crate::storage::Database;
+use super::handlers::{audit, require_user};
+use actix_web::HttpRequest;
+
+/// Checks the audit chain, answering 409 with the first broken link when it has been tampered with.
+pub async fn verify_audit(req: HttpRequest, db: web::Data<Database>, settings: web::Data<Settings>) -> impl Responder {
+    if let Err(response) = require_user(&req, &settings) {
+        return response;
+    }
+
+    match db.verify_audit_chain(settings.rx.audit.unkeyed_until, None).await {
+        Ok(verification) if verification.valid => HttpResponse::Ok().json(verification),
+        Ok(verification) => {
+            warn!("Audit chain broken: {:?}", verification.first_broken);
+            HttpResponse::Conflict().json(verification)
+        }
+        Err(e) => {
+            error!("Failed to verify the audit chain: {}", e);
+            HttpResponse::InternalServerError().body("DB Error")
+        }
+    }
+}
+
+/// Chain-of-custody report of a case, as JSON or with `?format=pdf` as a PDF. Generating it is itself
+/// recorded first, so the report lists its own request.
+pub async fn custody_report(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    settings: web::Data<Settings>,
+    path: web::Path<String>,
+    query: web::Query<CustodyQuery>,
+) -> impl Responder {
+    let case_number = path.into_inner();
+    let requested_by = match require_user(&req, &settings) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match db.case_documents(&case_number).await {
+        Ok(Some(_)) => {},
+        Ok(None) => return HttpResponse::NotFound().body("Case not found"),
+        Err(e) => {
+            error!("Failed to fetch documents of case {}: {}", case_number, e);
+            return HttpResponse::InternalServerError().body("DB Error");
+        }
+    }
+
+    let recorded = audit(&db, AuditEvent {
+        action: AUDIT_CUSTODY_REPORTED.to_string(),
+        actor: requested_by.clone(),
+        case_number: Some(case_number.clone()),
+        pdf_id: None,
+        detail: serde_json::json!({ "format": query.format }),
+    }).await;
+    if recorded.is_err() {
+        return HttpResponse::InternalServerError().body("Audit Error");
+    }
+
+    let report = match db.custody_report(&case_number, &requested_by, settings.rx.audit.unkeyed_until).await {
+        Ok(Some(report)) => report,
+        Ok(None) => return HttpResponse::NotFound().body("Case not found"),
+        Err(e) => {
+            error!("Failed to build the custody report of case {}: {}", case_number, e);
+            return HttpResponse::InternalServerError().body("DB Error");
+        }
+    };
+
+    if query.format == ReportFormat::Json {
+        return HttpResponse::Ok().json(report);
+    }
+
+    let rendered = tokio::task::spawn_blocking(move || CustodyPdf::render(&report)).await
+        .map_err(anyhow::Error::from)
+        .and_then(|rendered| rendered);
+
+    match rendered {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .append_header(("Content-Disposition", format!("attachment; filename=\"custody-{}.pdf\"", case_number)))
+            .body(bytes),
+        Err(e) => {
+            error!("Failed to render the custody report of case {}: {}", case_number, e);
+            HttpResponse::InternalServerError().body("Report Error")
+        }
+    }
+}
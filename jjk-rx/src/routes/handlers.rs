@@ -1,138 +1,1032 @@
-use crate::prelude::*;
-use crate::storage::Database;
-use crate::encryption::Decrypter;
-use crate::domain::{RxKeyResponse, RxPayload, PdfData, CaseSummary};
-use std::path::PathBuf;
-use tokio::fs;
-
-pub async fn get_public_key(db: web::Data<Database>) -> impl Responder {
-    info!("Generating new key pair for upcoming transmission...");
-    
-    let pdf_id = Uuid::new_v4().to_string();
-    
-    match Decrypter::generate_keys() {
-        Ok((priv_key, pub_key_pem)) => {
-            if let Err(e) = db.insert_keys(pdf_id.clone(), priv_key, pub_key_pem.clone()).await {
-                error!("Failed to save keys to DB: {}", e);
-                return HttpResponse::InternalServerError().body("DB Error");
-            }
-
-            info!("Keys generated for PDF ID: {}", pdf_id);
-            HttpResponse::Ok().json(RxKeyResponse {
-                pdf_id,
-                pub_key: pub_key_pem,
-            })
-        },
-        Err(e) => {
-            error!("Failed to generate keys: {}", e);
-            HttpResponse::InternalServerError().body("Key Generation Error")
-        }
-    }
-}
-
-pub async fn receive_package(
-    db: web::Data<Database>,
-    payload: web::Json<RxPayload>,
-) -> impl Responder {
-    let pdf_id = &payload.pdf_id;
-    let pkg = &payload.pkg;
-    
-    info!("Received encrypted package for PDF ID: {}", pdf_id);
-
-    let priv_key = match db.get_private_key(pdf_id).await {
-        Ok(k) => k,
-        Err(_) => {
-            error!("PDF ID {} not found", pdf_id);
-            return HttpResponse::NotFound().body("PDF ID not found");
-        }
-    };
-
-    let plaintext_bytes = match Decrypter::decrypt_hybrid(
-        &priv_key,
-        &pkg.encrypted_session_key_b64,
-        &pkg.encrypted_data_b64,
-        &pkg.nonce_b64
-    ) {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            error!("Decryption failed for {}: {}", pdf_id, e);
-            return HttpResponse::BadRequest().body(format!("Decryption failed: {}", e));
-        }
-    };
-
-    match Decrypter::verify_hash(&plaintext_bytes, &pkg.hash_b64) {
-        Ok(true) => {
-            debug!("Hash verification successful for {}", pdf_id);
-        },
-        Ok(false) => {
-            error!("Hash verification failed for {}", pdf_id);
-            return HttpResponse::BadRequest().body("Integrity check failed (Hash mismatch)");
-        },
-        Err(e) => {
-             error!("Hash verification error: {}", e);
-             return HttpResponse::BadRequest().body("Hash verification error");
-        }
-    }
-    
-    let pdf_data = match serde_json::from_slice::<PdfData>(&plaintext_bytes) {
-        Ok(data) => {
-            debug!("Decrypted Data - Title: '{}', Author: '{}'", data.title, data.author);
-            data
-        }
-        Err(e) => {
-            error!("Failed to deserialize PDF Data: {}", e);
-            return HttpResponse::BadRequest().body("Invalid PDF payload");
-        }
-    };
-
-    let out_dir = PathBuf::from("./out");
-    if let Err(e) = fs::create_dir_all(&out_dir).await {
-        error!("Failed to create output directory: {}", e);
-        return HttpResponse::InternalServerError().body("Storage Error");
-    }
-
-    let file_path = out_dir.join(format!("{}.pdf", pdf_id));
-    if let Err(e) = fs::write(&file_path, &pdf_data.file).await {
-        error!("Failed to write PDF file: {}", e);
-        return HttpResponse::InternalServerError().body("Storage Error");
-    }
-
-    if let Err(e) = db.update_file_path(pdf_id, &file_path.to_string_lossy()).await {
-        error!("Failed to update DB record: {}", e);
-        return HttpResponse::InternalServerError().body("DB Update Error");
-    }
-
-    info!("Transmission successful for PDF ID: {}", pdf_id);
-    HttpResponse::Ok().body("Transmission received and verified successfully")
-}
-
-pub async fn list_cases(db: web::Data<Database>) -> impl Responder {
-    let cases: Vec<CaseSummary> = match db.list_cases().await {
-        Ok(items) => items,
-        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
-    };
-
-    HttpResponse::Ok().json(cases)
-}
-
-pub async fn download_case(
-    db: web::Data<Database>,
-    path: web::Path<String>,
-) -> impl Responder {
-    let case_code = path.into_inner();
-
-    let file_path = match db.get_case_file_path(&case_code).await {
-        Ok(p) => p,
-        Err(_) => return HttpResponse::NotFound().body("Case not found"),
-    };
-
-    let bytes = match fs::read(&file_path).await {
-        Ok(data) => data,
-        Err(_) => return HttpResponse::NotFound().body("PDF not found"),
-    };
-
-    HttpResponse::Ok()
-        .content_type("application/pdf")
-        .append_header(("Content-Disposition", format!("attachment; filename=\"{}.pdf\"", case_code)))
-        .body(bytes)
-}
+use crate::prelude::*;
+use crate::settings::Settings;
+use crate::storage::{CaseIssue, CaseRegistered, Database, FileStore, KeyIssue, KeyStore, StoredFile, StoredKey, KeyClaim};
+use crate::encryption::{CaseKey, Decrypter, KeyPool, KeyWrap, Verifier};
+use crate::jury::{KeySealer, ShareVault};
+use crate::pdf::{Stamp, TextExtractor, Watermark};
+use crate::domain::{
+    AuditEvent, AUDIT_CASE_REGISTERED, AUDIT_CASES_LISTED, AUDIT_CASES_SEARCHED, AUDIT_CASE_DOWNLOADED, AUDIT_DOCUMENT_DOWNLOADED, AUDIT_KEY_ISSUED, AUDIT_PACKAGE_RECEIVED,
+    RxKeyResponse, KeyRequest, RxPayload, BinaryRxPayload, BinaryPackage, CaseCursor, CaseFilter, CasePage, CaseRegistration, DocumentMetadata, PdfData, SearchQuery, WireFormat,
+    ALG_RSA_OAEP_256, ALG_X25519_HKDF_SHA256, SIGNED_ENVELOPE_VERSION, associated_data, signed_message,
+    KEY_REQUEST_MAX_SKEW_SECS, KEY_REQUEST_SENDER_HEADER, KEY_REQUEST_SIGNATURE_HEADER, KEY_REQUEST_TIMESTAMP_HEADER, key_request_message,
+};
+use super::jury::seal_for_jury;
+use actix_web::HttpRequest;
+use actix_web::http::StatusCode;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Where a package waiting for the jury quorum is kept until its key can be rebuilt.
+pub(crate) fn sealed_package_path(pdf_id: &str) -> PathBuf {
+    PathBuf::from("./out").join(format!("{}.pkg.json", pdf_id))
+}
+
+/// Same as `sealed_package_path`, for packages received in the CBOR envelope.
+pub(crate) fn sealed_cbor_path(pdf_id: &str) -> PathBuf {
+    PathBuf::from("./out").join(format!("{}.pkg.cbor", pdf_id))
+}
+
+pub async fn get_public_key(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    key_store: web::Data<dyn KeyStore>,
+    settings: web::Data<Settings>,
+    key_pool: web::Data<KeyPool>,
+    query: web::Query<KeyRequest>,
+) -> impl Responder {
+    let alg = query.alg.as_deref().unwrap_or(ALG_RSA_OAEP_256);
+    if ![ALG_RSA_OAEP_256, ALG_X25519_HKDF_SHA256].contains(&alg) {
+        return HttpResponse::BadRequest().body(format!("Unsupported key algorithm '{}'", alg));
+    }
+
+    let sender_id = match authenticate_key_request(&req, &settings, alg, &query) {
+        Ok(sender_id) => sender_id,
+        Err(response) => return response,
+    };
+    if sender_id.is_none() && (query.case.is_some() || query.group.is_some()) {
+        return HttpResponse::Unauthorized().body("Filing under a case or group needs a signed key request");
+    }
+
+    let case_number = match (&query.case, &query.group) {
+        (Some(case_number), _) => Some(case_number.clone()),
+        (None, Some(group)) => match db.filing_of(group).await {
+            // Only the sender of the group's document may add to its case
+            Ok(Some((case_number, group_sender))) if group_sender == sender_id => Some(case_number),
+            Ok(Some(_)) => return HttpResponse::Forbidden().body("Group belongs to another sender"),
+            Ok(None) => return HttpResponse::NotFound().body("Group code not found"),
+            Err(e) => {
+                error!("Failed to look up group {}: {}", group, e);
+                return HttpResponse::InternalServerError().body("DB Error");
+            }
+        },
+        (None, None) => None,
+    };
+
+    info!("Issuing new {} key pair for upcoming transmission...", alg);
+    
+    let pdf_id = Uuid::new_v4().to_string();
+    // Without a case to join, the PDF opens its own under its PDF ID
+    let case_number = case_number.unwrap_or_else(|| pdf_id.clone());
+    
+    let (key_issue, priv_key, pub_key_pem) = match key_pool.begin_issue(alg).await {
+        Ok(issued) => issued,
+        Err(e) => {
+            error!("Failed to issue a key: {}", e);
+            return HttpResponse::InternalServerError().body("Key Generation Error");
+        }
+    };
+
+    let alg = priv_key.alg();
+    let issue = CaseIssue {
+        pdf_id: &pdf_id,
+        public_key_pem: &pub_key_pem,
+        case_number: &case_number,
+        sender_id: sender_id.as_deref(),
+        ttl_secs: settings.rx.key_expiry.ttl_secs,
+    };
+    let stored = if settings.rx.jury.enabled {
+        seal_for_jury(&db, key_store.get_ref(), &settings.rx.jury, key_issue, &issue, &priv_key).await
+    } else {
+        store_case_key(&db, key_store.get_ref(), key_issue, &issue, priv_key).await
+    };
+
+    if let Err(e) = stored {
+        error!("Failed to save keys to DB: {}", e);
+        return HttpResponse::InternalServerError().body("DB Error");
+    }
+
+    info!("Keys generated for PDF ID: {} (case {})", pdf_id, case_number);
+
+    // A key whose issue can't be accounted for is withdrawn rather than handed out
+    let recorded = audit(&db, AuditEvent {
+        action: AUDIT_KEY_ISSUED.to_string(),
+        actor: requester(&req, &settings),
+        case_number: Some(case_number),
+        pdf_id: Some(pdf_id.clone()),
+        detail: serde_json::json!({ "alg": alg }),
+    }).await;
+    if recorded.is_err() {
+        if let Err(e) = key_store.delete(&pdf_id).await {
+            error!("Failed to delete key of {}: {}", pdf_id, e);
+        }
+        if let Err(e) = db.discard_case(&pdf_id).await {
+            error!("Failed to discard {}: {}", pdf_id, e);
+        }
+        return HttpResponse::InternalServerError().body("Audit Error");
+    }
+
+    HttpResponse::Ok().json(RxKeyResponse {
+        pdf_id,
+        pub_key: pub_key_pem,
+        alg: alg.to_string(),
+    })
+}
+
+async fn store_case_key(
+    db: &Database,
+    key_store: &dyn KeyStore,
+    mut key_issue: KeyIssue,
+    issue: &CaseIssue<'_>,
+    priv_key: CaseKey,
+) -> Result<()> {
+    key_issue.insert_case(issue, priv_key.alg(), None, Vec::new()).await?;
+    key_issue.commit().await?;
+    put_issued_key(db, key_store, issue.pdf_id, &StoredKey::Plain(priv_key)).await
+}
+
+/// Hands the private key of a committed issue to the key store, discarding the case again if that fails
+/// so no case is left without its key.
+pub(crate) async fn put_issued_key(db: &Database, key_store: &dyn KeyStore, pdf_id: &str, key: &StoredKey) -> Result<()> {
+    if let Err(e) = key_store.put(pdf_id, key).await {
+        if let Err(discard_err) = db.discard_case(pdf_id).await {
+            error!("Failed to discard {} after its key could not be stored: {}", pdf_id, discard_err);
+        }
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Sender of a signed key request, `None` for an unsigned one.
+fn authenticate_key_request(req: &HttpRequest, settings: &Settings, alg: &str, query: &KeyRequest) -> Result<Option<String>, HttpResponse> {
+    let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let (sender_id, timestamp, signature) = match (
+        header(KEY_REQUEST_SENDER_HEADER),
+        header(KEY_REQUEST_TIMESTAMP_HEADER),
+        header(KEY_REQUEST_SIGNATURE_HEADER),
+    ) {
+        (None, None, None) => return Ok(None),
+        (Some(sender_id), Some(timestamp), Some(signature)) => (sender_id, timestamp, signature),
+        _ => return Err(HttpResponse::Unauthorized().body("Incomplete key request signature")),
+    };
+
+    let Ok(timestamp) = timestamp.parse::<i64>() else {
+        return Err(HttpResponse::Unauthorized().body("Invalid key request timestamp"));
+    };
+    if (chrono::Utc::now().timestamp() - timestamp).abs() > KEY_REQUEST_MAX_SKEW_SECS {
+        return Err(HttpResponse::Unauthorized().body("Key request is expired"));
+    }
+    let Ok(signature) = b64.decode(signature) else {
+        return Err(HttpResponse::Unauthorized().body("Invalid key request signature"));
+    };
+
+    let msg = key_request_message(timestamp, alg, query.case.as_deref(), query.group.as_deref());
+    match Verifier::verify_sender(&settings.rx.trusted_senders, Some(sender_id), Some(&signature), &msg) {
+        Ok(sender_id) => Ok(Some(sender_id)),
+        Err(e) => {
+            error!("Key request authentication failed: {}", e);
+            Err(HttpResponse::Unauthorized().body(format!("Sender authentication failed: {}", e)))
+        }
+    }
+}
+
+pub async fn receive_package(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    key_store: web::Data<dyn KeyStore>,
+    settings: web::Data<Settings>,
+    vault: web::Data<ShareVault>,
+    files: web::Data<FileStore>,
+    payload: web::Json<RxPayload>,
+) -> impl Responder {
+    let pdf_id = &payload.pdf_id;
+    let mut sender_id = None;
+
+    // Returns only leave the block, every outcome is recorded below
+    let response = async {
+        let pkg = match payload.pkg.decode() {
+            Ok(pkg) => pkg,
+            Err(e) => {
+                error!("Rejected envelope for {}: {}", pdf_id, e);
+                return HttpResponse::BadRequest().body(e.to_string());
+            }
+        };
+
+        sender_id = match authenticate(&settings, pdf_id, &pkg, WireFormat::Json) {
+            Ok(sender_id) => sender_id,
+            Err(response) => return response,
+        };
+        let tx_received_at = match signed_receipt_time(pdf_id, sender_id.as_deref(), pkg.received_at.as_deref()) {
+            Ok(tx_received_at) => tx_received_at,
+            Err(response) => return response,
+        };
+
+        let (claimed_at, priv_key) = match claim_case_key(&db, key_store.get_ref(), &vault, pdf_id, sender_id.as_deref(), tx_received_at).await {
+            Ok(claimed) => claimed,
+            Err(response) => return response,
+        };
+
+        let response = match priv_key {
+            None => match serde_json::to_vec(&payload.pkg) {
+                Ok(sealed) => {
+                    let receipt = receipt(&req, &settings, pdf_id, sender_id.as_deref(), "json", StatusCode::ACCEPTED);
+                    seal_package(&db, pdf_id, &sealed, &sealed_package_path(pdf_id), receipt).await
+                }
+                Err(e) => {
+                    error!("Failed to serialize package for {}: {}", pdf_id, e);
+                    HttpResponse::InternalServerError().body("Storage Error")
+                }
+            },
+            Some(priv_key) => {
+                let receipt = receipt(&req, &settings, pdf_id, sender_id.as_deref(), "json", StatusCode::OK);
+                accept(&db, &files, pdf_id, &priv_key, &pkg, Unpack::new(WireFormat::Json, &settings), receipt).await
+            }
+        };
+
+        if response.status() == StatusCode::OK {
+            vault.forget(pdf_id);
+        }
+
+        release_on_failure(&db, pdf_id, claimed_at, &response).await;
+        response
+    }
+    .await;
+
+    audit_package(&db, &req, &settings, pdf_id, sender_id, "json", &response).await;
+    response
+}
+
+/// Receives a package in the CBOR envelope, which carries the ciphertext as raw bytes instead of base64.
+pub async fn receive_cbor(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    key_store: web::Data<dyn KeyStore>,
+    settings: web::Data<Settings>,
+    vault: web::Data<ShareVault>,
+    files: web::Data<FileStore>,
+    body: web::Bytes,
+) -> impl Responder {
+    let payload = match ciborium::from_reader::<BinaryRxPayload, _>(body.as_ref()) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Rejected CBOR envelope: {}", e);
+            return HttpResponse::BadRequest().body(format!("Invalid CBOR envelope: {}", e));
+        }
+    };
+    let pdf_id = &payload.pdf_id;
+    let pkg = &payload.pkg;
+    let mut sender_id = None;
+
+    // Returns only leave the block, every outcome is recorded below
+    let response = async {
+        sender_id = match authenticate(&settings, pdf_id, pkg, WireFormat::Cbor) {
+            Ok(sender_id) => sender_id,
+            Err(response) => return response,
+        };
+        let tx_received_at = match signed_receipt_time(pdf_id, sender_id.as_deref(), pkg.received_at.as_deref()) {
+            Ok(tx_received_at) => tx_received_at,
+            Err(response) => return response,
+        };
+
+        let (claimed_at, priv_key) = match claim_case_key(&db, key_store.get_ref(), &vault, pdf_id, sender_id.as_deref(), tx_received_at).await {
+            Ok(claimed) => claimed,
+            Err(response) => return response,
+        };
+
+        let response = match priv_key {
+            None => {
+                let receipt = receipt(&req, &settings, pdf_id, sender_id.as_deref(), "cbor", StatusCode::ACCEPTED);
+                seal_package(&db, pdf_id, &body, &sealed_cbor_path(pdf_id), receipt).await
+            }
+            Some(priv_key) => {
+                let receipt = receipt(&req, &settings, pdf_id, sender_id.as_deref(), "cbor", StatusCode::OK);
+                accept(&db, &files, pdf_id, &priv_key, pkg, Unpack::new(WireFormat::Cbor, &settings), receipt).await
+            }
+        };
+
+        if response.status() == StatusCode::OK {
+            vault.forget(pdf_id);
+        }
+
+        release_on_failure(&db, pdf_id, claimed_at, &response).await;
+        response
+    }
+    .await;
+
+    audit_package(&db, &req, &settings, pdf_id, sender_id, "cbor", &response).await;
+    response
+}
+
+/// The user named by the proxy in front of RX. The header is only believed from an address in
+/// `rx.trusted_proxies`, any other client could set it itself.
+pub(crate) fn proxy_user(req: &HttpRequest, settings: &Settings) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    if !settings.rx.trusted_proxies.iter().any(|proxy| proxy.trim().parse::<IpAddr>() == Ok(peer)) {
+        return None;
+    }
+
+    req.headers()
+        .get(settings.rx.user_header.as_str())
+        .and_then(|user| user.to_str().ok())
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .map(str::to_string)
+}
+
+/// Who is making a request: the user named by a trusted proxy, or else the client address.
+pub(crate) fn requester(req: &HttpRequest, settings: &Settings) -> String {
+    proxy_user(req, settings)
+        .or_else(|| req.peer_addr().map(|peer| peer.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Requires a user authenticated by a trusted proxy, or the admin token. Returns who it is.
+pub(crate) fn require_user(req: &HttpRequest, settings: &Settings) -> Result<String, HttpResponse> {
+    if let Some(user) = proxy_user(req, settings) {
+        return Ok(user);
+    }
+
+    require_admin(req, settings)
+        .map(|()| "admin".to_string())
+        .map_err(|_| HttpResponse::Unauthorized().body("Authentication required"))
+}
+
+/// Checks the `Authorization: Bearer` token against the one in `rx.admin.token_env`. Admin endpoints are
+/// off while no token is configured.
+pub(crate) fn require_admin(req: &HttpRequest, settings: &Settings) -> Result<(), HttpResponse> {
+    let Some(expected) = std::env::var(&settings.rx.admin.token_env).ok().filter(|token| !token.trim().is_empty()) else {
+        return Err(HttpResponse::Forbidden().body("Admin endpoints are disabled, no admin token is configured"));
+    };
+
+    let given = req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    // Comparing digests keeps the time taken unrelated to where the tokens differ
+    if Sha256::digest(given.trim()) == Sha256::digest(expected.trim()) {
+        Ok(())
+    } else {
+        Err(HttpResponse::Unauthorized().body("Admin token required"))
+    }
+}
+
+/// Appends an event to the audit chain. Failures are logged, whether they stop the request is up to the caller.
+pub(crate) async fn audit(db: &Database, event: AuditEvent) -> Result<()> {
+    db.record_event(&event)
+        .await
+        .inspect_err(|e| error!("Failed to record {} event: {}", event.action, e))
+}
+
+/// The `package_received` event of a package RX takes in, written in the same transaction that stores it.
+/// The actor is the authenticated sender, or the client address when the package is unsigned.
+pub(crate) fn receipt(
+    req: &HttpRequest,
+    settings: &Settings,
+    pdf_id: &str,
+    sender_id: Option<&str>,
+    format: &str,
+    status: StatusCode,
+) -> AuditEvent {
+    AuditEvent {
+        action: AUDIT_PACKAGE_RECEIVED.to_string(),
+        actor: sender_id.map_or_else(|| requester(req, settings), str::to_string),
+        case_number: None,
+        pdf_id: Some(pdf_id.to_string()),
+        detail: serde_json::json!({
+            "format": format,
+            "status": status.as_u16(),
+            "authenticated": sender_id.is_some(),
+        }),
+    }
+}
+
+/// Records a package submission that was turned away. Packages taken in were recorded with their receipt,
+/// nothing was stored for the others so a failure to record them is only logged.
+pub(crate) async fn audit_package(
+    db: &Database,
+    req: &HttpRequest,
+    settings: &Settings,
+    pdf_id: &str,
+    sender_id: Option<String>,
+    format: &str,
+    response: &HttpResponse,
+) {
+    if response.status().is_success() {
+        return;
+    }
+
+    let mut event = receipt(req, settings, pdf_id, sender_id.as_deref(), format, response.status());
+    event.case_number = db.case_of_document(pdf_id).await.ok().flatten();
+
+    audit(db, event).await.ok();
+}
+
+/// Checks the envelope version and sender signature of a package, returning the authenticated sender.
+fn authenticate(settings: &Settings, pdf_id: &str, pkg: &BinaryPackage, format: WireFormat) -> Result<Option<String>, HttpResponse> {
+    info!("Received encrypted package for PDF ID: {} (envelope v{}, {}, {:?})", pdf_id, pkg.version, pkg.alg, format);
+
+    // Envelopes before version 3 predate the CBOR envelope, they only ever came as JSON
+    match KeyWrap::for_envelope(pkg.version, &pkg.alg) {
+        Ok(key_wrap) if key_wrap.is_legacy() && (format != WireFormat::Json || !settings.rx.allow_legacy_pkcs1v15) => {
+            error!("Rejected legacy PKCS#1 v1.5 envelope for {}", pdf_id);
+            return Err(HttpResponse::BadRequest().body("Legacy PKCS#1 v1.5 envelopes are not accepted"));
+        }
+        Ok(_) if pkg.version < SIGNED_ENVELOPE_VERSION && format != WireFormat::Json => {
+            error!("Rejected v{} envelope for {} in {:?}", pkg.version, pdf_id, format);
+            return Err(HttpResponse::BadRequest().body("Envelopes older than version 3 are only accepted as JSON"));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Rejected envelope for {}: {}", pdf_id, e);
+            return Err(HttpResponse::BadRequest().body(e.to_string()));
+        }
+    }
+
+    // Envelopes before version 3 predate sender signatures, they are only taken unsigned when explicitly allowed
+    if pkg.version < SIGNED_ENVELOPE_VERSION && pkg.signature.is_none() {
+        if !settings.rx.allow_unsigned_envelopes {
+            error!("Rejected unsigned v{} envelope for {}", pkg.version, pdf_id);
+            return Err(HttpResponse::Unauthorized().body("Unsigned envelopes are not accepted"));
+        }
+
+        warn!("Accepting unsigned v{} envelope for {}", pkg.version, pdf_id);
+        return Ok(None);
+    }
+
+    let msg = signed_message(
+        pdf_id,
+        pkg.version,
+        &pkg.alg,
+        &b64.encode(&pkg.hash),
+        pkg.received_at.as_deref(),
+        pkg.compression.as_deref(),
+    );
+    match Verifier::verify_sender(&settings.rx.trusted_senders, pkg.sender_id.as_deref(), pkg.signature.as_deref(), &msg) {
+        Ok(sender_id) => {
+            debug!("Package for {} signed by trusted sender '{}'", pdf_id, sender_id);
+            Ok(Some(sender_id))
+        }
+        Err(e) => {
+            error!("Sender authentication failed for {}: {}", pdf_id, e);
+            Err(HttpResponse::Unauthorized().body(format!("Sender authentication failed: {}", e)))
+        }
+    }
+}
+
+async fn accept(
+    db: &Database,
+    files: &FileStore,
+    pdf_id: &str,
+    priv_key: &CaseKey,
+    pkg: &BinaryPackage,
+    unpack: Unpack,
+    receipt: AuditEvent,
+) -> HttpResponse {
+    match open_package(db, files, pdf_id, priv_key, pkg, unpack, receipt).await {
+        Ok(()) => {
+            info!("Transmission successful for PDF ID: {}", pdf_id);
+            HttpResponse::Ok().body("Transmission received and verified successfully")
+        }
+        Err(response) => response,
+    }
+}
+
+/// When TX received the upload, from the `receivedAt` of a signed package. Unsigned packages have nothing
+/// vouching for it, so it is left out for them.
+pub(crate) fn signed_receipt_time(
+    pdf_id: &str,
+    sender_id: Option<&str>,
+    received_at: Option<&str>,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, HttpResponse> {
+    let Some(received_at) = received_at.filter(|_| sender_id.is_some()) else {
+        return Ok(None);
+    };
+
+    match chrono::DateTime::parse_from_rfc3339(received_at) {
+        Ok(received_at) => Ok(Some(received_at.to_utc())),
+        Err(e) => {
+            error!("Rejected package for {} with receivedAt '{}': {}", pdf_id, received_at, e);
+            Err(HttpResponse::BadRequest().body("receivedAt must be an RFC 3339 time"))
+        }
+    }
+}
+
+/// Claims the key of a case for an incoming package and returns the time of the claim with the key,
+/// or `None` while it is sealed until the jury quorum. Each key accepts a single package, and only until it expires.
+pub(crate) async fn claim_case_key(
+    db: &Database,
+    key_store: &dyn KeyStore,
+    vault: &ShareVault,
+    pdf_id: &str,
+    sender_id: Option<&str>,
+    tx_received_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<(chrono::NaiveDateTime, Option<CaseKey>), HttpResponse> {
+    let claimed_at = match db.claim_key(pdf_id).await {
+        Ok(KeyClaim::Claimed(claimed_at)) => claimed_at,
+        Ok(KeyClaim::NotFound) => {
+            error!("PDF ID {} not found", pdf_id);
+            return Err(HttpResponse::NotFound().body("PDF ID not found"));
+        }
+        Ok(KeyClaim::Consumed) => {
+            error!("Rejected second package for {}", pdf_id);
+            return Err(HttpResponse::Conflict().body("A package was already received for this PDF ID"));
+        }
+        Ok(KeyClaim::Expired) => {
+            error!("Rejected package for {}, its key has expired", pdf_id);
+            return Err(HttpResponse::Gone().body("The public key for this PDF ID has expired"));
+        }
+        Err(e) => {
+            error!("Failed to claim key for {}: {}", pdf_id, e);
+            return Err(HttpResponse::InternalServerError().body("DB Error"));
+        }
+    };
+
+    match unlock_case_key(db, key_store, vault, pdf_id, sender_id, tx_received_at).await {
+        Ok(key) => Ok((claimed_at, key)),
+        Err(response) => {
+            release_on_failure(db, pdf_id, claimed_at, &response).await;
+            Err(response)
+        }
+    }
+}
+
+async fn unlock_case_key(
+    db: &Database,
+    key_store: &dyn KeyStore,
+    vault: &ShareVault,
+    pdf_id: &str,
+    sender_id: Option<&str>,
+    tx_received_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<Option<CaseKey>, HttpResponse> {
+    if let Some(sender_id) = sender_id {
+        match db.set_sender(pdf_id, sender_id, tx_received_at).await {
+            Ok(true) => {}
+            Ok(false) => {
+                error!("Package for {} is signed by '{}', not by the sender that requested its key", pdf_id, sender_id);
+                return Err(HttpResponse::Forbidden().body("Package is signed by another sender than requested its key"));
+            }
+            Err(e) => {
+                error!("Failed to record sender for {}: {}", pdf_id, e);
+                return Err(HttpResponse::InternalServerError().body("DB Update Error"));
+            }
+        }
+    }
+
+    match key_store.get(pdf_id).await {
+        Ok(StoredKey::Plain(k)) => Ok(Some(k)),
+        Ok(StoredKey::Sealed(sealed_key)) => {
+            let shares = vault.shares(pdf_id);
+
+            if shares.len() < sealed_key.threshold as usize {
+                return Ok(None);
+            }
+
+            match KeySealer::unseal(&sealed_key.sealed_b64, &sealed_key.alg, sealed_key.threshold, &shares) {
+                Ok(k) => Ok(Some(k)),
+                Err(e) => {
+                    error!("Failed to unseal key for {}: {}", pdf_id, e);
+                    Err(HttpResponse::InternalServerError().body("Key Unseal Error"))
+                }
+            }
+        }
+        Err(e) => {
+            error!("Failed to load key for {}: {}", pdf_id, e);
+            Err(HttpResponse::InternalServerError().body("Key Store Error"))
+        }
+    }
+}
+
+/// Lets the sender retry with the same key if the package this request claimed it for could not be stored.
+pub(crate) async fn release_on_failure(db: &Database, pdf_id: &str, claimed_at: chrono::NaiveDateTime, response: &HttpResponse) {
+    if !response.status().is_success()
+        && let Err(e) = db.release_key(pdf_id, claimed_at).await
+    {
+        error!("Failed to release key for {}: {}", pdf_id, e);
+    }
+}
+
+/// Keeps a package whose key is still sealed until enough jurors submit their shares.
+async fn seal_package(db: &Database, pdf_id: &str, pkg_bytes: &[u8], sealed_path: &Path, receipt: AuditEvent) -> HttpResponse {
+    let out_dir = PathBuf::from("./out");
+    if let Err(e) = fs::create_dir_all(&out_dir).await {
+        error!("Failed to create output directory: {}", e);
+        return HttpResponse::InternalServerError().body("Storage Error");
+    }
+
+    if let Err(e) = fs::write(sealed_path, pkg_bytes).await {
+        error!("Failed to write sealed package: {}", e);
+        return HttpResponse::InternalServerError().body("Storage Error");
+    }
+
+    if let Err(e) = db.mark_sealed(pdf_id, receipt).await {
+        error!("Failed to update DB record: {}", e);
+        return HttpResponse::InternalServerError().body("DB Update Error");
+    }
+
+    info!("Package for PDF ID {} sealed until the jury quorum is reached", pdf_id);
+    HttpResponse::Accepted().body("Transmission received, sealed until the jury quorum is reached")
+}
+
+/// How the plaintext of a package is read: the format of its envelope, and how far it may decompress.
+#[derive(Clone, Copy)]
+pub(crate) struct Unpack {
+    pub format: WireFormat,
+    pub max_len: usize,
+}
+
+impl Unpack {
+    pub fn new(format: WireFormat, settings: &Settings) -> Self {
+        Self { format, max_len: settings.rx.max_package_bytes }
+    }
+}
+
+/// Decrypts and verifies a package, then stores its PDF and marks the case as received, recording `receipt` with it.
+pub(crate) async fn open_package(
+    db: &Database,
+    files: &FileStore,
+    pdf_id: &str,
+    priv_key: &CaseKey,
+    pkg: &BinaryPackage,
+    unpack: Unpack,
+    receipt: AuditEvent,
+) -> Result<(), HttpResponse> {
+    let key_wrap = match KeyWrap::for_envelope(pkg.version, &pkg.alg) {
+        Ok(key_wrap) => key_wrap,
+        Err(e) => return Err(HttpResponse::BadRequest().body(e.to_string())),
+    };
+
+    let aad = associated_data(pdf_id, pkg.version, &pkg.alg, pkg.compression.as_deref());
+
+    let plaintext_bytes = match Decrypter::decrypt_hybrid(priv_key, key_wrap, pkg, &aad, unpack.max_len) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            // Keep the cause out of the response so it can't be used as a decryption oracle
+            error!("Decryption failed for {}: {}", pdf_id, e);
+            return Err(HttpResponse::BadRequest().body("Decryption failed"));
+        }
+    };
+
+    if Decrypter::verify_hash(&plaintext_bytes, &pkg.hash) {
+        debug!("Hash verification successful for {}", pdf_id);
+    } else {
+        error!("Hash verification failed for {}", pdf_id);
+        return Err(HttpResponse::BadRequest().body("Integrity check failed (Hash mismatch)"));
+    }
+
+    let pdf_data = match unpack.format.decode_pdf(&plaintext_bytes) {
+        Ok(data) => {
+            debug!("Decrypted Data - Title: {:?}, Author: {:?}, Pages: {:?}", data.info.title, data.info.author, data.info.page_count);
+            data
+        }
+        Err(e) => {
+            error!("Failed to deserialize PDF Data: {}", e);
+            return Err(HttpResponse::BadRequest().body("Invalid PDF payload"));
+        }
+    };
+
+    let (file_path, file_key) = match files.write(pdf_id, &pdf_data.file).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("Failed to write PDF file: {}", e);
+            return Err(HttpResponse::InternalServerError().body("Storage Error"));
+        }
+    };
+
+    let PdfData { info, file } = pdf_data;
+    let file_size = file.len() as i64;
+    let sha256 = format!("{:x}", Sha256::digest(&file));
+    let metadata = DocumentMetadata {
+        info,
+        file_size,
+        sha256,
+        package_hash: b64.encode(&pkg.hash),
+        content: TextExtractor::searchable_text(pdf_id, file).await,
+    };
+
+    record_stored(db, pdf_id, &file_path, &file_key, &metadata, receipt).await
+}
+
+/// Records a document written to `file_path`. When another request stored it first, the copy just written is
+/// removed again and the request is answered with 409.
+pub(crate) async fn record_stored(
+    db: &Database,
+    pdf_id: &str,
+    file_path: &Path,
+    file_key: &str,
+    metadata: &DocumentMetadata,
+    receipt: AuditEvent,
+) -> Result<(), HttpResponse> {
+    match db.update_file_path(pdf_id, &file_path.to_string_lossy(), file_key, metadata, receipt).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            warn!("Package for {} was already opened by another request", pdf_id);
+            if let Err(e) = fs::remove_file(file_path).await {
+                error!("Failed to remove duplicate file of {}: {}", pdf_id, e);
+            }
+            Err(HttpResponse::Conflict().body("Package was already opened"))
+        }
+        Err(e) => {
+            error!("Failed to update DB record: {}", e);
+            Err(HttpResponse::InternalServerError().body("DB Update Error"))
+        }
+    }
+}
+
+/// Opens a case ahead of its documents, or fills in its court and parties and changes its status afterwards.
+pub async fn register_case(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    settings: web::Data<Settings>,
+    case: web::Json<CaseRegistration>,
+) -> impl Responder {
+    let registered_by = match require_user(&req, &settings) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    if case.case_number.trim().is_empty() {
+        return HttpResponse::BadRequest().body("Case number is required");
+    }
+
+    let event = AuditEvent {
+        action: AUDIT_CASE_REGISTERED.to_string(),
+        actor: registered_by,
+        case_number: Some(case.case_number.clone()),
+        pdf_id: None,
+        detail: serde_json::json!({
+            "court": case.court,
+            "parties": case.parties,
+            "status": case.status,
+        }),
+    };
+
+    match db.register_case(&case, event).await {
+        Ok(CaseRegistered::Opened) => {
+            info!("Case {} registered", case.case_number);
+            HttpResponse::Created().body("Case registered")
+        }
+        Ok(CaseRegistered::Updated) => HttpResponse::Ok().body("Case updated"),
+        Ok(CaseRegistered::Conflict) => HttpResponse::Conflict().body("Case already has another court or parties"),
+        Err(e) => {
+            error!("Failed to register case {}: {}", case.case_number, e);
+            HttpResponse::InternalServerError().body("DB Error")
+        }
+    }
+}
+
+/// Lists cases a page at a time, `limit` per page (50 by default, at most 200).
+pub async fn list_cases(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    settings: web::Data<Settings>,
+    filter: web::Query<CaseFilter>,
+) -> impl Responder {
+    let after = match filter.cursor.as_deref().map(CaseCursor::decode).transpose() {
+        Ok(Some(cursor)) if cursor.sort != filter.sort || cursor.order != filter.order => {
+            return HttpResponse::BadRequest().body("Cursor belongs to another sort or order");
+        }
+        Ok(after) => after,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid cursor"),
+    };
+
+    let limit = filter.limit.unwrap_or(50).clamp(1, 200);
+
+    let page: CasePage = match db.list_cases(&filter, after.as_ref(), limit).await {
+        Ok(page) => page,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    audit(&db, AuditEvent {
+        action: AUDIT_CASES_LISTED.to_string(),
+        actor: requester(&req, &settings),
+        case_number: None,
+        pdf_id: None,
+        detail: serde_json::json!({
+            "author": filter.author,
+            "keyword": filter.keyword,
+            "status": filter.status,
+            "from": filter.from,
+            "to": filter.to,
+            "cursor": filter.cursor,
+            "cases": page.cases.iter().map(|case| &case.case_number).collect::<Vec<_>>(),
+        }),
+    }).await.ok();
+
+    HttpResponse::Ok().json(page)
+}
+
+/// Ranked full-text search over the received documents of every case. The snippets quote the documents,
+/// so searching is recorded like a download, with the cases that were hit.
+pub async fn search_cases(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    settings: web::Data<Settings>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let searched_by = match require_user(&req, &settings) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let q = query.q.trim();
+    if q.is_empty() {
+        return HttpResponse::BadRequest().body("Search query is empty");
+    }
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+
+    let hits = match db.search_documents(q, limit).await {
+        Ok(hits) => hits,
+        Err(e) => {
+            error!("Search for {:?} failed: {}", q, e);
+            return HttpResponse::InternalServerError().body("DB Error");
+        }
+    };
+
+    let recorded = audit(&db, AuditEvent {
+        action: AUDIT_CASES_SEARCHED.to_string(),
+        actor: searched_by,
+        case_number: None,
+        pdf_id: None,
+        detail: serde_json::json!({
+            "q": q,
+            "limit": limit,
+            "cases": hits.iter().map(|hit| &hit.case_number).collect::<BTreeSet<_>>(),
+        }),
+    }).await;
+    if recorded.is_err() {
+        return HttpResponse::InternalServerError().body("Audit Error");
+    }
+
+    HttpResponse::Ok().json(hits)
+}
+
+/// Downloads every received document of a case, the PDF itself for a single-document case, a ZIP otherwise.
+pub async fn download_case(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    files: web::Data<FileStore>,
+    settings: web::Data<Settings>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let case_number = path.into_inner();
+    let stamp = download_stamp(&req, &settings, &case_number);
+
+    let documents = match db.case_documents(&case_number).await {
+        Ok(Some(documents)) => documents,
+        Ok(None) => return HttpResponse::NotFound().body("Case not found"),
+        Err(e) => {
+            error!("Failed to fetch documents of case {}: {}", case_number, e);
+            return HttpResponse::InternalServerError().body("DB Error");
+        }
+    };
+
+    let filed = documents.len();
+    let received: Vec<(String, StoredFile)> = documents
+        .into_iter()
+        .filter_map(|document| document.file.map(|file| (document.pdf_id, file)))
+        .collect();
+
+    if received.is_empty() {
+        return HttpResponse::Conflict().body("Case document not available yet (awaiting package or jury quorum)");
+    }
+
+    // A download that can't be accounted for isn't handed out
+    let recorded = audit(&db, AuditEvent {
+        action: AUDIT_CASE_DOWNLOADED.to_string(),
+        actor: requester(&req, &settings),
+        case_number: Some(case_number.clone()),
+        pdf_id: None,
+        detail: serde_json::json!({
+            "documents": received.iter().map(|(pdf_id, _)| pdf_id).collect::<Vec<_>>(),
+            "watermarked": stamp.is_some(),
+        }),
+    }).await;
+    if recorded.is_err() {
+        return HttpResponse::InternalServerError().body("Audit Error");
+    }
+
+    if filed == 1 {
+        let (pdf_id, stored) = &received[0];
+        return match read_document(&files, pdf_id, stored, stamp.as_ref()).await {
+            Ok(bytes) => pdf_attachment(&case_number, bytes),
+            Err(response) => response,
+        };
+    }
+
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    for (pdf_id, stored) in &received {
+        let bytes = match read_document(&files, pdf_id, stored, stamp.as_ref()).await {
+            Ok(bytes) => bytes,
+            Err(response) => return response,
+        };
+
+        let written = archive
+            .start_file(format!("{}.pdf", pdf_id), options)
+            .and_then(|()| archive.write_all(&bytes).map_err(zip::result::ZipError::from));
+        if let Err(e) = written {
+            error!("Failed to archive {} for case {}: {}", pdf_id, case_number, e);
+            return HttpResponse::InternalServerError().body("Storage Error");
+        }
+    }
+
+    let bytes = match archive.finish() {
+        Ok(cursor) => cursor.into_inner(),
+        Err(e) => {
+            error!("Failed to archive case {}: {}", case_number, e);
+            return HttpResponse::InternalServerError().body("Storage Error");
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/zip")
+        .append_header(("Content-Disposition", format!("attachment; filename=\"{}.zip\"", case_number)))
+        .body(bytes)
+}
+
+pub async fn download_document(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    files: web::Data<FileStore>,
+    settings: web::Data<Settings>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (case_number, pdf_id) = path.into_inner();
+    let stamp = download_stamp(&req, &settings, &case_number);
+
+    match db.case_of_document(&pdf_id).await {
+        Ok(Some(filed_under)) if filed_under == case_number => {},
+        Ok(_) => return HttpResponse::NotFound().body("Document not found in case"),
+        Err(e) => {
+            error!("Failed to look up document {}: {}", pdf_id, e);
+            return HttpResponse::InternalServerError().body("DB Error");
+        }
+    }
+
+    let stored = match db.get_case_file(&pdf_id).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return HttpResponse::Conflict().body("Case document not available yet (awaiting package or jury quorum)"),
+        Err(_) => return HttpResponse::NotFound().body("Case not found"),
+    };
+
+    let recorded = audit(&db, AuditEvent {
+        action: AUDIT_DOCUMENT_DOWNLOADED.to_string(),
+        actor: requester(&req, &settings),
+        case_number: Some(case_number.clone()),
+        pdf_id: Some(pdf_id.clone()),
+        detail: serde_json::json!({ "watermarked": stamp.is_some() }),
+    }).await;
+    if recorded.is_err() {
+        return HttpResponse::InternalServerError().body("Audit Error");
+    }
+
+    match read_document(&files, &pdf_id, &stored, stamp.as_ref()).await {
+        Ok(bytes) => pdf_attachment(&pdf_id, bytes),
+        Err(response) => response,
+    }
+}
+
+/// What downloads of the case are stamped with, `None` when watermarking is off. Only a user named by a
+/// trusted proxy is stamped as such, anyone else by their address.
+fn download_stamp(req: &HttpRequest, settings: &Settings, case_number: &str) -> Option<Stamp> {
+    if !settings.rx.watermark.enabled {
+        return None;
+    }
+
+    Some(Stamp {
+        case_number: case_number.to_string(),
+        requested_by: proxy_user(req, settings)
+            .unwrap_or_else(|| format!("unauthenticated client {}", requester(req, settings))),
+        at: chrono::Utc::now(),
+    })
+}
+
+/// Reads a stored PDF, stamped when a stamp is given. A PDF that can't be stamped isn't handed out.
+async fn read_document(files: &FileStore, pdf_id: &str, stored: &StoredFile, stamp: Option<&Stamp>) -> Result<Vec<u8>, HttpResponse> {
+    let file_path = PathBuf::from(&stored.path);
+    if !file_path.exists() {
+        return Err(HttpResponse::NotFound().body("PDF not found"));
+    }
+
+    let bytes = files.read(pdf_id, &file_path, stored.file_key.as_deref()).await.map_err(|e| {
+        error!("Failed to read stored PDF for {}: {}", pdf_id, e);
+        HttpResponse::InternalServerError().body("Storage Error")
+    })?;
+
+    let Some(stamp) = stamp.cloned() else {
+        return Ok(bytes);
+    };
+
+    let stamped = tokio::task::spawn_blocking(move || Watermark::apply(&bytes, &stamp)).await
+        .map_err(anyhow::Error::from)
+        .and_then(|stamped| stamped);
+
+    stamped.map_err(|e| {
+        error!("Failed to watermark PDF {}: {}", pdf_id, e);
+        HttpResponse::InternalServerError().body("Watermark Error")
+    })
+}
+
+fn pdf_attachment(name: &str, bytes: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/pdf")
+        .append_header(("Content-Disposition", format!("attachment; filename=\"{}.pdf\"", name)))
+        .body(bytes)
+}
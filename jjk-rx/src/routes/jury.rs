@@ -0,0 +1,235 @@
+use crate::prelude::*;
+use crate::settings::{JurySettings, Settings};
+use crate::storage::{CaseIssue, Database, FileStore, KeyIssue, KeyStore, StoredKey, SealedKey, IssuedShare};
+use crate::jury::{KeySealer, ShareVault};
+use crate::encryption::CaseKey;
+use crate::domain::{AuditEvent, AUDIT_PACKAGE_OPENED, JurorRegistration, ShareSubmission, ShareStatus, EncryptedPackage, BinaryRxPayload, WireFormat};
+use super::handlers::{Unpack, open_package, proxy_user, put_issued_key, require_admin, requester, sealed_package_path, sealed_cbor_path};
+use super::stream::{open_sealed_stream, sealed_stream_path};
+use actix_web::HttpRequest;
+use actix_web::http::StatusCode;
+use rsa::pkcs8::DecodePublicKey;
+use std::path::Path;
+use tokio::fs;
+
+/// Seals a freshly generated case key and issues one encrypted share to every registered juror.
+pub(crate) async fn seal_for_jury(
+    db: &Database,
+    key_store: &dyn KeyStore,
+    jury: &JurySettings,
+    mut key_issue: KeyIssue,
+    issue: &CaseIssue<'_>,
+    priv_key: &CaseKey,
+) -> Result<()> {
+    let jurors = db.list_jurors().await?;
+
+    if jurors.len() < jury.threshold as usize {
+        return Err(anyhow!(
+            "Only {} jurors registered, {} are needed to seal a key",
+            jurors.len(), jury.threshold
+        ));
+    }
+
+    let (sealed_key_b64, shares) = KeySealer::seal(priv_key, jury.threshold, jurors.len())?;
+
+    let mut issued = Vec::with_capacity(jurors.len());
+    for (juror, share) in jurors.iter().zip(shares) {
+        issued.push(IssuedShare {
+            juror_id: juror.id,
+            encrypted_share_b64: KeySealer::encrypt_share(&juror.public_key, &share)?,
+            share_hash: KeySealer::share_hash(&share),
+        });
+    }
+
+    let sealed_key = SealedKey {
+        sealed_b64: sealed_key_b64,
+        alg: priv_key.alg().to_string(),
+        threshold: jury.threshold,
+    };
+
+    key_issue.insert_case(issue, &sealed_key.alg, Some(sealed_key.threshold), issued).await?;
+    key_issue.commit().await?;
+    put_issued_key(db, key_store, issue.pdf_id, &StoredKey::Sealed(sealed_key)).await?;
+
+    debug!("Key for PDF ID {} sealed among {} jurors", issue.pdf_id, jurors.len());
+    Ok(())
+}
+
+/// Registers a juror, with the admin token. Every juror registered gets a share of the cases sealed after it.
+pub async fn register_juror(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    settings: web::Data<Settings>,
+    payload: web::Json<JurorRegistration>,
+) -> impl Responder {
+    if let Err(response) = require_admin(&req, &settings) {
+        warn!("Refused juror registration from {}", requester(&req, &settings));
+        return response;
+    }
+
+    let registration = payload.into_inner();
+
+    if registration.name.trim().is_empty() {
+        return HttpResponse::BadRequest().body("Juror name is required");
+    }
+
+    if RsaPublicKey::from_public_key_pem(&registration.public_key).is_err() {
+        return HttpResponse::BadRequest().body("Public key must be an RSA SPKI PEM");
+    }
+
+    match db.register_juror(&registration.name, &registration.public_key).await {
+        Ok(true) => {
+            info!("Registered juror '{}'", registration.name);
+            HttpResponse::Created().finish()
+        }
+        Ok(false) => HttpResponse::Conflict().body("Juror already registered"),
+        Err(e) => {
+            error!("Failed to register juror: {}", e);
+            HttpResponse::InternalServerError().body("DB Error")
+        }
+    }
+}
+
+/// Shares still awaited from a juror, for the juror named by a trusted proxy or the admin.
+pub async fn pending_shares(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    settings: web::Data<Settings>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let juror = path.into_inner();
+
+    if proxy_user(&req, &settings).as_deref() != Some(juror.as_str()) && require_admin(&req, &settings).is_err() {
+        warn!("Refused shares of juror '{}' to {}", juror, requester(&req, &settings));
+        return HttpResponse::Unauthorized().body("Juror or admin authentication required");
+    }
+
+    match db.pending_shares(&juror).await {
+        Ok(shares) => HttpResponse::Ok().json(shares),
+        Err(e) => {
+            error!("Failed to fetch shares of juror '{}': {}", juror, e);
+            HttpResponse::InternalServerError().body("DB Error")
+        }
+    }
+}
+
+pub async fn submit_share(
+    db: web::Data<Database>,
+    key_store: web::Data<dyn KeyStore>,
+    vault: web::Data<ShareVault>,
+    files: web::Data<FileStore>,
+    settings: web::Data<Settings>,
+    payload: web::Json<ShareSubmission>,
+) -> impl Responder {
+    let submission = payload.into_inner();
+    let pdf_id = &submission.case_code;
+
+    let sealed_key = match key_store.get(pdf_id).await {
+        Ok(StoredKey::Sealed(sealed_key)) => sealed_key,
+        Ok(StoredKey::Plain(_)) => return HttpResponse::Conflict().body("Case key is not sealed"),
+        Err(_) => return HttpResponse::NotFound().body("Case not found"),
+    };
+
+    let (juror_id, share_hash) = match db.get_issued_share(pdf_id, &submission.juror).await {
+        Ok(issued) => issued,
+        Err(_) => return HttpResponse::NotFound().body("No share was issued to this juror for the case"),
+    };
+
+    let share = match b64.decode(&submission.share_b64) {
+        Ok(share) => share,
+        Err(_) => return HttpResponse::BadRequest().body("Share must be base64 encoded"),
+    };
+
+    if KeySealer::share_hash(&share) != share_hash {
+        error!("Juror '{}' submitted a share that was not issued for {}", submission.juror, pdf_id);
+        return HttpResponse::BadRequest().body("Share does not match the one issued to this juror");
+    }
+
+    let submitted = vault.submit(pdf_id, juror_id, share);
+    info!("Juror '{}' submitted share {}/{} for PDF ID {}", submission.juror, submitted, sealed_key.threshold, pdf_id);
+
+    let quorum_reached = submitted >= sealed_key.threshold as usize;
+
+    // Open the package right away if it already arrived, otherwise `receive_package` will
+    let sealed_path = [sealed_package_path(pdf_id), sealed_cbor_path(pdf_id), sealed_stream_path(pdf_id)]
+        .into_iter()
+        .find(|path| path.exists());
+    if let Some(sealed_path) = sealed_path.filter(|_| quorum_reached) {
+        let priv_key = match KeySealer::unseal(&sealed_key.sealed_b64, &sealed_key.alg, sealed_key.threshold, &vault.shares(pdf_id)) {
+            Ok(k) => k,
+            Err(e) => {
+                error!("Failed to unseal key for {}: {}", pdf_id, e);
+                return HttpResponse::InternalServerError().body("Key Unseal Error");
+            }
+        };
+
+        // Opening the package is recorded along with storing it, on behalf of the juror completing the quorum
+        let opened = AuditEvent {
+            action: AUDIT_PACKAGE_OPENED.to_string(),
+            actor: submission.juror.clone(),
+            case_number: None,
+            pdf_id: Some(pdf_id.clone()),
+            detail: serde_json::json!({ "shares": submitted, "threshold": sealed_key.threshold }),
+        };
+
+        match open_sealed(&db, &files, pdf_id, &priv_key, &sealed_path, opened, &settings).await {
+            Ok(()) => {
+                if let Err(e) = fs::remove_file(&sealed_path).await {
+                    error!("Failed to remove sealed package for {}: {}", pdf_id, e);
+                }
+
+                vault.forget(pdf_id);
+                info!("Jury quorum reached, package opened for PDF ID: {}", pdf_id);
+            }
+            // Another juror's share completed the quorum at the same time and opened it
+            Err(response) if response.status() == StatusCode::CONFLICT => {}
+            Err(response) => return response,
+        }
+    }
+
+    HttpResponse::Ok().json(ShareStatus {
+        case_code: pdf_id.clone(),
+        submitted,
+        threshold: sealed_key.threshold as i16,
+        quorum_reached,
+    })
+}
+
+/// Opens a package or stream that was kept sealed, whichever format it arrived in.
+async fn open_sealed(
+    db: &Database,
+    files: &FileStore,
+    pdf_id: &str,
+    priv_key: &CaseKey,
+    sealed_path: &Path,
+    opened: AuditEvent,
+    settings: &Settings,
+) -> Result<(), HttpResponse> {
+    if sealed_path == sealed_stream_path(pdf_id) {
+        return open_sealed_stream(db, files, pdf_id, priv_key, opened, settings.rx.max_extract_bytes).await;
+    }
+
+    let format = if sealed_path == sealed_cbor_path(pdf_id) {
+        WireFormat::Cbor
+    } else {
+        WireFormat::Json
+    };
+
+    let pkg = match fs::read(sealed_path).await
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| match format {
+            WireFormat::Json => serde_json::from_slice::<EncryptedPackage>(&bytes)?.decode(),
+            WireFormat::Cbor => ciborium::from_reader::<BinaryRxPayload, _>(bytes.as_slice())
+                .map(|payload| payload.pkg)
+                .map_err(|e| anyhow!("{}", e)),
+        })
+    {
+        Ok(pkg) => pkg,
+        Err(e) => {
+            error!("Failed to load sealed package for {}: {}", pdf_id, e);
+            return Err(HttpResponse::InternalServerError().body("Storage Error"));
+        }
+    };
+
+    open_package(db, files, pdf_id, priv_key, &pkg, Unpack::new(format, settings), opened).await
+}
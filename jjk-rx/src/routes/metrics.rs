@@ -0,0 +1,20 @@
+use crate::prelude::*;
+use crate::encryption::KeyPool;
+
+/// Prometheus text exposition of RX's gauges.
+pub async fn metrics(key_pool: web::Data<KeyPool>) -> impl Responder {
+    match key_pool.depth().await {
+        Ok(depth) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(format!(
+                "# HELP jjk_rx_key_pool_depth Pre-generated RSA key pairs ready to be issued.\n\
+                 # TYPE jjk_rx_key_pool_depth gauge\n\
+                 jjk_rx_key_pool_depth {}\n",
+                depth
+            )),
+        Err(e) => {
+            error!("Failed to read key pool depth: {}", e);
+            HttpResponse::InternalServerError().body("DB Error")
+        }
+    }
+}
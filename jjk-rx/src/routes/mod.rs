@@ -1 +1,5 @@
-pub mod handlers;
+pub mod audit;
+pub mod handlers;
+pub mod jury;
+pub mod metrics;
+pub mod stream;
@@ -0,0 +1,311 @@
+use crate::prelude::*;
+use crate::settings::Settings;
+use crate::storage::{Database, FileStore, KeyStore};
+use crate::encryption::{CaseKey, KeyWrap, StreamOpener, Verifier, read_header};
+use crate::jury::ShareVault;
+use crate::pdf::TextExtractor;
+use crate::domain::{AuditEvent, DocumentMetadata, StreamHeader, PdfInfo, SIGNED_ENVELOPE_VERSION, signed_message};
+use super::handlers::{audit_package, claim_case_key, receipt, record_stored, release_on_failure, signed_receipt_time};
+use actix_web::HttpRequest;
+use actix_web::http::StatusCode;
+use futures_util::{Stream, TryStreamExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWriteExt, BufReader};
+use tokio_util::io::StreamReader;
+
+/// Where a stream waiting for the jury quorum is kept, still encrypted, until its key can be rebuilt.
+pub(crate) fn sealed_stream_path(pdf_id: &str) -> PathBuf {
+    PathBuf::from("./out").join(format!("{}.pkg.stream", pdf_id))
+}
+
+/// Receives a package in the chunked stream format, decrypting and storing it as it arrives.
+pub async fn receive_stream(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    key_store: web::Data<dyn KeyStore>,
+    settings: web::Data<Settings>,
+    vault: web::Data<ShareVault>,
+    files: web::Data<FileStore>,
+    payload: web::Payload,
+) -> impl Responder {
+    let too_large = Arc::new(AtomicBool::new(false));
+    let mut reader = StreamReader::new(limited(payload, settings.rx.max_stream_bytes, too_large.clone()));
+
+    let (header, raw_header) = match read_header(&mut reader).await {
+        Ok(header) => header,
+        Err(_) if too_large.load(Ordering::Relaxed) => return stream_too_large(&settings),
+        Err(e) => {
+            error!("Rejected stream: {}", e);
+            return HttpResponse::BadRequest().body(e.to_string());
+        }
+    };
+    let pdf_id = &header.pdf_id;
+
+    info!("Receiving stream for PDF ID: {} (envelope v{}, {})", pdf_id, header.version, header.alg);
+    let mut verified_sender = None;
+
+    // Returns only leave the block, every outcome is recorded below
+    let response = async {
+        // Streams were introduced with version 3 envelopes, there is no older form to accept
+        match KeyWrap::for_envelope(header.version, &header.alg) {
+            Ok(_) if header.version >= SIGNED_ENVELOPE_VERSION => {},
+            Ok(_) => return HttpResponse::BadRequest().body("Streams must use envelope version 3 or later"),
+            Err(e) => {
+                error!("Rejected stream for {}: {}", pdf_id, e);
+                return HttpResponse::BadRequest().body(e.to_string());
+            }
+        }
+
+        let msg = signed_message(pdf_id, header.version, &header.alg, &header.hash_b64, header.received_at.as_deref(), None);
+        let Ok(signature) = b64.decode(&header.signature_b64) else {
+            error!("Rejected stream for {}, its signature is not valid base64", pdf_id);
+            return HttpResponse::BadRequest().body("Stream signature is not valid base64");
+        };
+        let sender_id = match Verifier::verify_sender(&settings.rx.trusted_senders, Some(&header.sender_id), Some(&signature), &msg) {
+            Ok(sender_id) => sender_id,
+            Err(e) => {
+                error!("Sender authentication failed for {}: {}", pdf_id, e);
+                return HttpResponse::Unauthorized().body(format!("Sender authentication failed: {}", e));
+            }
+        };
+        verified_sender = Some(sender_id.clone());
+
+        let tx_received_at = match signed_receipt_time(pdf_id, Some(&sender_id), header.received_at.as_deref()) {
+            Ok(tx_received_at) => tx_received_at,
+            Err(response) => return response,
+        };
+
+        let (claimed_at, priv_key) = match claim_case_key(&db, key_store.get_ref(), &vault, pdf_id, Some(&sender_id), tx_received_at).await {
+            Ok(claimed) => claimed,
+            Err(response) => return response,
+        };
+
+        let response = match priv_key {
+            None => {
+                let receipt = receipt(&req, &settings, pdf_id, Some(&sender_id), "stream", StatusCode::ACCEPTED);
+                seal_stream(&db, pdf_id, &raw_header, &mut reader, receipt).await
+            }
+            Some(priv_key) => {
+                let receipt = receipt(&req, &settings, pdf_id, Some(&sender_id), "stream", StatusCode::OK);
+                match open_stream(&db, &files, &priv_key, &header, reader, receipt, settings.rx.max_extract_bytes).await {
+                    Ok(()) => {
+                        vault.forget(pdf_id);
+                        info!("Stream received successfully for PDF ID: {}", pdf_id);
+                        HttpResponse::Ok().body("Transmission received and verified successfully")
+                    }
+                    Err(response) => response,
+                }
+            }
+        };
+
+        // Whatever failed once the body went over the limit, that is the reason to report
+        let response = if too_large.load(Ordering::Relaxed) { stream_too_large(&settings) } else { response };
+
+        release_on_failure(&db, pdf_id, claimed_at, &response).await;
+        response
+    }
+    .await;
+
+    audit_package(&db, &req, &settings, pdf_id, verified_sender, "stream", &response).await;
+    response
+}
+
+/// Fails the request body once more than `limit` bytes have arrived, flagging `too_large`.
+fn limited(payload: web::Payload, limit: u64, too_large: Arc<AtomicBool>) -> impl Stream<Item = std::io::Result<web::Bytes>> {
+    let mut received = 0u64;
+
+    payload
+        .map_err(std::io::Error::other)
+        .and_then(move |chunk| {
+            received += chunk.len() as u64;
+            let chunk = if received > limit {
+                too_large.store(true, Ordering::Relaxed);
+                Err(std::io::Error::other("Stream exceeds the size limit"))
+            } else {
+                Ok(chunk)
+            };
+            futures_util::future::ready(chunk)
+        })
+}
+
+fn stream_too_large(settings: &Settings) -> HttpResponse {
+    HttpResponse::PayloadTooLarge().body(format!("Streams are limited to {} bytes", settings.rx.max_stream_bytes))
+}
+
+/// Keeps a stream whose key is still sealed, exactly as received, until enough jurors submit their shares.
+async fn seal_stream<R: AsyncRead + Unpin>(db: &Database, pdf_id: &str, raw_header: &[u8], reader: &mut R, receipt: AuditEvent) -> HttpResponse {
+    let stored = async {
+        fs::create_dir_all("./out").await?;
+
+        let path = sealed_stream_path(pdf_id);
+        let mut file = fs::File::create(&path).await?;
+        file.write_u32(raw_header.len() as u32).await?;
+        file.write_all(raw_header).await?;
+
+        if let Err(e) = tokio::io::copy(reader, &mut file).await {
+            drop(file);
+            fs::remove_file(&path).await?;
+            return Err(anyhow!(e));
+        }
+
+        file.sync_all().await?;
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    if let Err(e) = stored {
+        error!("Failed to write sealed stream for {}: {}", pdf_id, e);
+        return HttpResponse::InternalServerError().body("Storage Error");
+    }
+
+    if let Err(e) = db.mark_sealed(pdf_id, receipt).await {
+        error!("Failed to update DB record: {}", e);
+        return HttpResponse::InternalServerError().body("DB Update Error");
+    }
+
+    info!("Stream for PDF ID {} sealed until the jury quorum is reached", pdf_id);
+    HttpResponse::Accepted().body("Transmission received, sealed until the jury quorum is reached")
+}
+
+/// Decrypts a stream chunk by chunk straight into the file store, then marks the case as received, recording
+/// `receipt` with it. Nothing is kept if any chunk fails to authenticate or the hash doesn't match.
+/// Text is only extracted from documents up to `max_extract_bytes`.
+pub(crate) async fn open_stream<R: AsyncRead + Unpin>(
+    db: &Database,
+    files: &FileStore,
+    priv_key: &CaseKey,
+    header: &StreamHeader,
+    reader: R,
+    receipt: AuditEvent,
+    max_extract_bytes: u64,
+) -> Result<(), HttpResponse> {
+    let pdf_id = &header.pdf_id;
+
+    let opened = KeyWrap::for_envelope(header.version, &header.alg)
+        .and_then(|key_wrap| StreamOpener::new(priv_key, key_wrap, header, reader));
+    let mut opener = match opened {
+        Ok(opener) => opener,
+        Err(e) => {
+            // Keep the cause out of the response so it can't be used as a decryption oracle
+            error!("Decryption failed for {}: {}", pdf_id, e);
+            return Err(HttpResponse::BadRequest().body("Decryption failed"));
+        }
+    };
+
+    let info = match opener.next_chunk().await {
+        Ok(Some(chunk)) => serde_json::from_slice::<PdfInfo>(&chunk).ok(),
+        Ok(None) => None,
+        Err(e) => {
+            error!("Decryption failed for {}: {}", pdf_id, e);
+            return Err(HttpResponse::BadRequest().body("Decryption failed"));
+        }
+    };
+    let info = match info {
+        Some(info) => {
+            debug!("Decrypted Data - Title: {:?}, Author: {:?}, Pages: {:?}", info.title, info.author, info.page_count);
+            info
+        }
+        None => {
+            error!("Stream for {} does not start with the document metadata", pdf_id);
+            return Err(HttpResponse::BadRequest().body("Invalid PDF payload"));
+        }
+    };
+
+    let mut writer = match files.create(pdf_id).await {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!("Failed to create PDF file: {}", e);
+            return Err(HttpResponse::InternalServerError().body("Storage Error"));
+        }
+    };
+
+    let mut hasher = Sha256::new();
+    let mut file_size = 0;
+
+    loop {
+        let chunk = match opener.next_chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                writer.abort().await;
+                error!("Decryption failed for {}: {}", pdf_id, e);
+                return Err(HttpResponse::BadRequest().body("Decryption failed"));
+            }
+        };
+
+        if let Err(e) = writer.write(&chunk).await {
+            writer.abort().await;
+            error!("Failed to write PDF file: {}", e);
+            return Err(HttpResponse::InternalServerError().body("Storage Error"));
+        }
+
+        hasher.update(&chunk);
+        file_size += chunk.len() as i64;
+    }
+
+    let (file_path, file_key) = match writer.finish().await {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("Failed to write PDF file: {}", e);
+            return Err(HttpResponse::InternalServerError().body("Storage Error"));
+        }
+    };
+
+    // The text is read back from the stored file, lopdf needs the whole document at hand
+    let content = if file_size as u64 > max_extract_bytes {
+        info!("Skipping text extraction for {}, {} bytes is over the limit", pdf_id, file_size);
+        None
+    } else {
+        match files.read(pdf_id, &file_path, Some(&file_key)).await {
+            Ok(pdf) => TextExtractor::searchable_text(pdf_id, pdf).await,
+            Err(e) => {
+                warn!("Failed to read back {} for text extraction: {}", pdf_id, e);
+                None
+            }
+        }
+    };
+
+    let metadata = DocumentMetadata {
+        info,
+        file_size,
+        sha256: format!("{:x}", hasher.finalize()),
+        package_hash: header.hash_b64.clone(),
+        content,
+    };
+
+    record_stored(db, pdf_id, &file_path, &file_key, &metadata, receipt).await
+}
+
+/// Opens a stream kept by `seal_stream` once its key is available.
+pub(crate) async fn open_sealed_stream(
+    db: &Database,
+    files: &FileStore,
+    pdf_id: &str,
+    priv_key: &CaseKey,
+    opened: AuditEvent,
+    max_extract_bytes: u64,
+) -> Result<(), HttpResponse> {
+    let loaded = async {
+        let mut reader = BufReader::new(fs::File::open(sealed_stream_path(pdf_id)).await?);
+        let (header, _) = read_header(&mut reader).await?;
+        Ok::<_, anyhow::Error>((header, reader))
+    }
+    .await;
+
+    let (header, reader) = match loaded {
+        Ok(loaded) if loaded.0.pdf_id == pdf_id => loaded,
+        Ok(_) => {
+            error!("Sealed stream for {} belongs to another case", pdf_id);
+            return Err(HttpResponse::InternalServerError().body("Storage Error"));
+        }
+        Err(e) => {
+            error!("Failed to load sealed stream for {}: {}", pdf_id, e);
+            return Err(HttpResponse::InternalServerError().body("Storage Error"));
+        }
+    };
+
+    open_stream(db, files, priv_key, &header, reader, opened, max_extract_bytes).await
+}
@@ -13,6 +13,101 @@ pub struct RxSettings {
     pub port: u16,
     pub pub_key_endp: String,
     pub rcv_endp: String,
+    pub jury: JurySettings,
+    pub key_pool: KeyPoolSettings,
+    pub key_expiry: KeyExpirySettings,
+    pub kek: KekSettings,
+    pub key_store: KeyStoreSettings,
+    /// Accept version 1 envelopes, whose session key is wrapped with RSA PKCS#1 v1.5.
+    pub allow_legacy_pkcs1v15: bool,
+    /// Accept unsigned envelopes older than version 3, which predate sender signatures. Their sender is unknown.
+    pub allow_unsigned_envelopes: bool,
+    /// TX identities allowed to submit packages.
+    pub trusted_senders: Vec<TrustedSender>,
+    /// Largest JSON or CBOR envelope accepted on `/receive`, and the most a compressed package may decompress to.
+    pub max_package_bytes: usize,
+    /// Largest stream accepted on `/receive`. Streams are not buffered, this bounds what is written to disk.
+    pub max_stream_bytes: u64,
+    /// Largest streamed PDF whose text is extracted for search, lopdf parses it whole in memory.
+    /// Larger ones are stored without searchable text.
+    pub max_extract_bytes: u64,
+    /// Request header naming the user, as set by the authenticating proxy. Without it the client address is used.
+    pub user_header: String,
+    /// Addresses of the authenticating proxies, the only clients whose `user_header` is believed.
+    pub trusted_proxies: Vec<String>,
+    pub watermark: WatermarkSettings,
+    pub admin: AdminSettings,
+    pub audit: AuditSettings,
+}
+
+#[derive(Deserialize)]
+pub struct AuditSettings {
+    /// Last audit row written before rows were keyed with the KEK. Rows up to it may be plain SHA-256,
+    /// any unkeyed row after it counts as tampered. 0 when the chain was keyed from the start.
+    pub unkeyed_until: i64,
+}
+
+#[derive(Deserialize)]
+pub struct AdminSettings {
+    /// Environment variable holding the bearer token of admin endpoints such as juror registration.
+    /// Those endpoints are off while it is unset.
+    pub token_env: String,
+}
+
+#[derive(Deserialize)]
+pub struct WatermarkSettings {
+    /// Whether downloaded PDFs are stamped with the case number, requesting user and time.
+    pub enabled: bool,
+}
+
+#[derive(Deserialize)]
+pub struct TrustedSender {
+    pub id: String,
+    /// Base64 encoded Ed25519 public key, as logged by TX on startup.
+    pub public_key: String,
+}
+
+#[derive(Deserialize)]
+pub struct JurySettings {
+    /// Whether per-case private keys are sealed and split among the registered jurors.
+    pub enabled: bool,
+    /// Number of juror shares required to rebuild a sealed key.
+    pub threshold: u8,
+}
+
+#[derive(Deserialize)]
+pub struct KeyPoolSettings {
+    /// Number of ready RSA key pairs to keep in the pool, 0 disables it.
+    pub size: u32,
+    /// How often the pool is topped up when no key has been taken in between.
+    pub refill_interval_secs: u64,
+}
+
+#[derive(Deserialize)]
+pub struct KeyExpirySettings {
+    /// How long an issued public key accepts a package.
+    pub ttl_secs: u64,
+    /// How often keys that expired without receiving a package are purged.
+    pub janitor_interval_secs: u64,
+}
+
+#[derive(Deserialize)]
+pub struct KekSettings {
+    /// Environment variable holding the KEK keyring, takes precedence over `path`.
+    pub env: String,
+    /// File holding the KEK keyring, one `id=base64key` per line with the current KEK first.
+    pub path: String,
+    /// Rejects stored keys that are plaintext or not bound to their record. Set once `jjk-rx rotate-kek`
+    /// has re-wrapped the keys written by an older version.
+    pub require_bound: bool,
+}
+
+#[derive(Deserialize)]
+pub struct KeyStoreSettings {
+    /// Where case private keys are kept: `postgres`, `file` or `memory`.
+    pub backend: String,
+    /// Directory of the `file` backend.
+    pub path: String,
 }
 
 #[derive(Deserialize)]
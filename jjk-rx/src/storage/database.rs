@@ -1,109 +1,1716 @@
-use crate::prelude::*;
-use crate::db::db_component::Db;
-use crate::domain::CaseSummary;
-use rsa::pkcs8::{EncodePrivateKey, DecodePrivateKey};
-use sqlx::FromRow;
-
-#[derive(Clone)]
-pub struct Database {
-    db: Db,
-}
-
-impl Database {
-    pub async fn connect(database_url: &str) -> Result<Self> {
-        let db = Db::connect(database_url, 5).await?;
-
-        Ok(Self { db })
-    }
-
-    pub async fn insert_keys(&self, pdf_id: String, private_key: RsaPrivateKey, public_key_pem: String) -> Result<()> {
-        let private_key_pem = private_key.to_pkcs8_pem(LineEnding::LF)?.to_string();
-
-        let sql = "INSERT INTO pdf (record_num, public_key, private_key, file_path) VALUES ($1, $2, $3, '')";
-        
-        sqlx::query(sql)
-            .bind(pdf_id)
-            .bind(public_key_pem)
-            .bind(private_key_pem)
-            .execute(self.db.pool())
-            .await?;
-            
-        Ok(())
-    }
-
-    pub async fn get_private_key(&self, pdf_id: &str) -> Result<RsaPrivateKey> {
-        let sql = "SELECT private_key FROM pdf WHERE record_num = $1";
-        
-        let row: (String,) = sqlx::query_as::<_, (String,)>(sql)
-            .bind(pdf_id)
-            .fetch_one(self.db.pool())
-            .await
-            .map_err(|e| anyhow!("Failed to fetch private key: {}", e))?;
-
-        let priv_key_pem = row.0;
-        let priv_key = RsaPrivateKey::from_pkcs8_pem(&priv_key_pem)?;
-        
-        Ok(priv_key)
-    }
-
-    pub async fn update_file_path(&self, pdf_id: &str, file_path: &str) -> Result<()> {
-        let sql = "UPDATE pdf SET file_path = $1, description = 'Received' WHERE record_num = $2";
-
-        let result = sqlx::query(sql)
-            .bind(file_path)
-            .bind(pdf_id)
-            .execute(self.db.pool())
-            .await?;
-
-        if result.rows_affected() == 0 {
-            return Err(anyhow!("PDF Record not found to update"));
-        }
-
-        Ok(())
-    }
-
-    pub async fn get_case_file_path(&self, case_code: &str) -> Result<String> {
-        let sql = "SELECT file_path FROM pdf WHERE record_num = $1";
-
-        let row: (String,) = sqlx::query_as::<_, (String,)>(sql)
-            .bind(case_code)
-            .fetch_one(self.db.pool())
-            .await
-            .map_err(|e| anyhow!("Failed to fetch file path: {}", e))?;
-
-        if row.0.trim().is_empty() {
-            return Err(anyhow!("File path is empty for case"));
-        }
-
-        Ok(row.0)
-    }
-
-    pub async fn list_cases(&self) -> Result<Vec<CaseSummary>> {
-        #[derive(FromRow)]
-        struct CaseRow {
-            record_num: String,
-            file_path: String,
-            description: Option<String>,
-            created_at: Option<chrono::NaiveDateTime>,
-        }
-
-        let sql = "SELECT record_num, file_path, description, created_at FROM pdf ORDER BY created_at DESC";
-
-        let rows: Vec<CaseRow> = sqlx::query_as(sql)
-            .fetch_all(self.db.pool())
-            .await
-            .map_err(|e| anyhow!("Failed to fetch cases: {}", e))?;
-
-        Ok(rows
-            .into_iter()
-            .map(|row| CaseSummary {
-                case_code: row.record_num,
-                file_path: row.file_path,
-                description: row.description,
-                created_at: row.created_at,
-            })
-            .collect())
-    }
-}
-
-
+use crate::prelude::*;
+use crate::db::db_component::Db;
+use crate::db::model::Juror;
+use crate::domain::{
+    AuditEvent, AuditVerification, BrokenLink, AUDIT_CASES_LISTED, AUDIT_CASES_SEARCHED, AUDIT_GENESIS_HASH, AUDIT_PACKAGE_RECEIVED, CaseCursor, CaseFilter, CasePage, CaseRegistration, CaseSort, CaseSummary, CustodyEvent, CustodyReport,
+    CustodyVerification, DocumentCustody, DocumentMetadata, DocumentSummary, PendingShare, SearchHit, SortOrder, ALG_RSA_OAEP_256,
+};
+use crate::encryption::{CaseKey, Keyring};
+use super::KeyRecord;
+use sqlx::FromRow;
+use sqlx::types::Json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Database {
+    db: Db,
+    keyring: Arc<Keyring>,
+}
+
+/// Outcome of claiming a case key for an incoming package.
+pub enum KeyClaim {
+    /// Claimed at the given time, which identifies the claim to `release_key`.
+    Claimed(chrono::NaiveDateTime),
+    NotFound,
+    Consumed,
+    Expired,
+}
+
+/// Outcome of `Database::register_case`.
+pub enum CaseRegistered {
+    Opened,
+    Updated,
+    /// The case already has another court or parties, which registering doesn't overwrite.
+    Conflict,
+}
+
+/// Location of a received document and the wrapped data key it is encrypted with.
+pub struct StoredFile {
+    pub path: String,
+    /// `None` for documents stored before encryption at rest.
+    pub file_key: Option<String>,
+}
+
+/// A document of a case, `None` while its PDF has not been stored yet.
+pub struct CaseDocument {
+    pub pdf_id: String,
+    pub file: Option<StoredFile>,
+}
+
+/// A case key being issued, as recorded by `KeyIssue::insert_case`.
+pub struct CaseIssue<'a> {
+    pub pdf_id: &'a str,
+    pub public_key_pem: &'a str,
+    /// Case the PDF is filed under, its own PDF ID when it opens a case of its own.
+    pub case_number: &'a str,
+    /// Sender that signed the key request. Only that sender's package is accepted for the key.
+    pub sender_id: Option<&'a str>,
+    pub ttl_secs: u64,
+}
+
+/// Transaction of a case key being issued, see `Database::begin_issue`.
+pub struct KeyIssue {
+    tx: sqlx::Transaction<'static, sqlx::Postgres>,
+    keyring: Arc<Keyring>,
+}
+
+impl KeyIssue {
+    /// Removes the oldest key pair from the pool and returns it, or `None` if the pool is empty.
+    pub async fn take_pooled_key(&mut self) -> Result<Option<(CaseKey, String)>> {
+        let sql = "DELETE FROM key_pool WHERE id = \
+                   (SELECT id FROM key_pool ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED) \
+                   RETURNING private_key, public_key";
+
+        let row: Option<(String, String)> = sqlx::query_as::<_, (String, String)>(sql)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .map_err(|e| anyhow!("Failed to take pooled key: {}", e))?;
+
+        match row {
+            Some((private_key_pem, public_key_pem)) => {
+                let private_key = CaseKey::from_stored(ALG_RSA_OAEP_256, &self.keyring.unwrap(&private_key_pem, "key_pool.private_key", &public_key_pem)?)?;
+                Ok(Some((private_key, public_key_pem)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Creates the record of the issued case key and files its PDF under the case.
+    /// The private key itself goes to the `KeyStore` once the issue is committed.
+    pub async fn insert_case(&mut self, issue: &CaseIssue<'_>, alg: &str, threshold: Option<u8>, shares: Vec<IssuedShare>) -> Result<()> {
+        let pdf_id = issue.pdf_id;
+
+        let sql = "INSERT INTO pdf (record_num, public_key, file_path, key_alg, key_threshold, sender_id, expires_at) \
+                   VALUES ($1, $2, '', $3, $4, $5, CURRENT_TIMESTAMP + make_interval(secs => $6))";
+
+        sqlx::query(sql)
+            .bind(pdf_id)
+            .bind(issue.public_key_pem)
+            .bind(alg)
+            .bind(threshold.map(i16::from))
+            .bind(issue.sender_id)
+            .bind(issue.ttl_secs as f64)
+            .execute(&mut *self.tx)
+            .await?;
+
+        let sql = "INSERT INTO key_share (record_num, juror_id, encrypted_share, share_hash) VALUES ($1, $2, $3, $4)";
+
+        for share in shares {
+            sqlx::query(sql)
+                .bind(pdf_id)
+                .bind(share.juror_id)
+                .bind(share.encrypted_share_b64)
+                .bind(share.share_hash)
+                .execute(&mut *self.tx)
+                .await?;
+        }
+
+        file_document(&mut self.tx, pdf_id, issue.case_number).await
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        self.tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// A share encrypted for one juror, ready to be persisted.
+pub struct IssuedShare {
+    pub juror_id: i32,
+    pub encrypted_share_b64: String,
+    pub share_hash: String,
+}
+
+impl Database {
+    pub async fn connect(database_url: &str, keyring: Arc<Keyring>) -> Result<Self> {
+        let db = Db::connect(database_url, 5).await?;
+
+        Ok(Self { db, keyring })
+    }
+
+    /// Starts issuing a case key. The pooled key it takes and the case record are committed together,
+    /// so a failed issue returns the pooled key.
+    pub async fn begin_issue(&self) -> Result<KeyIssue> {
+        let tx = self.db.pool().begin().await?;
+
+        Ok(KeyIssue { tx, keyring: self.keyring.clone() })
+    }
+
+    /// Stores the private key material of a case in `pdf.private_key`, wrapped with the KEK.
+    pub async fn store_private_key(&self, pdf_id: &str, record: &KeyRecord) -> Result<()> {
+        let sql = "UPDATE pdf SET private_key = $1, key_alg = $2, key_threshold = $3 WHERE record_num = $4";
+
+        let result = sqlx::query(sql)
+            .bind(self.keyring.wrap(&record.material, "pdf.private_key", pdf_id)?)
+            .bind(&record.alg)
+            .bind(record.threshold.map(i16::from))
+            .bind(pdf_id)
+            .execute(self.db.pool())
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow!("PDF Record not found to store its key"));
+        }
+
+        Ok(())
+    }
+
+    pub async fn fetch_private_key(&self, pdf_id: &str) -> Result<KeyRecord> {
+        let sql = "SELECT private_key, key_alg, key_threshold FROM pdf WHERE record_num = $1 AND private_key IS NOT NULL";
+        
+        let row: (String, String, Option<i16>) = sqlx::query_as::<_, (String, String, Option<i16>)>(sql)
+            .bind(pdf_id)
+            .fetch_one(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch private key: {}", e))?;
+
+        let (private_key, alg, threshold) = row;
+
+        Ok(KeyRecord {
+            alg,
+            threshold: threshold.map(u8::try_from).transpose()?,
+            material: self.keyring.unwrap(&private_key, "pdf.private_key", pdf_id)?,
+        })
+    }
+
+    pub async fn clear_private_key(&self, pdf_id: &str) -> Result<()> {
+        let sql = "UPDATE pdf SET private_key = NULL WHERE record_num = $1";
+
+        sqlx::query(sql)
+            .bind(pdf_id)
+            .execute(self.db.pool())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Adds a ready key pair to the pool.
+    pub async fn insert_pooled_key(&self, private_key: &CaseKey, public_key_pem: &str) -> Result<()> {
+        let sql = "INSERT INTO key_pool (public_key, private_key) VALUES ($1, $2)";
+
+        sqlx::query(sql)
+            .bind(public_key_pem)
+            .bind(self.keyring.wrap(&private_key.to_stored()?, "key_pool.private_key", public_key_pem)?)
+            .execute(self.db.pool())
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn key_pool_depth(&self) -> Result<i64> {
+        let sql = "SELECT COUNT(*) FROM key_pool";
+
+        let row: (i64,) = sqlx::query_as::<_, (i64,)>(sql)
+            .fetch_one(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to count pooled keys: {}", e))?;
+
+        Ok(row.0)
+    }
+
+    /// Marks the key of a case as used by an incoming package. Only one package can claim a key,
+    /// and only before it expires.
+    pub async fn claim_key(&self, pdf_id: &str) -> Result<KeyClaim> {
+        let sql = "UPDATE pdf SET consumed_at = CURRENT_TIMESTAMP \
+                   WHERE record_num = $1 AND consumed_at IS NULL \
+                   AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP) \
+                   RETURNING consumed_at";
+
+        let claimed: Option<(chrono::NaiveDateTime,)> = sqlx::query_as::<_, (chrono::NaiveDateTime,)>(sql)
+            .bind(pdf_id)
+            .fetch_optional(self.db.pool())
+            .await?;
+
+        if let Some((claimed_at,)) = claimed {
+            return Ok(KeyClaim::Claimed(claimed_at));
+        }
+
+        // Nothing claimed, find out why
+        let sql = "SELECT consumed_at IS NOT NULL FROM pdf WHERE record_num = $1";
+
+        let row: Option<(bool,)> = sqlx::query_as::<_, (bool,)>(sql)
+            .bind(pdf_id)
+            .fetch_optional(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch key state: {}", e))?;
+
+        Ok(match row {
+            None => KeyClaim::NotFound,
+            Some((true,)) => KeyClaim::Consumed,
+            Some((false,)) => KeyClaim::Expired,
+        })
+    }
+
+    /// Gives a claimed key back, so the sender can retry after a package failed to open.
+    /// Only the claim made at `claimed_at` is released, never one another request made meanwhile.
+    pub async fn release_key(&self, pdf_id: &str, claimed_at: chrono::NaiveDateTime) -> Result<()> {
+        // The retry may come unsigned where legacy envelopes are allowed, it has to sign again to count as signed
+        let sql = "UPDATE pdf SET consumed_at = NULL, package_signed = FALSE WHERE record_num = $1 AND consumed_at = $2";
+
+        sqlx::query(sql)
+            .bind(pdf_id)
+            .bind(claimed_at)
+            .execute(self.db.pool())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes keys that expired without receiving a package, along with their juror shares.
+    /// Returns the PDF IDs of the purged cases.
+    pub async fn purge_expired_keys(&self) -> Result<Vec<String>> {
+        let mut tx = self.db.pool().begin().await?;
+
+        let expired = "SELECT record_num FROM pdf \
+                       WHERE consumed_at IS NULL AND expires_at <= CURRENT_TIMESTAMP";
+
+        let sql = format!("DELETE FROM key_share WHERE record_num IN ({})", expired);
+        sqlx::query(&sql)
+            .execute(&mut *tx)
+            .await?;
+
+        // Cases opened for a lone PDF go with it, registered cases stay
+        let sql = format!("DELETE FROM document WHERE record_num IN ({})", expired);
+        sqlx::query(&sql)
+            .execute(&mut *tx)
+            .await?;
+
+        let sql = format!("DELETE FROM court_case c WHERE c.case_number IN ({}) \
+                           AND NOT EXISTS (SELECT 1 FROM document d WHERE d.case_id = c.id)", expired);
+        sqlx::query(&sql)
+            .execute(&mut *tx)
+            .await?;
+
+        let sql = "DELETE FROM pdf WHERE consumed_at IS NULL AND expires_at <= CURRENT_TIMESTAMP RETURNING record_num";
+        let purged: Vec<(String,)> = sqlx::query_as::<_, (String,)>(sql)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(purged.into_iter().map(|(pdf_id,)| pdf_id).collect())
+    }
+
+    /// Withdraws a key that was issued but can't be handed out, with its shares and filing.
+    /// Like `purge_expired_keys`, a case opened for the PDF alone goes with it.
+    pub async fn discard_case(&self, pdf_id: &str) -> Result<()> {
+        let mut tx = self.db.pool().begin().await?;
+
+        sqlx::query("DELETE FROM key_share WHERE record_num = $1")
+            .bind(pdf_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM document WHERE record_num = $1")
+            .bind(pdf_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let sql = "DELETE FROM court_case c WHERE c.case_number = $1 \
+                   AND NOT EXISTS (SELECT 1 FROM document d WHERE d.case_id = c.id)";
+        sqlx::query(sql)
+            .bind(pdf_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM pdf WHERE record_num = $1")
+            .bind(pdf_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Re-wraps the file keys and pooled private keys that aren't wrapped with the current KEK yet.
+    pub async fn rewrap_file_and_pool_keys(&self) -> Result<u64> {
+        self.rewrap_columns(&[("pdf", "file_key", "record_num"), ("key_pool", "private_key", "public_key")]).await
+    }
+
+    /// Re-wraps the case private keys kept in `pdf.private_key` by the Postgres `KeyStore`.
+    pub async fn rewrap_private_keys(&self) -> Result<u64> {
+        self.rewrap_columns(&[("pdf", "private_key", "record_num")]).await
+    }
+
+    /// Re-wraps every value in the given columns that isn't wrapped with the current KEK and bound to its record yet,
+    /// including plaintext rows from before envelope encryption. Values are bound to the column named by table and
+    /// column and to the row's value of the record column. Each row is swapped only if it wasn't changed meanwhile,
+    /// so this can run next to live RX instances that know both the old and new KEK.
+    /// Returns how many rows were re-wrapped.
+    async fn rewrap_columns(&self, columns: &[(&str, &str, &str)]) -> Result<u64> {
+        let mut rewrapped = 0;
+
+        for (table, column, record_column) in columns {
+            let sql = format!("SELECT id, {record_column}, {column} FROM {table} WHERE {column} IS NOT NULL ORDER BY id");
+
+            let rows: Vec<(i32, String, String)> = sqlx::query_as::<_, (i32, String, String)>(&sql)
+                .fetch_all(self.db.pool())
+                .await
+                .map_err(|e| anyhow!("Failed to fetch {}.{}: {}", table, column, e))?;
+
+            let sql = format!("UPDATE {table} SET {column} = $1 WHERE id = $2 AND {column} = $3");
+
+            let bound_column = format!("{table}.{column}");
+            for (id, record, stored) in rows {
+                if !self.keyring.needs_rewrap(&stored) {
+                    continue;
+                }
+
+                let wrapped = self.keyring.wrap(&self.keyring.unwrap(&stored, &bound_column, &record)?, &bound_column, &record)?;
+
+                let result = sqlx::query(&sql)
+                    .bind(wrapped)
+                    .bind(id)
+                    .bind(&stored)
+                    .execute(self.db.pool())
+                    .await?;
+
+                rewrapped += result.rows_affected();
+            }
+        }
+
+        Ok(rewrapped)
+    }
+
+    /// Documents stored before encryption at rest, as PDF ID and path of the plaintext file.
+    pub async fn plaintext_files(&self) -> Result<Vec<(String, String)>> {
+        let sql = "SELECT record_num, file_path FROM pdf WHERE file_key IS NULL AND file_path <> '' ORDER BY id";
+
+        let rows: Vec<(String, String)> = sqlx::query_as::<_, (String, String)>(sql)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch plaintext files: {}", e))?;
+
+        Ok(rows)
+    }
+
+    /// Points a document stored before encryption at rest to its encrypted copy. Returns `false` when the
+    /// record no longer points to the plaintext file.
+    pub async fn set_file_key(&self, pdf_id: &str, plaintext_path: &str, file_path: &str, file_key: &str) -> Result<bool> {
+        let sql = "UPDATE pdf SET file_path = $1, file_key = $2 WHERE record_num = $3 AND file_key IS NULL AND file_path = $4";
+
+        let result = sqlx::query(sql)
+            .bind(file_path)
+            .bind(file_key)
+            .bind(pdf_id)
+            .bind(plaintext_path)
+            .execute(self.db.pool())
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Case number of the case a PDF is filed under, `None` if the PDF ID is unknown.
+    pub async fn case_of_document(&self, pdf_id: &str) -> Result<Option<String>> {
+        let sql = "SELECT c.case_number FROM document d JOIN court_case c ON c.id = d.case_id WHERE d.record_num = $1";
+
+        let case_number: Option<String> = sqlx::query_scalar(sql)
+            .bind(pdf_id)
+            .fetch_optional(self.db.pool())
+            .await?;
+
+        Ok(case_number)
+    }
+
+    /// Case number a PDF is filed under and the sender of the PDF, `None` if the PDF ID is unknown.
+    pub async fn filing_of(&self, pdf_id: &str) -> Result<Option<(String, Option<String>)>> {
+        let sql = "SELECT c.case_number, p.sender_id FROM document d \
+                   JOIN court_case c ON c.id = d.case_id \
+                   JOIN pdf p ON p.record_num = d.record_num \
+                   WHERE d.record_num = $1";
+
+        let filing: Option<(String, Option<String>)> = sqlx::query_as(sql)
+            .bind(pdf_id)
+            .fetch_optional(self.db.pool())
+            .await?;
+
+        Ok(filing)
+    }
+
+    /// Opens a case or fills in the details it doesn't have yet, leaving out the ones not given, and appends `event`
+    /// to the audit chain along with it. The status may change, a court or parties already set may not.
+    pub async fn register_case(&self, case: &CaseRegistration, mut event: AuditEvent) -> Result<CaseRegistered> {
+        let mut tx = self.db.pool().begin().await?;
+
+        let sql = "INSERT INTO court_case (case_number, court, parties, status) VALUES ($1, $2, $3, COALESCE($4, 'Open')) \
+                   ON CONFLICT (case_number) DO UPDATE SET \
+                   court = COALESCE(court_case.court, EXCLUDED.court), \
+                   parties = COALESCE(court_case.parties, EXCLUDED.parties), \
+                   status = COALESCE($4, court_case.status) \
+                   WHERE (EXCLUDED.court IS NULL OR court_case.court IS NULL OR court_case.court = EXCLUDED.court) \
+                   AND (EXCLUDED.parties IS NULL OR court_case.parties IS NULL OR court_case.parties = EXCLUDED.parties) \
+                   RETURNING xmax = 0";
+
+        let created: Option<bool> = sqlx::query_scalar(sql)
+            .bind(&case.case_number)
+            .bind(&case.court)
+            .bind(&case.parties)
+            .bind(&case.status)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let Some(created) = created else {
+            return Ok(CaseRegistered::Conflict);
+        };
+
+        event.detail["opened"] = created.into();
+        append_event(&mut tx, &self.keyring, &event).await?;
+
+        tx.commit().await?;
+
+        Ok(if created { CaseRegistered::Opened } else { CaseRegistered::Updated })
+    }
+
+    /// Records who signed the package of a case, and when they say TX received the upload. Returns `false`
+    /// when another sender requested the key, whose package is the only one the key accepts.
+    pub async fn set_sender(&self, pdf_id: &str, sender_id: &str, tx_received_at: Option<chrono::DateTime<chrono::Utc>>) -> Result<bool> {
+        let sql = "UPDATE pdf SET sender_id = $1, tx_received_at = $2, package_signed = TRUE \
+                   WHERE record_num = $3 AND (sender_id IS NULL OR sender_id = $1)";
+
+        let result = sqlx::query(sql)
+            .bind(sender_id)
+            .bind(tx_received_at)
+            .bind(pdf_id)
+            .execute(self.db.pool())
+            .await?;
+
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        let sql = "SELECT EXISTS (SELECT 1 FROM pdf WHERE record_num = $1)";
+
+        let exists: bool = sqlx::query_scalar(sql)
+            .bind(pdf_id)
+            .fetch_one(self.db.pool())
+            .await?;
+
+        if !exists {
+            return Err(anyhow!("PDF Record not found to update"));
+        }
+
+        Ok(false)
+    }
+
+    /// Marks a package as kept sealed until the jury quorum, appending `receipt` to the audit chain along with it.
+    pub async fn mark_sealed(&self, pdf_id: &str, mut receipt: AuditEvent) -> Result<()> {
+        let mut tx = self.db.pool().begin().await?;
+
+        let sql = "UPDATE pdf SET description = 'Sealed' WHERE record_num = $1";
+
+        sqlx::query(sql)
+            .bind(pdf_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let sql = "SELECT c.case_number FROM document d JOIN court_case c ON c.id = d.case_id WHERE d.record_num = $1";
+
+        receipt.case_number = sqlx::query_scalar(sql)
+            .bind(pdf_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        append_event(&mut tx, &self.keyring, &receipt).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Returns `false` when a juror with that name is already registered.
+    pub async fn register_juror(&self, name: &str, public_key_pem: &str) -> Result<bool> {
+        let sql = "INSERT INTO juror (name, public_key) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING";
+
+        let result = sqlx::query(sql)
+            .bind(name)
+            .bind(public_key_pem)
+            .execute(self.db.pool())
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_jurors(&self) -> Result<Vec<Juror>> {
+        let sql = "SELECT id, name, public_key, created_at FROM juror ORDER BY id";
+
+        let jurors = sqlx::query_as::<_, Juror>(sql)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch jurors: {}", e))?;
+
+        Ok(jurors)
+    }
+
+    /// Shares issued to a juror for cases whose package has not been opened yet.
+    pub async fn pending_shares(&self, juror: &str) -> Result<Vec<PendingShare>> {
+        #[derive(FromRow)]
+        struct ShareRow {
+            record_num: String,
+            key_threshold: i16,
+            encrypted_share: String,
+        }
+
+        let sql = "SELECT s.record_num, p.key_threshold, s.encrypted_share \
+                   FROM key_share s \
+                   JOIN juror j ON j.id = s.juror_id \
+                   JOIN pdf p ON p.record_num = s.record_num \
+                   WHERE j.name = $1 AND p.description IS DISTINCT FROM 'Received' \
+                   ORDER BY p.created_at";
+
+        let rows: Vec<ShareRow> = sqlx::query_as(sql)
+            .bind(juror)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch shares: {}", e))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| PendingShare {
+                case_code: row.record_num,
+                threshold: row.key_threshold,
+                encrypted_share_b64: row.encrypted_share,
+            })
+            .collect())
+    }
+
+    /// Looks up the share issued to a juror for a case, returning the juror ID and the share fingerprint.
+    pub async fn get_issued_share(&self, pdf_id: &str, juror: &str) -> Result<(i32, String)> {
+        let sql = "SELECT j.id, s.share_hash FROM key_share s JOIN juror j ON j.id = s.juror_id \
+                   WHERE s.record_num = $1 AND j.name = $2";
+
+        let row: (i32, String) = sqlx::query_as::<_, (i32, String)>(sql)
+            .bind(pdf_id)
+            .bind(juror)
+            .fetch_one(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch issued share: {}", e))?;
+
+        Ok(row)
+    }
+
+    /// Records where a received document was stored, along with its metadata, and appends `receipt` to the audit
+    /// chain under the case the document ends up in. A document filed under a case of its own moves to the case
+    /// number TX detected in it, as far as `file_under_detected_case` allows. Returns `false`, changing nothing,
+    /// when another request already stored the document, e.g. two jurors completing the quorum at once.
+    pub async fn update_file_path(
+        &self,
+        pdf_id: &str,
+        file_path: &str,
+        file_key: &str,
+        metadata: &DocumentMetadata,
+        mut receipt: AuditEvent,
+    ) -> Result<bool> {
+        let mut tx = self.db.pool().begin().await?;
+
+        let sql = "UPDATE pdf SET file_path = $1, file_key = $2, description = 'Received' \
+                   WHERE record_num = $3 AND description IS DISTINCT FROM 'Received'";
+
+        let result = sqlx::query(sql)
+            .bind(file_path)
+            .bind(file_key)
+            .bind(pdf_id)
+            .execute(&mut *tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pdf WHERE record_num = $1)")
+                .bind(pdf_id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+            if !exists {
+                return Err(anyhow!("PDF Record not found to update"));
+            }
+
+            return Ok(false);
+        }
+
+        let sql = "UPDATE document SET title = $1, subject = $2, author = $3, keywords = $4, file_size = $5, sha256 = $6, \
+                   creator = $7, producer = $8, creation_date = $9, mod_date = $10, page_count = $11, pdf_version = $12, \
+                   encrypted = $13, xmp = $14, content = $15, package_hash = $16, detected_case_number = $17, \
+                   stored_at = CURRENT_TIMESTAMP \
+                   WHERE record_num = $18";
+
+        let info = &metadata.info;
+        let detected = info.case_number.as_deref().map(str::trim).filter(|case_number| !case_number.is_empty());
+        sqlx::query(sql)
+            .bind(&info.title)
+            .bind(&info.subject)
+            .bind(&info.author)
+            .bind(&info.keywords)
+            .bind(metadata.file_size)
+            .bind(&metadata.sha256)
+            .bind(&info.creator)
+            .bind(&info.producer)
+            .bind(info.creation_date)
+            .bind(info.mod_date)
+            .bind(info.page_count)
+            .bind(&info.pdf_version)
+            .bind(info.encrypted)
+            .bind(Json(&info.xmp))
+            .bind(&metadata.content)
+            .bind(&metadata.package_hash)
+            .bind(detected)
+            .bind(pdf_id)
+            .execute(&mut *tx)
+            .await?;
+
+        if let Some(case_number) = detected
+            && case_number != pdf_id
+        {
+            file_under_detected_case(&mut tx, pdf_id, case_number).await?;
+        }
+
+        let sql = "SELECT c.case_number FROM document d JOIN court_case c ON c.id = d.case_id WHERE d.record_num = $1";
+
+        receipt.case_number = sqlx::query_scalar(sql)
+            .bind(pdf_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        append_event(&mut tx, &self.keyring, &receipt).await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Returns `None` while the case exists but its document has not been stored yet.
+    pub async fn get_case_file(&self, case_code: &str) -> Result<Option<StoredFile>> {
+        let sql = "SELECT file_path, file_key FROM pdf WHERE record_num = $1";
+
+        let (path, file_key): (String, Option<String>) = sqlx::query_as::<_, (String, Option<String>)>(sql)
+            .bind(case_code)
+            .fetch_one(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch file path: {}", e))?;
+
+        if path.trim().is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(StoredFile { path, file_key }))
+    }
+
+    /// Documents of a case in the order they were filed, `None` if there is no such case.
+    pub async fn case_documents(&self, case_number: &str) -> Result<Option<Vec<CaseDocument>>> {
+        let sql = "SELECT EXISTS (SELECT 1 FROM court_case WHERE case_number = $1)";
+
+        let exists: bool = sqlx::query_scalar(sql)
+            .bind(case_number)
+            .fetch_one(self.db.pool())
+            .await?;
+
+        if !exists {
+            return Ok(None);
+        }
+
+        let sql = "SELECT p.record_num, p.file_path, p.file_key FROM document d \
+                   JOIN court_case c ON c.id = d.case_id \
+                   JOIN pdf p ON p.record_num = d.record_num \
+                   WHERE c.case_number = $1 ORDER BY d.id";
+
+        let rows: Vec<(String, String, Option<String>)> = sqlx::query_as::<_, (String, String, Option<String>)>(sql)
+            .bind(case_number)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch case documents: {}", e))?;
+
+        Ok(Some(rows
+            .into_iter()
+            .map(|(pdf_id, path, file_key)| CaseDocument {
+                pdf_id,
+                file: (!path.trim().is_empty()).then_some(StoredFile { path, file_key }),
+            })
+            .collect()))
+    }
+
+    /// One page of cases, each with its documents. `after` is the cursor of the previous page, which must use the same sort.
+    pub async fn list_cases(&self, filter: &CaseFilter, after: Option<&CaseCursor>, limit: i64) -> Result<CasePage> {
+        #[derive(FromRow)]
+        struct DocumentRow {
+            case_id: i32,
+            record_num: String,
+            title: Option<String>,
+            subject: Option<String>,
+            author: Option<String>,
+            keywords: Option<String>,
+            file_size: Option<i64>,
+            sha256: Option<String>,
+            creator: Option<String>,
+            producer: Option<String>,
+            creation_date: Option<chrono::DateTime<chrono::Utc>>,
+            mod_date: Option<chrono::DateTime<chrono::Utc>>,
+            page_count: Option<i32>,
+            pdf_version: Option<String>,
+            encrypted: Option<bool>,
+            xmp: Option<Json<BTreeMap<String, String>>>,
+            detected_case_number: Option<String>,
+            sender_id: Option<String>,
+            file_path: String,
+            description: Option<String>,
+            created_at: Option<chrono::NaiveDateTime>,
+        }
+
+        #[derive(FromRow)]
+        struct CaseRow {
+            id: i32,
+            case_number: String,
+            court: Option<String>,
+            parties: Option<String>,
+            status: String,
+            created_at: chrono::NaiveDateTime,
+        }
+
+        // Document filters, $1 to $3, shared by the case and document queries
+        let document_match = "($1::text IS NULL OR strpos(lower(d.author), lower($1)) > 0) \
+                              AND ($2::text IS NULL OR EXISTS (SELECT 1 FROM regexp_split_to_table(d.keywords, '[,;]') k \
+                                                               WHERE lower(trim(k)) = lower(trim($2)))) \
+                              AND ($3::text IS NULL OR p.description = $3)";
+
+        let case_match = format!(
+            "($4::date IS NULL OR c.created_at >= $4) \
+             AND ($5::date IS NULL OR c.created_at < $5 + 1) \
+             AND (($1::text IS NULL AND $2::text IS NULL AND $3::text IS NULL) \
+                  OR EXISTS (SELECT 1 FROM document d JOIN pdf p ON p.record_num = d.record_num \
+                             WHERE d.case_id = c.id AND {}))",
+            document_match
+        );
+
+        let sql = format!("SELECT count(*) FROM court_case c WHERE {}", case_match);
+
+        let total: i64 = sqlx::query_scalar(&sql)
+            .bind(&filter.author)
+            .bind(&filter.keyword)
+            .bind(&filter.status)
+            .bind(filter.from)
+            .bind(filter.to)
+            .fetch_one(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to count cases: {}", e))?;
+
+        // Keyset paging on the sort column, the ID breaking ties
+        let (column, cast) = match filter.sort {
+            CaseSort::CreatedAt => ("c.created_at", "timestamp"),
+            CaseSort::CaseNumber => ("c.case_number", "text"),
+        };
+        let (direction, comparison) = match filter.order {
+            SortOrder::Asc => ("ASC", ">"),
+            SortOrder::Desc => ("DESC", "<"),
+        };
+
+        let sql = format!(
+            "SELECT c.id, c.case_number, c.court, c.parties, c.status, c.created_at FROM court_case c \
+             WHERE {case_match} AND ($6::text IS NULL OR ({column}, c.id) {comparison} ($6::{cast}, $7::integer)) \
+             ORDER BY {column} {direction}, c.id {direction} LIMIT $8"
+        );
+
+        // One more than asked tells whether there is a next page
+        let mut rows: Vec<CaseRow> = sqlx::query_as(&sql)
+            .bind(&filter.author)
+            .bind(&filter.keyword)
+            .bind(&filter.status)
+            .bind(filter.from)
+            .bind(filter.to)
+            .bind(after.map(|cursor| &cursor.value))
+            .bind(after.map(|cursor| cursor.id))
+            .bind(limit + 1)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch cases: {}", e))?;
+
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            rows.last()
+                .map(|last| CaseCursor {
+                    sort: filter.sort,
+                    order: filter.order,
+                    value: match filter.sort {
+                        CaseSort::CreatedAt => last.created_at.format("%Y-%m-%dT%H:%M:%S%.6f").to_string(),
+                        CaseSort::CaseNumber => last.case_number.clone(),
+                    },
+                    id: last.id,
+                })
+                .map(|cursor| cursor.encode())
+                .transpose()?
+        } else {
+            None
+        };
+
+        let sql = format!(
+            "SELECT d.case_id, p.record_num, d.title, d.subject, d.author, d.keywords, d.file_size, d.sha256, \
+             d.creator, d.producer, d.creation_date, d.mod_date, d.page_count, d.pdf_version, d.encrypted, d.xmp, \
+             d.detected_case_number, p.sender_id, p.file_path, p.description, p.created_at \
+             FROM document d JOIN pdf p ON p.record_num = d.record_num \
+             WHERE d.case_id = ANY($4) AND {} \
+             ORDER BY p.created_at",
+            document_match
+        );
+
+        let case_ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+        let document_rows: Vec<DocumentRow> = sqlx::query_as(&sql)
+            .bind(&filter.author)
+            .bind(&filter.keyword)
+            .bind(&filter.status)
+            .bind(&case_ids)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch documents: {}", e))?;
+
+        let mut documents: HashMap<i32, Vec<DocumentSummary>> = HashMap::new();
+        for row in document_rows {
+            documents.entry(row.case_id).or_default().push(DocumentSummary {
+                case_code: row.record_num,
+                title: row.title,
+                subject: row.subject,
+                author: row.author,
+                keywords: row.keywords,
+                file_size: row.file_size,
+                sha256: row.sha256,
+                creator: row.creator,
+                producer: row.producer,
+                creation_date: row.creation_date,
+                mod_date: row.mod_date,
+                page_count: row.page_count,
+                pdf_version: row.pdf_version,
+                encrypted: row.encrypted,
+                xmp: row.xmp.map(|xmp| xmp.0),
+                detected_case_number: row.detected_case_number,
+                sender_id: row.sender_id,
+                file_path: row.file_path,
+                description: row.description,
+                created_at: row.created_at,
+            });
+        }
+
+        let cases = rows
+            .into_iter()
+            .map(|row| CaseSummary {
+                documents: documents.remove(&row.id).unwrap_or_default(),
+                case_number: row.case_number,
+                court: row.court,
+                parties: row.parties,
+                status: row.status,
+                created_at: Some(row.created_at),
+            })
+            .collect();
+
+        Ok(CasePage { cases, total, next_cursor })
+    }
+
+    /// Full-text search over the metadata and text of received documents, best matches first.
+    /// Title weighs most, then subject and keywords, author, and the text last.
+    pub async fn search_documents(&self, query: &str, limit: i64) -> Result<Vec<SearchHit>> {
+        #[derive(FromRow)]
+        struct HitRow {
+            case_number: String,
+            court: Option<String>,
+            status: String,
+            record_num: String,
+            title: Option<String>,
+            author: Option<String>,
+            rank: f32,
+            snippet: String,
+        }
+
+        // Headlines are only built for the hits returned, they need the whole text
+        let sql = "WITH hits AS ( \
+                       SELECT d.id, d.case_id, d.record_num, d.title, d.subject, d.keywords, d.author, d.content, q.query, \
+                              ts_rank_cd(d.search, q.query) AS rank \
+                       FROM document d, websearch_to_tsquery('english', $1) AS q(query) \
+                       WHERE d.search @@ q.query \
+                       ORDER BY rank DESC, d.id \
+                       LIMIT $2 \
+                   ) \
+                   SELECT c.case_number, c.court, c.status, h.record_num, h.title, h.author, h.rank, \
+                          ts_headline('english', concat_ws(' - ', h.title, h.subject, h.keywords, h.author, h.content), h.query, \
+                                      'StartSel=<mark>, StopSel=</mark>, MaxWords=20, MinWords=8, MaxFragments=3, FragmentDelimiter=\" ... \"') AS snippet \
+                   FROM hits h JOIN court_case c ON c.id = h.case_id \
+                   ORDER BY h.rank DESC, h.id";
+
+        let rows: Vec<HitRow> = sqlx::query_as(sql)
+            .bind(query)
+            .bind(limit)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to search documents: {}", e))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| SearchHit {
+                case_number: row.case_number,
+                court: row.court,
+                status: row.status,
+                case_code: row.record_num,
+                title: row.title,
+                author: row.author,
+                rank: row.rank,
+                snippet: row.snippet,
+            })
+            .collect())
+    }
+
+    /// Appends an event to the audit chain.
+    pub async fn record_event(&self, event: &AuditEvent) -> Result<()> {
+        let mut tx = self.db.pool().begin().await?;
+        append_event(&mut tx, &self.keyring, event).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Custody report of a case, `None` if there is no such case. Listings and searches are matched on the case
+    /// numbers they returned, through the index on `detail -> 'cases'`. The audit chain is verified up to the last
+    /// event of the report, later events don't bear on it.
+    pub async fn custody_report(&self, case_number: &str, generated_by: &str, unkeyed_until: i64) -> Result<Option<CustodyReport>> {
+        #[derive(FromRow)]
+        struct CaseRow {
+            case_number: String,
+            court: Option<String>,
+            parties: Option<String>,
+            status: String,
+            created_at: chrono::NaiveDateTime,
+        }
+
+        #[derive(FromRow)]
+        struct DocumentRow {
+            record_num: String,
+            title: Option<String>,
+            tx_received_at: Option<chrono::DateTime<chrono::Utc>>,
+            created_at: Option<chrono::NaiveDateTime>,
+            consumed_at: Option<chrono::NaiveDateTime>,
+            stored_at: Option<chrono::NaiveDateTime>,
+            sender_id: Option<String>,
+            package_hash: Option<String>,
+            sha256: Option<String>,
+            file_size: Option<i64>,
+            description: Option<String>,
+            file_path: String,
+        }
+
+        #[derive(FromRow)]
+        struct EventRow {
+            id: i64,
+            created_at: chrono::DateTime<chrono::Utc>,
+            action: String,
+            actor: String,
+            pdf_id: Option<String>,
+            detail: Json<serde_json::Value>,
+            hash: String,
+        }
+
+        let sql = "SELECT case_number, court, parties, status, created_at FROM court_case WHERE case_number = $1";
+
+        let Some(case) = sqlx::query_as::<_, CaseRow>(sql)
+            .bind(case_number)
+            .fetch_optional(self.db.pool())
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let sql = "SELECT p.record_num, d.title, p.tx_received_at, p.created_at, p.consumed_at, d.stored_at, p.sender_id, d.package_hash, \
+                   d.sha256, d.file_size, p.description, p.file_path \
+                   FROM document d \
+                   JOIN court_case c ON c.id = d.case_id \
+                   JOIN pdf p ON p.record_num = d.record_num \
+                   WHERE c.case_number = $1 ORDER BY d.id";
+
+        let rows: Vec<DocumentRow> = sqlx::query_as(sql)
+            .bind(case_number)
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch case documents: {}", e))?;
+
+        let pdf_ids: Vec<&str> = rows.iter().map(|row| row.record_num.as_str()).collect();
+
+        // Events of documents that were filed under a case of their own before moving to this one are kept too
+        let sql = "SELECT id, created_at, action, actor, pdf_id, detail, hash FROM audit_event \
+                   WHERE case_number = $1 OR pdf_id = ANY($2) OR (action = ANY($3) AND detail -> 'cases' ? $1) \
+                   ORDER BY id";
+
+        let events: Vec<EventRow> = sqlx::query_as(sql)
+            .bind(case_number)
+            .bind(&pdf_ids)
+            .bind([AUDIT_CASES_LISTED, AUDIT_CASES_SEARCHED])
+            .fetch_all(self.db.pool())
+            .await
+            .map_err(|e| anyhow!("Failed to fetch audit events: {}", e))?;
+
+        let last_event = events.last().map(|event| event.id);
+
+        let documents = rows
+            .into_iter()
+            .map(|row| {
+                // Turned away packages are only in the audit log, as receipts answered with an error
+                let rejected_packages = events
+                    .iter()
+                    .filter(|event| event.action == AUDIT_PACKAGE_RECEIVED && event.pdf_id.as_deref() == Some(row.record_num.as_str()))
+                    .filter(|event| event.detail.get("status").and_then(serde_json::Value::as_u64).is_some_and(|status| status >= 400))
+                    .count();
+
+                let verification = match (row.description.as_deref(), &row.sender_id) {
+                    (Some("Received"), Some(_)) => CustodyVerification::Verified,
+                    (Some("Received"), None) => CustodyVerification::Unsigned,
+                    (Some("Sealed"), _) => CustodyVerification::Sealed,
+                    _ if rejected_packages > 0 => CustodyVerification::Failed,
+                    _ => CustodyVerification::Pending,
+                };
+
+                DocumentCustody {
+                    case_code: row.record_num,
+                    title: row.title,
+                    tx_received_at: row.tx_received_at,
+                    key_issued_at: row.created_at,
+                    received_at: row.consumed_at,
+                    stored_at: row.stored_at,
+                    sender_id: row.sender_id,
+                    package_hash: row.package_hash,
+                    sha256: row.sha256,
+                    file_size: row.file_size,
+                    verification,
+                    rejected_packages,
+                    storage_location: Some(row.file_path).filter(|path| !path.trim().is_empty()),
+                }
+            })
+            .collect();
+
+        let events = events
+            .into_iter()
+            .map(|row| CustodyEvent {
+                id: row.id,
+                at: row.created_at,
+                action: row.action,
+                actor: row.actor,
+                pdf_id: row.pdf_id,
+                detail: row.detail.0,
+                hash: row.hash,
+            })
+            .collect();
+
+        Ok(Some(CustodyReport {
+            case_number: case.case_number,
+            court: case.court,
+            parties: case.parties,
+            status: case.status,
+            opened_at: case.created_at,
+            generated_at: chrono::Utc::now(),
+            generated_by: generated_by.to_string(),
+            documents,
+            events,
+            audit_chain: self.verify_audit_chain(unkeyed_until, last_event).await?,
+        }))
+    }
+
+    /// Walks the audit chain in order, recomputing each MAC, and stops at the first row that doesn't link up.
+    /// Rows up to `unkeyed_until` may predate keyed rows and are checked as plain SHA-256. With `up_to`,
+    /// the walk ends at that row instead of the head of the chain.
+    pub async fn verify_audit_chain(&self, unkeyed_until: i64, up_to: Option<i64>) -> Result<AuditVerification> {
+        const BATCH: i64 = 1000;
+
+        let sql = "SELECT id, created_at, action, actor, case_number, pdf_id, detail, prev_hash, hash, kek_id \
+                   FROM audit_event WHERE id > $1 AND id <= $3 ORDER BY id LIMIT $2";
+
+        let mut expected_prev = AUDIT_GENESIS_HASH.to_string();
+        let mut last_id = 0;
+        let mut checked = 0;
+
+        loop {
+            let rows: Vec<AuditRow> = sqlx::query_as(sql)
+                .bind(last_id)
+                .bind(BATCH)
+                .bind(up_to.unwrap_or(i64::MAX))
+                .fetch_all(self.db.pool())
+                .await
+                .map_err(|e| anyhow!("Failed to fetch audit events: {}", e))?;
+
+            if rows.is_empty() {
+                break;
+            }
+
+            for row in rows {
+                checked += 1;
+
+                if let Some(reason) = row.broken_link(&self.keyring, unkeyed_until, &expected_prev)? {
+                    return Ok(AuditVerification {
+                        valid: false,
+                        checked,
+                        first_broken: Some(BrokenLink { id: row.id, reason }),
+                    });
+                }
+
+                expected_prev = row.hash;
+                last_id = row.id;
+            }
+        }
+
+        Ok(AuditVerification { valid: true, checked, first_broken: None })
+    }
+}
+
+/// A row of the audit chain as stored.
+#[derive(FromRow)]
+struct AuditRow {
+    id: i64,
+    created_at: chrono::DateTime<chrono::Utc>,
+    action: String,
+    actor: String,
+    case_number: Option<String>,
+    pdf_id: Option<String>,
+    detail: Json<serde_json::Value>,
+    prev_hash: String,
+    hash: String,
+    kek_id: Option<String>,
+}
+
+impl AuditRow {
+    /// Why the row doesn't link up after the row hashed `expected_prev`, `None` when it does.
+    /// Rows up to `unkeyed_until` may be plain SHA-256, every later one must be keyed.
+    fn broken_link(&self, keyring: &Keyring, unkeyed_until: i64, expected_prev: &str) -> Result<Option<String>> {
+        if self.prev_hash != expected_prev {
+            return Ok(Some("prev_hash does not match the hash of the previous row".to_string()));
+        }
+
+        let event = AuditEvent {
+            action: self.action.clone(),
+            actor: self.actor.clone(),
+            case_number: self.case_number.clone(),
+            pdf_id: self.pdf_id.clone(),
+            detail: self.detail.0.clone(),
+        };
+
+        let expected_hash = match &self.kek_id {
+            Some(kek_id) => keyring.audit_mac(kek_id, &event.content(&self.prev_hash, &self.created_at)?),
+            None if self.id <= unkeyed_until => event.hash(&self.prev_hash, &self.created_at),
+            None => Err(anyhow!("row is not keyed")),
+        };
+
+        Ok(match expected_hash {
+            Ok(hash) if hash == self.hash => None,
+            Ok(_) => Some("hash does not match the content of the row".to_string()),
+            Err(e) => Some(e.to_string()),
+        })
+    }
+}
+
+/// Files a PDF under a case, opening the case if needed.
+async fn file_document(conn: &mut sqlx::PgConnection, pdf_id: &str, case_number: &str) -> Result<()> {
+    let sql = "INSERT INTO court_case (case_number) VALUES ($1) \
+               ON CONFLICT (case_number) DO UPDATE SET case_number = EXCLUDED.case_number \
+               RETURNING id";
+
+    let case_id: i32 = sqlx::query_scalar(sql)
+        .bind(case_number)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let sql = "INSERT INTO document (case_id, record_num) VALUES ($1, $2)";
+
+    sqlx::query(sql)
+        .bind(case_id)
+        .bind(pdf_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Moves a document filed under a case of its own to the case number TX detected in it, leaving its own case behind.
+/// Like `?case=` and `?group=` on a key request, only a signed package moves, and only into a case that is new or
+/// holds no document of another sender. Other documents stay where they are, with the number kept as metadata.
+async fn file_under_detected_case(conn: &mut sqlx::PgConnection, pdf_id: &str, case_number: &str) -> Result<()> {
+    let sql = "SELECT c.case_number FROM document d JOIN court_case c ON c.id = d.case_id WHERE d.record_num = $1";
+
+    let filed_under: Option<String> = sqlx::query_scalar(sql)
+        .bind(pdf_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    if filed_under.as_deref() != Some(pdf_id) {
+        return Ok(());
+    }
+
+    let sql = "SELECT p.package_signed AND NOT EXISTS ( \
+                   SELECT 1 FROM court_case c \
+                   JOIN document d ON d.case_id = c.id \
+                   JOIN pdf other ON other.record_num = d.record_num \
+                   WHERE c.case_number = $2 AND other.sender_id IS DISTINCT FROM p.sender_id) \
+               FROM pdf p WHERE p.record_num = $1";
+
+    let allowed: bool = sqlx::query_scalar(sql)
+        .bind(pdf_id)
+        .bind(case_number)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    if !allowed {
+        warn!("Document {} stays in its own case, it may not join detected case {}", pdf_id, case_number);
+        return Ok(());
+    }
+
+    let sql = "INSERT INTO court_case (case_number) VALUES ($1) \
+               ON CONFLICT (case_number) DO UPDATE SET case_number = EXCLUDED.case_number \
+               RETURNING id";
+
+    let case_id: i32 = sqlx::query_scalar(sql)
+        .bind(case_number)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    sqlx::query("UPDATE document SET case_id = $1 WHERE record_num = $2")
+        .bind(case_id)
+        .bind(pdf_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let sql = "DELETE FROM court_case c WHERE c.case_number = $1 \
+               AND NOT EXISTS (SELECT 1 FROM document d WHERE d.case_id = c.id)";
+
+    sqlx::query(sql)
+        .bind(pdf_id)
+        .execute(&mut *conn)
+        .await?;
+
+    info!("Document {} filed under detected case {}", pdf_id, case_number);
+
+    Ok(())
+}
+
+/// Appends an event to the audit chain within a transaction of the caller. Writers take turns,
+/// so every row links to the one written before it.
+async fn append_event(conn: &mut sqlx::PgConnection, keyring: &Keyring, event: &AuditEvent) -> Result<()> {
+    sqlx::query("LOCK TABLE audit_event IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *conn)
+        .await?;
+
+    let prev_hash: Option<String> = sqlx::query_scalar("SELECT hash FROM audit_event ORDER BY id DESC LIMIT 1")
+        .fetch_optional(&mut *conn)
+        .await?;
+    let prev_hash = prev_hash.unwrap_or_else(|| AUDIT_GENESIS_HASH.to_string());
+
+    // Postgres keeps microseconds, the hash has to cover the time as it will be read back
+    let now = chrono::Utc::now();
+    let created_at = chrono::DateTime::from_timestamp_micros(now.timestamp_micros()).unwrap_or(now);
+    let hash = keyring.audit_mac(keyring.current_id(), &event.content(&prev_hash, &created_at)?)?;
+
+    let sql = "INSERT INTO audit_event (created_at, action, actor, case_number, pdf_id, detail, prev_hash, hash, kek_id) \
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)";
+
+    sqlx::query(sql)
+        .bind(created_at)
+        .bind(&event.action)
+        .bind(&event.actor)
+        .bind(&event.case_number)
+        .bind(&event.pdf_id)
+        .bind(Json(&event.detail))
+        .bind(&prev_hash)
+        .bind(&hash)
+        .bind(keyring.current_id())
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Failed to record audit event: {}", e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::AUDIT_CASE_REGISTERED;
+    use crate::storage::FileStore;
+    use std::path::Path;
+
+    const KEYRING: &str = "k2=AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=\nk1=AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    /// A row appended after `prev_hash`, keyed with the current KEK like `append_event` does, or plain SHA-256
+    /// like rows written before the chain was keyed.
+    fn row(id: i64, prev_hash: &str, actor: &str, keyring: Option<&Keyring>) -> AuditRow {
+        let created_at = chrono::DateTime::from_timestamp_micros(1_700_000_000_000_000 + id).unwrap();
+        let event = AuditEvent {
+            action: AUDIT_PACKAGE_RECEIVED.to_string(),
+            actor: actor.to_string(),
+            case_number: None,
+            pdf_id: Some(format!("pdf-{}", id)),
+            detail: serde_json::json!({ "status": 200 }),
+        };
+        let content = event.content(prev_hash, &created_at).unwrap();
+        let hash = match keyring {
+            Some(keyring) => keyring.audit_mac(keyring.current_id(), &content).unwrap(),
+            None => event.hash(prev_hash, &created_at).unwrap(),
+        };
+
+        AuditRow {
+            id,
+            created_at,
+            action: event.action,
+            actor: event.actor,
+            case_number: event.case_number,
+            pdf_id: event.pdf_id,
+            detail: Json(event.detail),
+            prev_hash: prev_hash.to_string(),
+            hash,
+            kek_id: keyring.map(|keyring| keyring.current_id().to_string()),
+        }
+    }
+
+    fn chain(keyring: &Keyring, len: i64) -> Vec<AuditRow> {
+        let mut rows: Vec<AuditRow> = Vec::new();
+        for id in 1..=len {
+            let prev_hash = rows.last().map_or(AUDIT_GENESIS_HASH.to_string(), |row| row.hash.clone());
+            rows.push(row(id, &prev_hash, "jjk-tx", Some(keyring)));
+        }
+        rows
+    }
+
+    /// Walks the rows like `verify_audit_chain`, returning the first broken one and why.
+    fn first_broken(keyring: &Keyring, unkeyed_until: i64, rows: &[AuditRow]) -> Option<(i64, String)> {
+        let mut expected_prev = AUDIT_GENESIS_HASH;
+        for row in rows {
+            if let Some(reason) = row.broken_link(keyring, unkeyed_until, expected_prev).unwrap() {
+                return Some((row.id, reason));
+            }
+            expected_prev = &row.hash;
+        }
+        None
+    }
+
+    #[test]
+    fn keyed_chain_verifies() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+
+        assert_eq!(first_broken(&keyring, 0, &chain(&keyring, 3)), None);
+    }
+
+    #[test]
+    fn edited_or_dropped_rows_break_the_chain() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+
+        let mut edited = chain(&keyring, 3);
+        edited[1].actor = "someone else".to_string();
+        let (id, reason) = first_broken(&keyring, 0, &edited).unwrap();
+        assert_eq!(id, 2);
+        assert!(reason.contains("content"));
+
+        let mut dropped = chain(&keyring, 3);
+        dropped.remove(1);
+        let (id, reason) = first_broken(&keyring, 0, &dropped).unwrap();
+        assert_eq!(id, 3);
+        assert!(reason.contains("prev_hash"));
+    }
+
+    #[test]
+    fn rows_recomputed_without_the_kek_break_the_chain() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        let rows = chain(&keyring, 2);
+
+        // Rewriting a row means recomputing its hash, which takes the KEK: plain SHA-256 no longer passes
+        let unkeyed = row(2, &rows[0].hash, "someone else", None);
+        assert!(unkeyed.broken_link(&keyring, 0, &rows[0].hash).unwrap().unwrap().contains("not keyed"));
+
+        let mut claimed = row(2, &rows[0].hash, "someone else", None);
+        claimed.kek_id = rows[1].kek_id.clone();
+        assert!(claimed.broken_link(&keyring, 0, &rows[0].hash).unwrap().unwrap().contains("content"));
+
+        // A KEK of the same ID but other bytes
+        let other = Keyring::parse("k2=AwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwM=").unwrap();
+        assert_eq!(first_broken(&other, 0, &rows).unwrap().0, 1);
+    }
+
+    #[test]
+    fn unkeyed_rows_verify_up_to_the_cutover() {
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        let first = row(1, AUDIT_GENESIS_HASH, "jjk-tx", None);
+        let second = row(2, &first.hash, "jjk-tx", Some(&keyring));
+        let rows = [first, second];
+
+        assert_eq!(first_broken(&keyring, 1, &rows), None);
+        assert_eq!(first_broken(&keyring, 0, &rows).unwrap().0, 1);
+    }
+
+    #[test]
+    fn rows_of_a_retired_kek_verify_while_it_is_in_the_keyring() {
+        let old = Keyring::parse("k1=AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=").unwrap();
+        let keyring = Keyring::parse(KEYRING).unwrap();
+        let first = row(1, AUDIT_GENESIS_HASH, "jjk-tx", Some(&old));
+        let second = row(2, &first.hash, "jjk-tx", Some(&keyring));
+        let rows = [first, second];
+
+        assert_eq!(first_broken(&keyring, 0, &rows), None);
+
+        let unknown = Keyring::parse("k2=AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=").unwrap();
+        assert!(first_broken(&unknown, 0, &rows).is_some());
+    }
+
+    /// The schema script, as run over a new or an existing database.
+    const SCHEMA: &str = include_str!("../db/tables.sql");
+
+    /// The `pdf` table as it was before cases, single use keys and encryption at rest.
+    const LEGACY_PDF: &str = "CREATE TABLE pdf (id SERIAL PRIMARY KEY, public_key TEXT NOT NULL, private_key TEXT NOT NULL, \
+                              file_path TEXT NOT NULL, record_num TEXT NOT NULL, \
+                              created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, description TEXT)";
+
+    /// A database on a schema of its own at `TEST_DATABASE_URL`, set up with `before` and then upgraded by the
+    /// schema script. `None` when the variable isn't set, the tests using it only run against a live database.
+    async fn database(before: &[&str]) -> Option<Database> {
+        use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+        use std::str::FromStr;
+
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+
+        let options = PgConnectOptions::from_str(&url).unwrap().options([("search_path", schema.as_str())]);
+        let pool = PgPoolOptions::new().max_connections(2).connect_with(options).await.unwrap();
+
+        sqlx::query(&format!("CREATE SCHEMA {schema}")).execute(&pool).await.unwrap();
+        for sql in before {
+            sqlx::raw_sql(sql).execute(&pool).await.unwrap();
+        }
+        sqlx::raw_sql(SCHEMA).execute(&pool).await.unwrap();
+
+        Some(Database { db: Db::from_pool(pool), keyring: Arc::new(Keyring::parse(KEYRING).unwrap()) })
+    }
+
+    async fn drop_schema(database: Database) {
+        let schema: String = sqlx::query_scalar("SELECT current_schema()").fetch_one(database.db.pool()).await.unwrap();
+        sqlx::query(&format!("DROP SCHEMA {schema} CASCADE")).execute(database.db.pool()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keys_used_before_single_use_stay_consumed() {
+        let legacy = [
+            LEGACY_PDF,
+            "INSERT INTO pdf (public_key, private_key, file_path, record_num, description) VALUES \
+             ('pk', 'sk', './out/received.pdf', 'received', 'Received'), \
+             ('pk', 'sk', '', 'sealed', 'Sealed'), \
+             ('pk', 'sk', '', 'issued', NULL)",
+        ];
+        let Some(database) = database(&legacy).await else {
+            return;
+        };
+
+        assert!(matches!(database.claim_key("received").await.unwrap(), KeyClaim::Consumed));
+        assert!(matches!(database.claim_key("sealed").await.unwrap(), KeyClaim::Consumed));
+
+        // Issued but never used, it still takes its package until the janitor purges it
+        let expires: bool = sqlx::query_scalar("SELECT expires_at > CURRENT_TIMESTAMP FROM pdf WHERE record_num = 'issued'")
+            .fetch_one(database.db.pool())
+            .await
+            .unwrap();
+        assert!(expires);
+        assert!(matches!(database.claim_key("issued").await.unwrap(), KeyClaim::Claimed(_)));
+
+        drop_schema(database).await;
+    }
+
+    /// Issues the key of a PDF opening a case of its own, then stores its package as signed by `sender`, or
+    /// unsigned, with `detected` as the case number TX found in it.
+    async fn receive(database: &Database, pdf_id: &str, sender: Option<&str>, detected: &str) {
+        let issue = CaseIssue { pdf_id, public_key_pem: "pk", case_number: pdf_id, sender_id: None, ttl_secs: 900 };
+        let mut key_issue = database.begin_issue().await.unwrap();
+        key_issue.insert_case(&issue, ALG_RSA_OAEP_256, None, Vec::new()).await.unwrap();
+        key_issue.commit().await.unwrap();
+
+        if let Some(sender) = sender {
+            assert!(database.set_sender(pdf_id, sender, None).await.unwrap());
+        }
+
+        let metadata = DocumentMetadata {
+            info: serde_json::from_value(serde_json::json!({ "caseNumber": detected })).unwrap(),
+            file_size: 4,
+            sha256: String::new(),
+            package_hash: String::new(),
+            content: None,
+        };
+        let receipt = AuditEvent {
+            action: AUDIT_PACKAGE_RECEIVED.to_string(),
+            actor: sender.unwrap_or("127.0.0.1").to_string(),
+            case_number: None,
+            pdf_id: Some(pdf_id.to_string()),
+            detail: serde_json::json!({ "authenticated": sender.is_some() }),
+        };
+        assert!(database.update_file_path(pdf_id, "./out/document.pdf", "file-key", &metadata, receipt).await.unwrap());
+    }
+
+    async fn detected_case_number(database: &Database, pdf_id: &str) -> Option<String> {
+        sqlx::query_scalar("SELECT detected_case_number FROM document WHERE record_num = $1")
+            .bind(pdf_id)
+            .fetch_one(database.db.pool())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_signed_packages_join_the_detected_case_of_their_sender() {
+        let Some(database) = database(&[]).await else {
+            return;
+        };
+
+        receive(&database, "a1", Some("court-a"), "EXP-0892").await;
+        receive(&database, "a2", Some("court-a"), "EXP-0892").await;
+        assert_eq!(database.case_of_document("a1").await.unwrap().as_deref(), Some("EXP-0892"));
+        assert_eq!(database.case_of_document("a2").await.unwrap().as_deref(), Some("EXP-0892"));
+        assert!(database.case_documents("a1").await.unwrap().is_none());
+
+        // Another sender's case, and an unsigned package, keep the document in a case of its own
+        receive(&database, "b1", Some("court-b"), "EXP-0892").await;
+        receive(&database, "u1", None, "EXP-0893").await;
+        assert_eq!(database.case_of_document("b1").await.unwrap().as_deref(), Some("b1"));
+        assert_eq!(database.case_of_document("u1").await.unwrap().as_deref(), Some("u1"));
+        assert_eq!(detected_case_number(&database, "b1").await.as_deref(), Some("EXP-0892"));
+        assert_eq!(detected_case_number(&database, "u1").await.as_deref(), Some("EXP-0893"));
+        assert!(database.case_documents("EXP-0893").await.unwrap().is_none());
+
+        drop_schema(database).await;
+    }
+
+    #[tokio::test]
+    async fn custody_report_lists_the_searches_that_hit_the_case() {
+        let Some(database) = database(&[]).await else {
+            return;
+        };
+
+        receive(&database, "a1", Some("court-a"), "EXP-0892").await;
+        for cases in [vec!["EXP-0892", "EXP-0900"], vec!["EXP-0900"]] {
+            database.record_event(&AuditEvent {
+                action: AUDIT_CASES_SEARCHED.to_string(),
+                actor: "clerk".to_string(),
+                case_number: None,
+                pdf_id: None,
+                detail: serde_json::json!({ "q": "expediente", "limit": 20, "cases": cases }),
+            }).await.unwrap();
+        }
+
+        let report = database.custody_report("EXP-0892", "clerk", 0).await.unwrap().unwrap();
+        let actions: Vec<&str> = report.events.iter().map(|event| event.action.as_str()).collect();
+        assert_eq!(actions, [AUDIT_PACKAGE_RECEIVED, AUDIT_CASES_SEARCHED]);
+        assert!(report.audit_chain.valid);
+
+        drop_schema(database).await;
+    }
+
+    fn registration(court: Option<&str>, parties: Option<&str>, status: Option<&str>) -> (CaseRegistration, AuditEvent) {
+        let case = CaseRegistration {
+            case_number: "EXP-0892".to_string(),
+            court: court.map(str::to_string),
+            parties: parties.map(str::to_string),
+            status: status.map(str::to_string),
+        };
+        let event = AuditEvent {
+            action: AUDIT_CASE_REGISTERED.to_string(),
+            actor: "clerk".to_string(),
+            case_number: Some(case.case_number.clone()),
+            pdf_id: None,
+            detail: serde_json::json!({ "court": court, "parties": parties, "status": status }),
+        };
+        (case, event)
+    }
+
+    #[tokio::test]
+    async fn registering_a_case_fills_in_details_but_never_overwrites_them() {
+        let Some(database) = database(&[]).await else {
+            return;
+        };
+
+        let (case, event) = registration(Some("Juzgado 1"), None, None);
+        assert!(matches!(database.register_case(&case, event).await.unwrap(), CaseRegistered::Opened));
+
+        let (case, event) = registration(Some("Juzgado 1"), Some("A v B"), Some("Closed"));
+        assert!(matches!(database.register_case(&case, event).await.unwrap(), CaseRegistered::Updated));
+
+        let (case, event) = registration(Some("Juzgado 2"), None, None);
+        assert!(matches!(database.register_case(&case, event).await.unwrap(), CaseRegistered::Conflict));
+        let (case, event) = registration(None, Some("C v D"), None);
+        assert!(matches!(database.register_case(&case, event).await.unwrap(), CaseRegistered::Conflict));
+
+        let report = database.custody_report("EXP-0892", "clerk", 0).await.unwrap().unwrap();
+        assert_eq!(report.court.as_deref(), Some("Juzgado 1"));
+        assert_eq!(report.parties.as_deref(), Some("A v B"));
+        assert_eq!(report.status, "Closed");
+
+        let opened: Vec<_> = report.events.iter().map(|event| (event.action.as_str(), event.detail["opened"].as_bool())).collect();
+        assert_eq!(opened, [(AUDIT_CASE_REGISTERED, Some(true)), (AUDIT_CASE_REGISTERED, Some(false))]);
+
+        drop_schema(database).await;
+    }
+
+    #[tokio::test]
+    async fn plaintext_files_are_encrypted_and_removed() {
+        let legacy = [
+            LEGACY_PDF,
+            "INSERT INTO pdf (public_key, private_key, file_path, record_num, description) VALUES \
+             ('pk', 'sk', '', 'issued', NULL)",
+        ];
+        let Some(database) = database(&legacy).await else {
+            return;
+        };
+
+        let out_dir = std::env::temp_dir().join(format!("jjk-out-{}", uuid::Uuid::new_v4()));
+        let files = FileStore::new(database.keyring.clone(), &out_dir);
+        tokio::fs::create_dir_all(&out_dir).await.unwrap();
+        let plaintext_path = out_dir.join("received.pdf");
+        tokio::fs::write(&plaintext_path, b"%PDF-1.4 legacy").await.unwrap();
+        sqlx::query("INSERT INTO pdf (public_key, file_path, record_num, description) VALUES ('pk', $1, 'received', 'Received')")
+            .bind(plaintext_path.to_string_lossy())
+            .execute(database.db.pool())
+            .await
+            .unwrap();
+
+        assert_eq!(files.encrypt_plaintext_files(&database).await.unwrap(), 1);
+        assert!(!plaintext_path.exists());
+
+        let stored = database.get_case_file("received").await.unwrap().unwrap();
+        let file_key = stored.file_key.as_deref().unwrap();
+        assert_ne!(tokio::fs::read(&stored.path).await.unwrap(), b"%PDF-1.4 legacy");
+        let read = files.read("received", Path::new(&stored.path), Some(file_key)).await.unwrap();
+        assert_eq!(read, b"%PDF-1.4 legacy");
+
+        assert_eq!(files.encrypt_plaintext_files(&database).await.unwrap(), 0);
+
+        tokio::fs::remove_dir_all(&out_dir).await.unwrap();
+        drop_schema(database).await;
+    }
+
+    #[tokio::test]
+    async fn a_document_is_stored_once() {
+        let Some(database) = database(&[]).await else {
+            return;
+        };
+
+        receive(&database, "a1", Some("court-a"), "EXP-0892").await;
+
+        // A second juror completing the quorum at the same time
+        let metadata = DocumentMetadata {
+            info: serde_json::from_value(serde_json::json!({})).unwrap(),
+            file_size: 4,
+            sha256: String::new(),
+            package_hash: String::new(),
+            content: None,
+        };
+        let opened = AuditEvent {
+            action: AUDIT_PACKAGE_RECEIVED.to_string(),
+            actor: "juror-2".to_string(),
+            case_number: None,
+            pdf_id: Some("a1".to_string()),
+            detail: serde_json::json!({}),
+        };
+        assert!(!database.update_file_path("a1", "./out/other.pdf", "other-key", &metadata, opened).await.unwrap());
+
+        let stored = database.get_case_file("a1").await.unwrap().unwrap();
+        assert_eq!(stored.path, "./out/document.pdf");
+        assert_eq!(stored.file_key.as_deref(), Some("file-key"));
+        assert_eq!(database.case_of_document("a1").await.unwrap().as_deref(), Some("EXP-0892"));
+
+        drop_schema(database).await;
+    }
+}
@@ -0,0 +1,277 @@
+use crate::prelude::*;
+use crate::encryption::Keyring;
+use super::Database;
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use rand::RngCore;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+const MAGIC: &[u8; 4] = b"JJKF";
+const NONCE_PREFIX_LEN: usize = 7;
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// Received PDFs on disk, each encrypted with its own data key (AES-256-GCM STREAM, 64 KiB chunks).
+/// The data key is wrapped with the KEK keyring and kept in `pdf.file_key`, so the files alone reveal nothing.
+#[derive(Clone)]
+pub struct FileStore {
+    keyring: Arc<Keyring>,
+    out_dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(keyring: Arc<Keyring>, out_dir: impl Into<PathBuf>) -> Self {
+        Self { keyring, out_dir: out_dir.into() }
+    }
+
+    /// Encrypts and writes the document of a case, returning its path and wrapped data key.
+    pub async fn write(&self, pdf_id: &str, plaintext: &[u8]) -> Result<(PathBuf, String)> {
+        let mut writer = self.create(pdf_id).await?;
+
+        if let Err(e) = writer.write(plaintext).await {
+            writer.abort().await;
+            return Err(e);
+        }
+
+        writer.finish().await
+    }
+
+    /// Starts writing the document of a case piece by piece. Nothing is visible under the final path until `finish`.
+    pub async fn create(&self, pdf_id: &str) -> Result<FileWriter> {
+        let data_key = Aes256Gcm::generate_key(&mut OsRng);
+        let cipher = Aes256Gcm::new(&data_key);
+
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        // Each write gets files of its own, so a write losing a race to store the document can't replace the winner's
+        fs::create_dir_all(&self.out_dir).await?;
+        let name = format!("{}-{}", pdf_id, Uuid::new_v4().simple());
+        let file_path = self.out_dir.join(format!("{}.pdf.enc", name));
+        let part_path = self.out_dir.join(format!("{}.pdf.enc.part", name));
+
+        let mut file = fs::File::create(&part_path).await?;
+        file.write_all(MAGIC).await?;
+        file.write_all(&nonce_prefix).await?;
+
+        Ok(FileWriter {
+            file,
+            encryptor: EncryptorBE32::from_aead(cipher, nonce_prefix.as_slice().into()),
+            buffer: Vec::with_capacity(2 * CHUNK_LEN),
+            // Every chunk is bound to the PDF ID, so a file can't be swapped in for another case
+            aad: pdf_id.as_bytes().to_vec(),
+            part_path,
+            file_path,
+            wrapped_key: self.keyring.wrap(&b64.encode(data_key), "pdf.file_key", pdf_id)?,
+        })
+    }
+
+    /// Encrypts the documents stored before encryption at rest, then removes their plaintext once their record
+    /// points to the encrypted copy. Returns how many were encrypted, files that can't be read are left for another run.
+    pub async fn encrypt_plaintext_files(&self, db: &Database) -> Result<u64> {
+        let mut encrypted = 0;
+
+        for (pdf_id, plaintext_path) in db.plaintext_files().await? {
+            let plaintext = match fs::read(&plaintext_path).await {
+                Ok(plaintext) => plaintext,
+                Err(e) => {
+                    error!("Failed to read document {} at '{}': {}", pdf_id, plaintext_path, e);
+                    continue;
+                }
+            };
+
+            let (file_path, file_key) = self.write(&pdf_id, &plaintext).await?;
+
+            if !db.set_file_key(&pdf_id, &plaintext_path, &file_path.to_string_lossy(), &file_key).await? {
+                warn!("Document {} changed while it was being encrypted, leaving it", pdf_id);
+                fs::remove_file(&file_path).await?;
+                continue;
+            }
+
+            fs::remove_file(&plaintext_path).await?;
+            encrypted += 1;
+        }
+
+        Ok(encrypted)
+    }
+
+    /// Reads the document of a case back. Files stored before encryption at rest have no data key
+    /// and are returned as-is until `jjk-rx encrypt-files` has run.
+    pub async fn read(&self, pdf_id: &str, file_path: &Path, wrapped_key: Option<&str>) -> Result<Vec<u8>> {
+        let bytes = fs::read(file_path).await?;
+
+        let Some(wrapped_key) = wrapped_key else {
+            return Ok(bytes);
+        };
+
+        let data_key = b64.decode(self.keyring.unwrap(wrapped_key, "pdf.file_key", pdf_id)?)
+            .map_err(|e| anyhow!("Failed to decode file key: {}", e))?;
+
+        Self::decrypt(&data_key, pdf_id, &bytes)
+    }
+
+    fn decrypt(data_key: &[u8], pdf_id: &str, bytes: &[u8]) -> Result<Vec<u8>> {
+        let header_len = MAGIC.len() + NONCE_PREFIX_LEN;
+        if bytes.len() < header_len + TAG_LEN || !bytes.starts_with(MAGIC) {
+            return Err(anyhow!("Stored file is not an encrypted document"));
+        }
+
+        let cipher = Aes256Gcm::new_from_slice(data_key)
+            .map_err(|e| anyhow!("Invalid file key length: {}", e))?;
+
+        let aad = pdf_id.as_bytes();
+        let nonce_prefix = &bytes[MAGIC.len()..header_len];
+        let mut decryptor = DecryptorBE32::from_aead(cipher, nonce_prefix.into());
+
+        let mut plaintext = Vec::with_capacity(bytes.len());
+        let mut chunks = bytes[header_len..].chunks(CHUNK_LEN + TAG_LEN).peekable();
+
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                plaintext.extend(decryptor.decrypt_last(Payload { msg: chunk, aad })
+                    .map_err(|_| anyhow!("Stored file failed authentication"))?);
+                break;
+            }
+
+            plaintext.extend(decryptor.decrypt_next(Payload { msg: chunk, aad })
+                .map_err(|_| anyhow!("Stored file failed authentication"))?);
+        }
+
+        Ok(plaintext)
+    }
+}
+
+/// Encrypts a document as it is written, one 64 KiB chunk at a time.
+pub struct FileWriter {
+    file: fs::File,
+    encryptor: EncryptorBE32<Aes256Gcm>,
+    buffer: Vec<u8>,
+    aad: Vec<u8>,
+    part_path: PathBuf,
+    file_path: PathBuf,
+    wrapped_key: String,
+}
+
+impl FileWriter {
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(data);
+
+        // Keep the last chunk buffered, it has to be encrypted as the final one
+        while self.buffer.len() > CHUNK_LEN {
+            let chunk: Vec<u8> = self.buffer.drain(..CHUNK_LEN).collect();
+            let ciphertext = self.encryptor.encrypt_next(Payload { msg: &chunk, aad: &self.aad })
+                .map_err(|e| anyhow!("AES error: {}", e))?;
+            self.file.write_all(&ciphertext).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the final chunk and moves the file in place, returning its path and wrapped data key.
+    pub async fn finish(mut self) -> Result<(PathBuf, String)> {
+        let ciphertext = self.encryptor.encrypt_last(Payload { msg: &self.buffer, aad: &self.aad })
+            .map_err(|e| anyhow!("AES error: {}", e))?;
+
+        self.file.write_all(&ciphertext).await?;
+        self.file.sync_all().await?;
+        fs::rename(&self.part_path, &self.file_path).await?;
+
+        Ok((self.file_path, self.wrapped_key))
+    }
+
+    /// Discards a document that failed to arrive in full.
+    pub async fn abort(self) {
+        drop(self.file);
+        if let Err(e) = fs::remove_file(&self.part_path).await {
+            error!("Failed to remove partial file '{}': {}", self.part_path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> FileStore {
+        let keyring = Keyring::parse("k1=AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=").unwrap();
+        FileStore::new(Arc::new(keyring), std::env::temp_dir().join(format!("jjk-files-{}", Uuid::new_v4())))
+    }
+
+    #[tokio::test]
+    async fn file_round_trip_across_chunk_boundaries() {
+        let store = store();
+
+        for len in [0, 1, CHUNK_LEN, 2 * CHUNK_LEN + 1] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let (path, wrapped_key) = store.write("pdf-1", &plaintext).await.unwrap();
+
+            assert_eq!(store.read("pdf-1", &path, Some(&wrapped_key)).await.unwrap(), plaintext);
+            assert_ne!(fs::read(&path).await.unwrap().get(MAGIC.len()..), Some(plaintext.as_slice()));
+            assert!(!path.with_extension("enc.part").exists());
+        }
+
+        fs::remove_dir_all(&store.out_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn each_write_has_files_of_its_own() {
+        let store = store();
+        let (first, first_key) = store.write("pdf-1", b"first").await.unwrap();
+        let (second, _) = store.write("pdf-1", b"second").await.unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(store.read("pdf-1", &first, Some(&first_key)).await.unwrap(), b"first");
+
+        fs::remove_dir_all(&store.out_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tampered_truncated_or_swapped_files_fail() {
+        let store = store();
+        let plaintext = vec![7u8; 2 * CHUNK_LEN + 1];
+        let (path, wrapped_key) = store.write("pdf-1", &plaintext).await.unwrap();
+        let bytes = fs::read(&path).await.unwrap();
+        let data_key = b64.decode(store.keyring.unwrap(&wrapped_key, "pdf.file_key", "pdf-1").unwrap()).unwrap();
+
+        let mut tampered = bytes.clone();
+        tampered[MAGIC.len() + NONCE_PREFIX_LEN + 10] ^= 1;
+        assert!(FileStore::decrypt(&data_key, "pdf-1", &tampered).is_err());
+
+        // Dropping the final chunk leaves an intermediate one posing as the last
+        let truncated = &bytes[..bytes.len() - (1 + TAG_LEN)];
+        assert!(FileStore::decrypt(&data_key, "pdf-1", truncated).is_err());
+
+        assert!(FileStore::decrypt(&data_key, "pdf-2", &bytes).is_err());
+        assert!(store.read("pdf-2", &path, Some(&wrapped_key)).await.is_err());
+
+        fs::remove_dir_all(&store.out_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn files_from_before_encryption_at_rest_are_read_as_is() {
+        let store = store();
+        fs::create_dir_all(&store.out_dir).await.unwrap();
+        let path = store.out_dir.join("pdf-1.pdf");
+        fs::write(&path, b"%PDF-1.4").await.unwrap();
+
+        assert_eq!(store.read("pdf-1", &path, None).await.unwrap(), b"%PDF-1.4");
+
+        fs::remove_dir_all(&store.out_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn aborted_writes_leave_nothing_behind() {
+        let store = store();
+        let mut writer = store.create("pdf-1").await.unwrap();
+        writer.write(&[1u8; CHUNK_LEN + 1]).await.unwrap();
+        let (part_path, file_path) = (writer.part_path.clone(), writer.file_path.clone());
+
+        writer.abort().await;
+        assert!(!part_path.exists());
+        assert!(!file_path.exists());
+
+        fs::remove_dir_all(&store.out_dir).await.unwrap();
+    }
+}
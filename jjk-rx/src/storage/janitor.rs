@@ -0,0 +1,35 @@
+use crate::prelude::*;
+use super::{Database, KeyStore};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Starts a background task that periodically purges keys that expired without receiving a package.
+pub fn spawn_janitor(db: Database, key_store: Arc<dyn KeyStore>, interval_secs: u64) {
+    let interval = Duration::from_secs(interval_secs.max(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            let purged = match db.purge_expired_keys().await {
+                Ok(purged) => purged,
+                Err(e) => {
+                    error!("Failed to purge expired keys: {}", e);
+                    continue;
+                }
+            };
+
+            for pdf_id in &purged {
+                if let Err(e) = key_store.delete(pdf_id).await {
+                    error!("Failed to delete expired key for {}: {}", pdf_id, e);
+                }
+            }
+
+            if !purged.is_empty() {
+                info!("Purged {} expired unused keys", purged.len());
+            }
+        }
+    });
+}
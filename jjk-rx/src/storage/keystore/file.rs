@@ -0,0 +1,100 @@
+use crate::prelude::*;
+use crate::encryption::Keyring;
+use super::{KeyRecord, KeyStore, StoredKey};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+
+/// Names key files in the AAD of their wrapped material, next to the PDF ID.
+const KEY_FILE_COLUMN: &str = "key_file.material";
+
+/// Keeps each private key in its own JSON file under `dir`, with the key material wrapped with the KEK.
+pub struct FileKeyStore {
+    keyring: Arc<Keyring>,
+    dir: PathBuf,
+}
+
+impl FileKeyStore {
+    pub fn new(keyring: Arc<Keyring>, dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self { keyring, dir })
+    }
+
+    fn key_path(&self, pdf_id: &str) -> Result<PathBuf> {
+        Ok(self.dir.join(format!("{}.key.json", Self::key_id(pdf_id)?)))
+    }
+
+    /// The PDF ID in the form key files are named and bound to.
+    fn key_id(pdf_id: &str) -> Result<String> {
+        // PDF IDs come from clients, only accept the UUIDs RX issues
+        let pdf_id = Uuid::parse_str(pdf_id)
+            .map_err(|_| anyhow!("Invalid PDF ID '{}'", pdf_id))?;
+
+        Ok(pdf_id.to_string())
+    }
+
+    async fn write_record(&self, path: &Path, record: &KeyRecord) -> Result<()> {
+        let bytes = serde_json::to_vec(record)?;
+
+        // Write then rename, so a reader never sees a half-written key
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, bytes).await?;
+        fs::rename(&tmp_path, path).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KeyStore for FileKeyStore {
+    async fn put(&self, pdf_id: &str, key: &StoredKey) -> Result<()> {
+        let mut record = key.to_record()?;
+        record.material = self.keyring.wrap(&record.material, KEY_FILE_COLUMN, &Self::key_id(pdf_id)?)?;
+
+        self.write_record(&self.key_path(pdf_id)?, &record).await
+    }
+
+    async fn get(&self, pdf_id: &str) -> Result<StoredKey> {
+        let bytes = fs::read(self.key_path(pdf_id)?).await
+            .map_err(|e| anyhow!("Failed to read private key for {}: {}", pdf_id, e))?;
+
+        let mut record: KeyRecord = serde_json::from_slice(&bytes)?;
+        record.material = self.keyring.unwrap(&record.material, KEY_FILE_COLUMN, &Self::key_id(pdf_id)?)?;
+
+        record.into_stored()
+    }
+
+    async fn delete(&self, pdf_id: &str) -> Result<()> {
+        match fs::remove_file(self.key_path(pdf_id)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn rewrap(&self) -> Result<u64> {
+        let mut rewrapped = 0;
+        let mut entries = fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let Some(pdf_id) = path.file_name().and_then(|name| Some(name.to_str()?.strip_suffix(".key.json")?.to_string())) else {
+                continue;
+            };
+
+            let mut record: KeyRecord = serde_json::from_slice(&fs::read(&path).await?)?;
+            if !self.keyring.needs_rewrap(&record.material) {
+                continue;
+            }
+
+            let material = self.keyring.unwrap(&record.material, KEY_FILE_COLUMN, &pdf_id)?;
+            record.material = self.keyring.wrap(&material, KEY_FILE_COLUMN, &pdf_id)?;
+            self.write_record(&path, &record).await?;
+            rewrapped += 1;
+        }
+
+        Ok(rewrapped)
+    }
+}
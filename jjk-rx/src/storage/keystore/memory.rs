@@ -0,0 +1,93 @@
+use crate::prelude::*;
+use super::{KeyRecord, KeyStore, StoredKey};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Keeps private keys in process memory only. Meant for tests and throwaway instances.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    keys: Mutex<HashMap<String, KeyRecord>>,
+}
+
+#[async_trait]
+impl KeyStore for MemoryKeyStore {
+    async fn put(&self, pdf_id: &str, key: &StoredKey) -> Result<()> {
+        let record = key.to_record()?;
+        self.keys.lock().unwrap().insert(pdf_id.to_string(), record);
+        Ok(())
+    }
+
+    async fn get(&self, pdf_id: &str) -> Result<StoredKey> {
+        let record = self.keys.lock().unwrap()
+            .get(pdf_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("No private key stored for {}", pdf_id))?;
+
+        record.into_stored()
+    }
+
+    async fn delete(&self, pdf_id: &str) -> Result<()> {
+        self.keys.lock().unwrap().remove(pdf_id);
+        Ok(())
+    }
+
+    async fn rewrap(&self) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ALG_X25519_HKDF_SHA256;
+    use crate::encryption::CaseKey;
+    use crate::storage::SealedKey;
+
+    #[tokio::test]
+    async fn plain_key_round_trip() {
+        let store = MemoryKeyStore::default();
+        let (key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        let stored = key.to_stored().unwrap();
+
+        store.put("pdf-1", &StoredKey::Plain(key)).await.unwrap();
+
+        match store.get("pdf-1").await.unwrap() {
+            StoredKey::Plain(key) => {
+                assert_eq!(key.alg(), ALG_X25519_HKDF_SHA256);
+                assert_eq!(key.to_stored().unwrap(), stored);
+            }
+            StoredKey::Sealed(_) => panic!("plain key came back sealed"),
+        }
+    }
+
+    #[tokio::test]
+    async fn sealed_key_round_trip() {
+        let store = MemoryKeyStore::default();
+        let sealed = SealedKey { sealed_b64: "c2VhbGVk".to_string(), alg: ALG_X25519_HKDF_SHA256.to_string(), threshold: 2 };
+
+        store.put("pdf-1", &StoredKey::Sealed(sealed)).await.unwrap();
+
+        match store.get("pdf-1").await.unwrap() {
+            StoredKey::Sealed(sealed) => {
+                assert_eq!(sealed.sealed_b64, "c2VhbGVk");
+                assert_eq!(sealed.alg, ALG_X25519_HKDF_SHA256);
+                assert_eq!(sealed.threshold, 2);
+            }
+            StoredKey::Plain(_) => panic!("sealed key came back plain"),
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_and_deleted_keys_fail() {
+        let store = MemoryKeyStore::default();
+        assert!(store.get("pdf-1").await.is_err());
+
+        let (key, _) = CaseKey::generate(ALG_X25519_HKDF_SHA256).unwrap();
+        store.put("pdf-1", &StoredKey::Plain(key)).await.unwrap();
+        store.delete("pdf-1").await.unwrap();
+
+        assert!(store.get("pdf-1").await.is_err());
+        assert_eq!(store.rewrap().await.unwrap(), 0);
+    }
+}
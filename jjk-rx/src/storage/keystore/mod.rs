@@ -0,0 +1,93 @@
+pub mod file;
+pub mod memory;
+pub mod postgres;
+
+pub use file::FileKeyStore;
+pub use memory::MemoryKeyStore;
+pub use postgres::PgKeyStore;
+
+use crate::prelude::*;
+use crate::encryption::{CaseKey, Keyring};
+use crate::settings::KeyStoreSettings;
+use super::Database;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Private key of a case as persisted, either usable as-is or sealed until the jury reaches quorum.
+pub enum StoredKey {
+    Plain(CaseKey),
+    Sealed(SealedKey),
+}
+
+/// A private key encrypted under a case key split among the jurors.
+pub struct SealedKey {
+    pub sealed_b64: String,
+    pub alg: String,
+    pub threshold: u8,
+}
+
+/// Text form of a `StoredKey` handed to the backends, which wrap `material` with the KEK where it leaves the process.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub alg: String,
+    /// Jury threshold, set when `material` is a sealed key.
+    pub threshold: Option<u8>,
+    pub material: String,
+}
+
+impl StoredKey {
+    pub fn to_record(&self) -> Result<KeyRecord> {
+        match self {
+            Self::Plain(key) => Ok(KeyRecord {
+                alg: key.alg().to_string(),
+                threshold: None,
+                material: key.to_stored()?,
+            }),
+            Self::Sealed(sealed) => Ok(KeyRecord {
+                alg: sealed.alg.clone(),
+                threshold: Some(sealed.threshold),
+                material: sealed.sealed_b64.clone(),
+            }),
+        }
+    }
+}
+
+impl KeyRecord {
+    pub fn into_stored(self) -> Result<StoredKey> {
+        match self.threshold {
+            Some(threshold) => Ok(StoredKey::Sealed(SealedKey {
+                sealed_b64: self.material,
+                alg: self.alg,
+                threshold,
+            })),
+            None => Ok(StoredKey::Plain(CaseKey::from_stored(&self.alg, &self.material)?)),
+        }
+    }
+}
+
+/// Custody of per-case private keys, kept apart from the case records in the `pdf` table.
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    async fn put(&self, pdf_id: &str, key: &StoredKey) -> Result<()>;
+
+    /// Fails if no key is stored for the case.
+    async fn get(&self, pdf_id: &str) -> Result<StoredKey>;
+
+    async fn delete(&self, pdf_id: &str) -> Result<()>;
+
+    /// Re-wraps stored keys with the current KEK, returning how many were re-wrapped.
+    async fn rewrap(&self) -> Result<u64>;
+}
+
+/// Builds the backend selected by `rx.key_store.backend`: `postgres`, `file` or `memory`.
+pub fn build_key_store(settings: &KeyStoreSettings, db: &Database, keyring: Arc<Keyring>) -> Result<Arc<dyn KeyStore>> {
+    match settings.backend.as_str() {
+        "postgres" => Ok(Arc::new(PgKeyStore::new(db.clone()))),
+        "file" => Ok(Arc::new(FileKeyStore::new(keyring, &settings.path)?)),
+        "memory" => {
+            warn!("Using the in-memory key store, keys will be lost on restart");
+            Ok(Arc::new(MemoryKeyStore::default()))
+        }
+        other => Err(anyhow!("Unknown key store backend '{}'", other)),
+    }
+}
@@ -0,0 +1,34 @@
+use crate::prelude::*;
+use crate::storage::Database;
+use super::{KeyStore, StoredKey};
+use async_trait::async_trait;
+
+/// Keeps private keys in the `private_key` column of the `pdf` table, wrapped with the KEK.
+pub struct PgKeyStore {
+    db: Database,
+}
+
+impl PgKeyStore {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl KeyStore for PgKeyStore {
+    async fn put(&self, pdf_id: &str, key: &StoredKey) -> Result<()> {
+        self.db.store_private_key(pdf_id, &key.to_record()?).await
+    }
+
+    async fn get(&self, pdf_id: &str) -> Result<StoredKey> {
+        self.db.fetch_private_key(pdf_id).await?.into_stored()
+    }
+
+    async fn delete(&self, pdf_id: &str) -> Result<()> {
+        self.db.clear_private_key(pdf_id).await
+    }
+
+    async fn rewrap(&self) -> Result<u64> {
+        self.db.rewrap_private_keys().await
+    }
+}
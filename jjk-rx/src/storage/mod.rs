@@ -1,2 +1,8 @@
-pub mod database;
-pub use database::Database;
+pub mod database;
+pub mod files;
+pub mod janitor;
+pub mod keystore;
+pub use database::{CaseDocument, CaseIssue, CaseRegistered, Database, IssuedShare, KeyClaim, KeyIssue, StoredFile};
+pub use files::{FileStore, FileWriter};
+pub use janitor::spawn_janitor;
+pub use keystore::{KeyStore, KeyRecord, StoredKey, SealedKey, build_key_store};
@@ -1,47 +1,208 @@
 use crate::prelude::*;
 use crate::transmission::Transmitter;
-use super::EncryptedPackage;
+use super::{BinaryPackage, RxPublicKey, SenderIdentity, ENVELOPE_VERSION, COMPRESSION_DEFLATE, COMPRESSION_ZSTD, associated_data, derive_session_key, signed_message};
+use crate::settings::Compression;
+use aes_gcm::Key;
+use flate2::write::DeflateEncoder;
+use std::borrow::Cow;
+use x25519_dalek::{EphemeralSecret, PublicKey};
 
 pub struct Encrypter {}
 
+/// AES session key of a package, with what RX needs to recover it.
+pub(super) struct SessionKey {
+    pub key: Key<Aes256Gcm>,
+    /// Session key wrapped with RSA-OAEP, empty for key agreement.
+    pub encrypted_session_key: Vec<u8>,
+    /// Ephemeral X25519 public key, only set for key agreement.
+    pub ephemeral_public_key: Option<Vec<u8>>,
+}
+
 impl Encrypter {
     pub async fn perform_hybrid_encryption(
         msg_bytes: &[u8],
-    ) -> anyhow::Result<(String, EncryptedPackage)> {
+        identity: &SenderIdentity,
+        compression: Compression,
+        group: Option<&str>,
+        received_at: &str,
+    ) -> anyhow::Result<(String, BinaryPackage)> {
         // Hash the message bytes
         let mut hasher = Sha256::new();
         hasher.update(msg_bytes);
         let hash = hasher.finalize();
 
+        // Compress before encrypting, RX checks the hash after decompressing
+        let (msg_bytes, compression) = Self::compress(msg_bytes, compression)?;
+
         // Fetch public key from RX
-        let (pdf_id, rx_pub_key) = Transmitter::get_pub_key().await?;
+        let (pdf_id, rx_pub_key) = Transmitter::get_pub_key(group, identity).await?;
 
-        let mut rng = OsRng;
+        let alg = rx_pub_key.alg();
+        let aad = associated_data(&pdf_id, ENVELOPE_VERSION, alg, compression);
 
-        // Generate AES session key
-        let session_key = Aes256Gcm::generate_key(&mut rng);
-        debug!("Generated AES session key");
+        let session_key = Self::session_key(&rx_pub_key, &aad)?;
 
-        // Encrypt message (PDF bytes)
-        let cipher = Aes256Gcm::new(&session_key);
-        let nonce = Aes256Gcm::generate_nonce(&mut rng);
-        let encrypted_data = cipher.encrypt(&nonce, msg_bytes)
+        // Encrypt message (PDF bytes), authenticating the PDF ID, envelope header and compression with it
+        let cipher = Aes256Gcm::new(&session_key.key);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let encrypted_data = cipher.encrypt(&nonce, Payload { msg: &msg_bytes, aad: &aad })
             .map_err(|e| anyhow!("AES error: {}", e))?;
         debug!("Encrypted message with AES session key");
 
-        // Encrypt session key with RSA
-        let encrypted_session_key = rx_pub_key.encrypt(&mut rng, Pkcs1v15Encrypt, session_key.as_slice())
-            .map_err(|e| anyhow!("RSA error: {}", e))?;
-        debug!("Encrypted AES session key with RX public key");
+        // Sign the hash together with the PDF ID, envelope header, receipt time and compression
+        let signature = identity.sign(&signed_message(&pdf_id, ENVELOPE_VERSION, alg, &b64.encode(hash), received_at, compression));
+        debug!("Signed package as sender '{}'", identity.id());
 
         Ok((
             pdf_id,
-            EncryptedPackage {
-                encrypted_session_key_b64: b64.encode(encrypted_session_key),
-                encrypted_data_b64: b64.encode(encrypted_data),
-                nonce_b64: b64.encode(nonce),
-                hash_b64: b64.encode(hash),
+            BinaryPackage {
+                version: ENVELOPE_VERSION,
+                alg: alg.to_string(),
+                encrypted_session_key: session_key.encrypted_session_key,
+                ephemeral_public_key: session_key.ephemeral_public_key,
+                encrypted_data,
+                nonce: nonce.to_vec(),
+                hash: hash.to_vec(),
+                sender_id: identity.id().to_string(),
+                signature,
+                compression: compression.map(str::to_string),
+                received_at: received_at.to_string(),
             }
         ))
     }
-}
\ No newline at end of file
+
+    /// Compresses the message, returning it with the name RX knows the compression by.
+    /// Data that doesn't shrink, like most born-digital PDFs, is sent as is.
+    fn compress(msg_bytes: &[u8], compression: Compression) -> anyhow::Result<(Cow<'_, [u8]>, Option<&'static str>)> {
+        let (compressed, name) = match compression {
+            Compression::None => return Ok((Cow::Borrowed(msg_bytes), None)),
+            Compression::Zstd => (zstd::encode_all(msg_bytes, 0)?, COMPRESSION_ZSTD),
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(msg_bytes)?;
+                (encoder.finish()?, COMPRESSION_DEFLATE)
+            }
+        };
+
+        if compressed.len() >= msg_bytes.len() {
+            debug!("{} did not shrink the message, sending it uncompressed", name);
+            return Ok((Cow::Borrowed(msg_bytes), None));
+        }
+
+        debug!("Compressed message with {} from {} to {} bytes", name, msg_bytes.len(), compressed.len());
+        Ok((Cow::Owned(compressed), Some(name)))
+    }
+
+    /// Gets the AES session key: generated and wrapped with RSA-OAEP, or derived from an X25519 exchange.
+    pub(super) fn session_key(
+        rx_pub_key: &RxPublicKey,
+        aad: &[u8],
+    ) -> anyhow::Result<SessionKey> {
+        let mut rng = OsRng;
+
+        match rx_pub_key {
+            RxPublicKey::Rsa(rsa_key) => {
+                let session_key = Aes256Gcm::generate_key(&mut rng);
+                debug!("Generated AES session key");
+
+                let encrypted_session_key = rsa_key.encrypt(&mut rng, Oaep::new::<Sha256>(), session_key.as_slice())
+                    .map_err(|e| anyhow!("RSA error: {}", e))?;
+                debug!("Encrypted AES session key with RX public key");
+
+                Ok(SessionKey { key: session_key, encrypted_session_key, ephemeral_public_key: None })
+            }
+            RxPublicKey::X25519(recipient_key) => {
+                let ephemeral_secret = EphemeralSecret::random_from_rng(rng);
+                let ephemeral_public_key = PublicKey::from(&ephemeral_secret);
+
+                let shared_secret = ephemeral_secret.diffie_hellman(recipient_key);
+                if !shared_secret.was_contributory() {
+                    return Err(anyhow!("RX X25519 public key is a low order point"));
+                }
+
+                let session_key = derive_session_key(
+                    shared_secret.as_bytes(),
+                    ephemeral_public_key.as_bytes(),
+                    recipient_key.as_bytes(),
+                    aad,
+                );
+                debug!("Derived AES session key from X25519 exchange");
+
+                Ok(SessionKey {
+                    key: session_key.into(),
+                    encrypted_session_key: Vec::new(),
+                    ephemeral_public_key: Some(ephemeral_public_key.as_bytes().to_vec()),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::ALG_X25519_HKDF_SHA256;
+    use rsa::RsaPrivateKey;
+    use std::io::Read;
+
+    #[test]
+    fn compression_round_trip() {
+        let msg = b"%PDF-1.7 the same words again and again ".repeat(50);
+
+        let (compressed, name) = Encrypter::compress(&msg, Compression::Zstd).unwrap();
+        assert_eq!(name, Some(COMPRESSION_ZSTD));
+        assert!(compressed.len() < msg.len());
+        assert_eq!(zstd::decode_all(compressed.as_ref()).unwrap(), msg);
+
+        let (compressed, name) = Encrypter::compress(&msg, Compression::Deflate).unwrap();
+        assert_eq!(name, Some(COMPRESSION_DEFLATE));
+        let mut decompressed = Vec::new();
+        flate2::read::DeflateDecoder::new(compressed.as_ref()).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, msg);
+
+        let (sent, name) = Encrypter::compress(&msg, Compression::None).unwrap();
+        assert_eq!((sent.as_ref(), name), (msg.as_slice(), None));
+    }
+
+    #[test]
+    fn data_that_does_not_shrink_is_sent_as_is() {
+        let msg: Vec<u8> = (0..64).map(|i| (i * 37 % 251) as u8).collect();
+
+        for compression in [Compression::Zstd, Compression::Deflate] {
+            let (sent, name) = Encrypter::compress(&msg, compression).unwrap();
+            assert!(matches!(sent, Cow::Borrowed(_)));
+            assert_eq!(name, None);
+        }
+    }
+
+    #[test]
+    fn x25519_session_key_is_what_rx_derives() {
+        let rx_secret = EphemeralSecret::random_from_rng(OsRng);
+        let rx_public = PublicKey::from(&rx_secret);
+        let aad = associated_data("pdf-1", ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, None);
+
+        let session_key = Encrypter::session_key(&RxPublicKey::X25519(rx_public), &aad).unwrap();
+        assert!(session_key.encrypted_session_key.is_empty());
+
+        let ephemeral_public: [u8; 32] = session_key.ephemeral_public_key.unwrap().try_into().unwrap();
+        let shared_secret = rx_secret.diffie_hellman(&PublicKey::from(ephemeral_public));
+        let derived = derive_session_key(shared_secret.as_bytes(), &ephemeral_public, rx_public.as_bytes(), &aad);
+        assert_eq!(session_key.key.as_slice(), derived);
+
+        // Bound to the header through the HKDF info
+        let other_aad = associated_data("pdf-2", ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, None);
+        assert_ne!(derive_session_key(shared_secret.as_bytes(), &ephemeral_public, rx_public.as_bytes(), &other_aad), derived);
+    }
+
+    #[test]
+    fn rsa_session_key_unwraps_with_the_case_key() {
+        let private_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let public_key = RsaPublicKey::from(&private_key);
+
+        let session_key = Encrypter::session_key(&RxPublicKey::Rsa(public_key), b"").unwrap();
+        assert!(session_key.ephemeral_public_key.is_none());
+
+        let unwrapped = private_key.decrypt(Oaep::new::<Sha256>(), &session_key.encrypted_session_key).unwrap();
+        assert_eq!(unwrapped, session_key.key.as_slice());
+    }
+}
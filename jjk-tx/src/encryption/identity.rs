@@ -0,0 +1,114 @@
+use crate::prelude::*;
+use crate::settings::IdentitySettings;
+use ed25519_dalek::{
+    Signer,
+    SigningKey,
+    pkcs8::{DecodePrivateKey, EncodePrivateKey},
+};
+use rsa::pkcs8::LineEnding;
+
+/// Long-lived Ed25519 key this TX signs every package with, so RX can tell who sent it.
+pub struct SenderIdentity {
+    id: String,
+    signing_key: SigningKey,
+}
+
+impl SenderIdentity {
+    /// Loads the signing key from `key_path`, generating and saving a new one if the file doesn't exist.
+    pub fn load_or_create(settings: &IdentitySettings) -> anyhow::Result<Self> {
+        let key_path = PathBuf::from(&settings.key_path);
+
+        let signing_key = if key_path.exists() {
+            let pem = fs::read_to_string(&key_path)?;
+            SigningKey::from_pkcs8_pem(&pem)
+                .map_err(|e| anyhow!("Invalid identity key '{}': {}", key_path.display(), e))?
+        } else {
+            let signing_key = SigningKey::generate(&mut OsRng);
+            let pem = signing_key.to_pkcs8_pem(LineEnding::LF)
+                .map_err(|e| anyhow!("Failed to encode identity key: {}", e))?;
+
+            if let Some(dir) = key_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            // Readable by the TX user only, from the moment it exists
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(&key_path)?.write_all(pem.as_bytes())?;
+
+            warn!("Generated a new sender identity key at '{}'", key_path.display());
+            signing_key
+        };
+
+        Ok(Self { id: settings.id.clone(), signing_key })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Base64 encoded public key, to be registered in RX's trusted senders.
+    pub fn public_key_b64(&self) -> String {
+        b64.encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Vec<u8> {
+        self.signing_key.sign(msg).to_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    #[test]
+    fn key_is_created_once_and_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = IdentitySettings {
+            id: "jjk-tx".to_string(),
+            key_path: dir.path().join("keys/identity.pem").to_string_lossy().into_owned(),
+        };
+
+        let created = SenderIdentity::load_or_create(&settings).unwrap();
+        let reloaded = SenderIdentity::load_or_create(&settings).unwrap();
+        assert_eq!(created.public_key_b64(), reloaded.public_key_b64());
+        assert_eq!(reloaded.id(), "jjk-tx");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&settings.key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn signatures_verify_with_the_public_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = IdentitySettings {
+            id: "jjk-tx".to_string(),
+            key_path: dir.path().join("identity.pem").to_string_lossy().into_owned(),
+        };
+        let identity = SenderIdentity::load_or_create(&settings).unwrap();
+
+        let public_key: [u8; 32] = b64.decode(identity.public_key_b64()).unwrap().try_into().unwrap();
+        let public_key = VerifyingKey::from_bytes(&public_key).unwrap();
+        let signature = Signature::from_slice(&identity.sign(b"message")).unwrap();
+
+        assert!(public_key.verify(b"message", &signature).is_ok());
+        assert!(public_key.verify(b"other message", &signature).is_err());
+    }
+
+    #[test]
+    fn invalid_key_file_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("identity.pem");
+        fs::write(&key_path, "not a key").unwrap();
+        let settings = IdentitySettings { id: "jjk-tx".to_string(), key_path: key_path.to_string_lossy().into_owned() };
+
+        assert!(SenderIdentity::load_or_create(&settings).is_err());
+    }
+}
@@ -1,14 +1,229 @@
 pub mod encrypter;
+pub mod identity;
+pub mod stream;
 
 pub use encrypter::Encrypter;
+pub use identity::SenderIdentity;
+pub use stream::StreamEncrypter;
 
 use crate::prelude::*;
 
+/// Version of the envelope format produced by this TX.
+pub const ENVELOPE_VERSION: u8 = 4;
+/// The AES session key is wrapped with RSA-OAEP (SHA-256).
+pub const ALG_RSA_OAEP_256: &str = "RSA-OAEP-256";
+/// The AES session key is derived with HKDF-SHA256 from an X25519 exchange with an ephemeral key.
+pub const ALG_X25519_HKDF_SHA256: &str = "X25519-HKDF-SHA256";
+
+/// Content type RX routes to its CBOR envelope receiver.
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+/// Content type RX routes to its stream receiver.
+pub const STREAM_CONTENT_TYPE: &str = "application/vnd.jjk.stream";
+
+/// Plaintext compressed with zstd before encryption.
+pub const COMPRESSION_ZSTD: &str = "zstd";
+/// Plaintext compressed with raw deflate before encryption.
+pub const COMPRESSION_DEFLATE: &str = "deflate";
+
+/// Public key RX issued for a case.
+pub enum RxPublicKey {
+    Rsa(RsaPublicKey),
+    X25519(x25519_dalek::PublicKey),
+}
+
+/// HKDF-SHA256 over the X25519 shared secret, salted with both public keys and bound to `info`.
+/// Must match RX's derivation exactly.
+pub fn derive_session_key(
+    shared_secret: &[u8],
+    ephemeral_public_key: &[u8],
+    recipient_public_key: &[u8],
+    info: &[u8],
+) -> [u8; 32] {
+    let salt = [ephemeral_public_key, recipient_public_key].concat();
+    let hkdf = hkdf::Hkdf::<Sha256>::new(Some(&salt), shared_secret);
+
+    let mut session_key = [0u8; 32];
+    hkdf.expand(info, &mut session_key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    session_key
+}
+
+impl RxPublicKey {
+    pub fn alg(&self) -> &'static str {
+        match self {
+            Self::Rsa(_) => ALG_RSA_OAEP_256,
+            Self::X25519(_) => ALG_X25519_HKDF_SHA256,
+        }
+    }
+}
+
+/// Bytes authenticated as AES-GCM associated data, binding the ciphertext to its PDF ID, envelope header and
+/// compression. Must match RX's `associated_data`.
+pub fn associated_data(pdf_id: &str, version: u8, alg: &str, compression: Option<&str>) -> Vec<u8> {
+    format!("jjk:v{}:{}:{}:{}", version, alg, pdf_id, compression.unwrap_or("none")).into_bytes()
+}
+
+/// Bytes the sender signs: the package hash bound to its PDF ID and envelope header, the time TX received
+/// the upload and the compression. Must match RX's `signed_message`.
+pub fn signed_message(
+    pdf_id: &str,
+    version: u8,
+    alg: &str,
+    hash_b64: &str,
+    received_at: &str,
+    compression: Option<&str>,
+) -> Vec<u8> {
+    format!("jjk-sig:v{}:{}:{}:{}:{}:{}", version, alg, pdf_id, hash_b64, received_at, compression.unwrap_or("none"))
+        .into_bytes()
+}
+
+/// When TX received an upload, as carried in `receivedAt`.
+pub fn received_at(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// Headers of a signed key request, must match RX's.
+pub const KEY_REQUEST_SENDER_HEADER: &str = "X-JJK-Sender";
+pub const KEY_REQUEST_TIMESTAMP_HEADER: &str = "X-JJK-Timestamp";
+pub const KEY_REQUEST_SIGNATURE_HEADER: &str = "X-JJK-Signature";
+
+/// Bytes the sender signs to request a key, binding the case or group it files the PDF under.
+/// Must match RX's `key_request_message`.
+pub fn key_request_message(timestamp: i64, alg: &str, case: Option<&str>, group: Option<&str>) -> Vec<u8> {
+    format!("jjk-key:{}:{}:{}:{}", timestamp, alg, case.unwrap_or_default(), group.unwrap_or_default()).into_bytes()
+}
+
+/// Package with raw bytes, sent as is in the CBOR envelope and base64 encoded in the JSON one.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BinaryPackage {
+    version: u8,
+    alg: String,
+    #[serde(with = "serde_bytes")]
+    encrypted_session_key: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    ephemeral_public_key: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    encrypted_data: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    nonce: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    hash: Vec<u8>,
+    sender_id: String,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<String>,
+    received_at: String,
+}
+
+impl From<BinaryPackage> for EncryptedPackage {
+    fn from(pkg: BinaryPackage) -> Self {
+        Self {
+            version: pkg.version,
+            alg: pkg.alg,
+            encrypted_session_key_b64: b64.encode(pkg.encrypted_session_key),
+            ephemeral_public_key_b64: pkg.ephemeral_public_key.map(|epk| b64.encode(epk)),
+            encrypted_data_b64: b64.encode(pkg.encrypted_data),
+            nonce_b64: b64.encode(pkg.nonce),
+            hash_b64: b64.encode(pkg.hash),
+            sender_id: pkg.sender_id,
+            signature_b64: b64.encode(pkg.signature),
+            compression: pkg.compression,
+            received_at: pkg.received_at,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EncryptedPackage {
+    version: u8,
+    alg: String,
     encrypted_session_key_b64: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ephemeral_public_key_b64: Option<String>,
     encrypted_data_b64: String,
     nonce_b64: String,
     hash_b64: String,
-}
\ No newline at end of file
+    sender_id: String,
+    signature_b64: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<String>,
+    received_at: String,
+}
+
+/// Header that opens a chunked stream, followed by the encrypted frames. Must match RX's `StreamHeader`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamHeader {
+    pdf_id: String,
+    version: u8,
+    alg: String,
+    encrypted_session_key_b64: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ephemeral_public_key_b64: Option<String>,
+    nonce_prefix_b64: String,
+    hash_b64: String,
+    sender_id: String,
+    signature_b64: String,
+    received_at: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The expected bytes are what RX's `associated_data`, `signed_message` and `key_request_message` build
+    #[test]
+    fn messages_match_rx() {
+        assert_eq!(
+            associated_data("pdf-1", ENVELOPE_VERSION, ALG_RSA_OAEP_256, Some(COMPRESSION_ZSTD)),
+            b"jjk:v4:RSA-OAEP-256:pdf-1:zstd",
+        );
+        assert_eq!(associated_data("pdf-1", ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, None), b"jjk:v4:X25519-HKDF-SHA256:pdf-1:none");
+        assert_eq!(
+            signed_message("pdf-1", ENVELOPE_VERSION, ALG_X25519_HKDF_SHA256, "aGFzaA==", "2026-01-02T03:04:05.000Z", None),
+            b"jjk-sig:v4:X25519-HKDF-SHA256:pdf-1:aGFzaA==:2026-01-02T03:04:05.000Z:none",
+        );
+        assert_eq!(key_request_message(1700000000, ALG_RSA_OAEP_256, None, Some("pdf-1")), b"jjk-key:1700000000:RSA-OAEP-256::pdf-1");
+    }
+
+    #[test]
+    fn received_at_is_rfc3339_in_milliseconds() {
+        let time = chrono::DateTime::from_timestamp_micros(1_767_323_045_123_456).unwrap();
+
+        assert_eq!(received_at(time), "2026-01-02T03:04:05.123Z");
+    }
+
+    #[test]
+    fn json_package_carries_the_binary_one_in_base64() {
+        let pkg = BinaryPackage {
+            version: ENVELOPE_VERSION,
+            alg: ALG_X25519_HKDF_SHA256.to_string(),
+            encrypted_session_key: Vec::new(),
+            ephemeral_public_key: Some(vec![1u8; 32]),
+            encrypted_data: b"ciphertext".to_vec(),
+            nonce: vec![2u8; 12],
+            hash: vec![3u8; 32],
+            sender_id: "jjk-tx".to_string(),
+            signature: vec![4u8; 64],
+            compression: None,
+            received_at: "2026-01-02T03:04:05.123Z".to_string(),
+        };
+
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&pkg, &mut cbor).unwrap();
+        let cbor: ciborium::Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        let data = cbor.as_map().unwrap().iter()
+            .find(|(key, _)| key.as_text() == Some("encryptedData"))
+            .map(|(_, value)| value.clone());
+        assert_eq!(data, Some(ciborium::Value::Bytes(b"ciphertext".to_vec())));
+
+        let json = serde_json::to_value(EncryptedPackage::from(pkg)).unwrap();
+        assert_eq!(json["encryptedDataB64"], b64.encode(b"ciphertext"));
+        assert_eq!(json["ephemeralPublicKeyB64"], b64.encode([1u8; 32]));
+        assert_eq!(json["signatureB64"], b64.encode([4u8; 64]));
+        assert!(json.get("compression").is_none());
+    }
+}
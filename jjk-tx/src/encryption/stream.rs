@@ -0,0 +1,195 @@
+use crate::prelude::*;
+use crate::pdf::SpooledPdf;
+use crate::transmission::Transmitter;
+use super::{Encrypter, SenderIdentity, StreamHeader, ENVELOPE_VERSION, associated_data, signed_message};
+use aes_gcm::aead::stream::EncryptorBE32;
+use rand::RngCore;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncReadExt;
+
+/// Plaintext bytes per chunk.
+const CHUNK_LEN: usize = 64 * 1024;
+
+const FLAG_NEXT: u8 = 0;
+const FLAG_LAST: u8 = 1;
+
+/// Encrypts a spooled upload into the chunked stream format, reading it back one chunk at a time.
+/// The body is `len: u32 | header JSON` followed by `flag: u8 | len: u32 | ciphertext` frames,
+/// AES-256-GCM STREAM with a big-endian 32-bit counter. The first chunk holds the `PdfInfo` JSON.
+pub struct StreamEncrypter {}
+
+impl StreamEncrypter {
+    pub async fn encrypt(
+        pdf: SpooledPdf,
+        identity: &SenderIdentity,
+        group: Option<&str>,
+        received_at: &str,
+    ) -> anyhow::Result<(String, reqwest::Body)> {
+        let info_bytes = serde_json::to_vec(&pdf.info)?;
+
+        // The hash covers every chunk in order, so the file is read once here and once more while sending
+        let mut hasher = Sha256::new();
+        hasher.update(&info_bytes);
+        let mut file = tokio::fs::File::open(pdf.file.path()).await?;
+        let mut buffer = vec![0u8; CHUNK_LEN];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        let hash_b64 = b64.encode(hasher.finalize());
+
+        // Fetch public key from RX
+        let (pdf_id, rx_pub_key) = Transmitter::get_pub_key(group, identity).await?;
+
+        let alg = rx_pub_key.alg();
+        let aad = associated_data(&pdf_id, ENVELOPE_VERSION, alg, None);
+
+        let session_key = Encrypter::session_key(&rx_pub_key, &aad)?;
+
+        let mut nonce_prefix = [0u8; 7];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        let encryptor = EncryptorBE32::from_aead(Aes256Gcm::new(&session_key.key), nonce_prefix.as_slice().into());
+
+        let signature = identity.sign(&signed_message(&pdf_id, ENVELOPE_VERSION, alg, &hash_b64, received_at, None));
+        debug!("Signed stream as sender '{}'", identity.id());
+
+        let header = serde_json::to_vec(&StreamHeader {
+            pdf_id: pdf_id.clone(),
+            version: ENVELOPE_VERSION,
+            alg: alg.to_string(),
+            encrypted_session_key_b64: b64.encode(session_key.encrypted_session_key),
+            ephemeral_public_key_b64: session_key.ephemeral_public_key.map(|epk| b64.encode(epk)),
+            nonce_prefix_b64: b64.encode(nonce_prefix),
+            hash_b64,
+            sender_id: identity.id().to_string(),
+            signature_b64: b64.encode(signature),
+            received_at: received_at.to_string(),
+        })?;
+
+        let mut head = (header.len() as u32).to_be_bytes().to_vec();
+        head.extend_from_slice(&header);
+
+        let frames = Frames {
+            file: tokio::fs::File::open(pdf.file.path()).await?,
+            _spool: pdf.file,
+            encryptor: Some(encryptor),
+            aad,
+            pending: info_bytes,
+        };
+
+        let body = futures::stream::once(async move { Ok::<_, anyhow::Error>(head) })
+            .chain(futures::stream::try_unfold(frames, Frames::next_frame));
+
+        Ok((pdf_id, reqwest::Body::wrap_stream(body)))
+    }
+}
+
+/// Encrypts the chunks as RX pulls the body, keeping only one chunk of plaintext in memory.
+struct Frames {
+    file: tokio::fs::File,
+    // Keeps the temporary file around until the last chunk is read
+    _spool: NamedTempFile,
+    encryptor: Option<EncryptorBE32<Aes256Gcm>>,
+    aad: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+impl Frames {
+    async fn next_frame(mut self) -> anyhow::Result<Option<(Vec<u8>, Self)>> {
+        if self.encryptor.is_none() {
+            return Ok(None);
+        }
+
+        // Read ahead one chunk, the current one is the last if nothing follows
+        let mut next = Vec::with_capacity(CHUNK_LEN);
+        (&mut self.file).take(CHUNK_LEN as u64).read_to_end(&mut next).await?;
+
+        let chunk = std::mem::replace(&mut self.pending, next);
+        let payload = Payload { msg: &chunk, aad: &self.aad };
+
+        let (flag, ciphertext) = if self.pending.is_empty() {
+            let encryptor = self.encryptor.take().expect("checked above");
+            (FLAG_LAST, encryptor.encrypt_last(payload))
+        } else {
+            let encryptor = self.encryptor.as_mut().expect("checked above");
+            (FLAG_NEXT, encryptor.encrypt_next(payload))
+        };
+        let ciphertext = ciphertext.map_err(|e| anyhow!("AES error: {}", e))?;
+
+        let mut frame = Vec::with_capacity(5 + ciphertext.len());
+        frame.push(flag);
+        frame.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&ciphertext);
+
+        Ok(Some((frame, self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::stream::DecryptorBE32;
+
+    const KEY: [u8; 32] = [9u8; 32];
+    const NONCE_PREFIX: [u8; 7] = [3u8; 7];
+
+    /// Frames a file behind an info chunk and collects every frame as RX reads the body.
+    async fn frame(info: &[u8], file_len: usize) -> (Vec<u8>, Vec<(u8, Vec<u8>)>) {
+        let content: Vec<u8> = (0..file_len).map(|i| (i % 251) as u8).collect();
+        let mut spool = NamedTempFile::new().unwrap();
+        spool.write_all(&content).unwrap();
+
+        let mut frames = Frames {
+            file: tokio::fs::File::open(spool.path()).await.unwrap(),
+            _spool: spool,
+            encryptor: Some(EncryptorBE32::from_aead(Aes256Gcm::new(&KEY.into()), NONCE_PREFIX.as_slice().into())),
+            aad: b"aad".to_vec(),
+            pending: info.to_vec(),
+        };
+
+        let mut out = Vec::new();
+        while let Some((frame, next)) = frames.next_frame().await.unwrap() {
+            let len = u32::from_be_bytes(frame[1..5].try_into().unwrap()) as usize;
+            assert_eq!(len, frame.len() - 5);
+            out.push((frame[0], frame[5..].to_vec()));
+            frames = next;
+        }
+
+        (content, out)
+    }
+
+    fn decrypt(frames: &[(u8, Vec<u8>)]) -> Vec<Vec<u8>> {
+        let mut decryptor = Some(DecryptorBE32::from_aead(Aes256Gcm::new(&KEY.into()), NONCE_PREFIX.as_slice().into()));
+
+        frames.iter()
+            .map(|(flag, ciphertext)| {
+                let payload = Payload { msg: ciphertext, aad: b"aad" };
+                match *flag {
+                    FLAG_LAST => decryptor.take().unwrap().decrypt_last(payload),
+                    _ => decryptor.as_mut().unwrap().decrypt_next(payload),
+                }
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn info_chunk_then_file_chunks_with_the_last_flagged() {
+        for file_len in [0, 1, CHUNK_LEN, 2 * CHUNK_LEN + 1] {
+            let (content, frames) = frame(b"{\"title\":null}", file_len).await;
+
+            let flags: Vec<u8> = frames.iter().map(|(flag, _)| *flag).collect();
+            assert_eq!(flags.last(), Some(&FLAG_LAST));
+            assert!(flags[..flags.len() - 1].iter().all(|flag| *flag == FLAG_NEXT));
+            assert_eq!(frames.len(), 1 + file_len.div_ceil(CHUNK_LEN));
+
+            let chunks = decrypt(&frames);
+            assert_eq!(chunks[0], b"{\"title\":null}");
+            assert!(chunks[1..].iter().all(|chunk| chunk.len() <= CHUNK_LEN));
+            assert_eq!(chunks[1..].concat(), content);
+        }
+    }
+}
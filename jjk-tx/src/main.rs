@@ -1,5 +1,7 @@
 use jjk_tx::{
     prelude::*,
+    encryption::SenderIdentity,
+    pdf::CaseNumberDetector,
     settings::get_settings,
     routes::upload,
     telemetry,
@@ -15,6 +17,13 @@ async fn main() -> anyhow::Result<()> {
     let (subscriber, _guard) = telemetry::get_subscriber(&settings).await?;
     telemetry::init_subscriber(subscriber);
 
+    // Load the identity packages are signed with
+    let identity = SenderIdentity::load_or_create(&settings.tx.identity)?;
+    info!("Signing packages as '{}', public key: {}", identity.id(), identity.public_key_b64());
+    let identity = web::Data::new(identity);
+
+    let detector = web::Data::new(CaseNumberDetector::new(&settings.tx.case_numbers.patterns)?);
+
     let host = settings.tx.host;
     let port = settings.tx.port;
 
@@ -23,6 +32,8 @@ async fn main() -> anyhow::Result<()> {
 
     HttpServer::new(move || {
         App::new()
+            .app_data(identity.clone())
+            .app_data(detector.clone())
             .route(
                 &format!("/{}", settings.tx.upload_endp),
                 web::post().to(upload)
@@ -0,0 +1,100 @@
+use crate::prelude::*;
+use super::PdfInfo;
+use regex::Regex;
+
+/// Finds the case number a PDF belongs to with the configured patterns, the most specific first. A pattern's
+/// `case` group is the case number when it has one, the whole match otherwise.
+pub struct CaseNumberDetector {
+    patterns: Vec<Regex>,
+}
+
+impl CaseNumberDetector {
+    pub fn new(patterns: &[String]) -> anyhow::Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern).map_err(|e| anyhow!("Invalid case number pattern {:?}: {}", pattern, e)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { patterns })
+    }
+
+    /// Tries each pattern in turn on the file name, then the title, subject and keywords, then the text of
+    /// the first page, so a specific pattern matching the text wins over a loose one matching the file name.
+    pub fn detect(&self, file_name: Option<&str>, info: &PdfInfo) -> Option<String> {
+        let sources = [
+            ("file name", file_name),
+            ("title", info.title.as_deref()),
+            ("subject", info.subject.as_deref()),
+            ("keywords", info.keywords.as_deref()),
+            ("first page", Some(info.first_page_text.as_str())),
+        ];
+
+        self.patterns.iter().find_map(|pattern| {
+            sources.iter().find_map(|(source, text)| {
+                let case_number = find(pattern, text.as_deref()?)?;
+                debug!("Case number {} found in the {}", case_number, source);
+                Some(case_number)
+            })
+        })
+    }
+}
+
+fn find(pattern: &Regex, text: &str) -> Option<String> {
+    let captures = pattern.captures(text)?;
+    let found = captures.name("case").or_else(|| captures.get(0))?;
+    Some(found.as_str().trim().to_string()).filter(|case_number| !case_number.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(patterns: &[&str]) -> CaseNumberDetector {
+        CaseNumberDetector::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn case_group_or_whole_match() {
+        let info = PdfInfo { title: Some("Judgment in case no. C-123/24".to_string()), ..Default::default() };
+
+        assert_eq!(detector(&[r"case no\. (?P<case>C-\d+/\d+)"]).detect(None, &info).as_deref(), Some("C-123/24"));
+        assert_eq!(detector(&[r"C-\d+/\d+"]).detect(None, &info).as_deref(), Some("C-123/24"));
+        assert_eq!(detector(&[r"case no\. (?P<case>\s*)"]).detect(None, &info), None);
+    }
+
+    #[test]
+    fn specific_pattern_wins_over_earlier_source() {
+        let info = PdfInfo { first_page_text: "Case C-123/24 before the court".to_string(), ..Default::default() };
+        let detector = detector(&[r"C-\d+/\d+", r"\d+"]);
+
+        assert_eq!(detector.detect(Some("scan_0042.pdf"), &info).as_deref(), Some("C-123/24"));
+    }
+
+    #[test]
+    fn sources_in_order() {
+        let info = PdfInfo {
+            subject: Some("C-2/24".to_string()),
+            keywords: Some("C-3/24".to_string()),
+            first_page_text: "C-4/24".to_string(),
+            ..Default::default()
+        };
+        let detector = detector(&[r"C-\d+/\d+"]);
+
+        assert_eq!(detector.detect(Some("C-1/24.pdf"), &info).as_deref(), Some("C-1/24"));
+        assert_eq!(detector.detect(None, &info).as_deref(), Some("C-2/24"));
+    }
+
+    #[test]
+    fn no_match() {
+        let info = PdfInfo { title: Some("Minutes".to_string()), ..Default::default() };
+
+        assert_eq!(detector(&[r"C-\d+/\d+"]).detect(Some("minutes.pdf"), &info), None);
+        assert_eq!(detector(&[]).detect(Some("C-1/24.pdf"), &info), None);
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let error = CaseNumberDetector::new(&["C-(\\d+".to_string()]).err().unwrap();
+        assert!(error.to_string().starts_with("Invalid case number pattern"));
+    }
+}
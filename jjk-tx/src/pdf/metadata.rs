@@ -0,0 +1,494 @@
+use crate::prelude::*;
+use super::PdfInfo;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use lopdf::{Dictionary, Document, PdfMetadata};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// Reads the metadata of a PDF. Documents without an Info dictionary or XMP stream are fine,
+/// and so are password-protected ones, which only report their structure.
+/// With `whole` the document is parsed whole and returned, for the sanitizer to reuse. Otherwise only its
+/// trailer, Info dictionary and page tree are, and it has no XMP properties or first page text.
+pub fn read(path: &Path, whole: bool) -> anyhow::Result<(PdfInfo, Option<Document>)> {
+    if !whole {
+        let buffer = std::fs::read(path)?;
+        let encrypted = trailer_encrypted(&buffer);
+
+        return match Document::load_metadata_mem(&buffer) {
+            Ok(metadata) => Ok((summary(metadata, encrypted), None)),
+            // lopdf only reads the metadata of encrypted documents that open without a password
+            Err(e) if encrypted => {
+                debug!("Encrypted PDF can't be read without a password: {}", e);
+                Ok((PdfInfo { encrypted, locked: true, ..Default::default() }, None))
+            }
+            Err(e) => Err(e.into()),
+        };
+    }
+
+    let document = Document::load(path)?;
+    let info = from_document(&document);
+
+    Ok((info, Some(document)))
+}
+
+fn from_document(document: &Document) -> PdfInfo {
+    // Still holding its Encrypt dictionary means lopdf couldn't open it without a password
+    let locked = document.is_encrypted();
+    let info = if locked { None } else { info_dictionary(document) };
+    let xmp = if locked { BTreeMap::new() } else { xmp_properties(document) };
+
+    let text = |key: &[u8], xmp_key: &str| {
+        info.and_then(|info| info.get(key).ok())
+            .and_then(|value| document.dereference(value).ok())
+            .and_then(|(_, value)| lopdf::decode_text_string(value).ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .or_else(|| xmp.get(xmp_key).cloned())
+    };
+    let date = |key: &[u8], xmp_key: &str| {
+        info.and_then(|info| info.get(key).ok())
+            .and_then(|value| document.dereference(value).ok())
+            .and_then(|(_, value)| lopdf::decode_text_string(value).ok())
+            .and_then(|value| parse_pdf_date(&value))
+            .or_else(|| xmp.get(xmp_key).and_then(|value| parse_xmp_date(value)))
+    };
+
+    PdfInfo {
+        title: text(b"Title", "dc:title"),
+        subject: text(b"Subject", "dc:description"),
+        author: text(b"Author", "dc:creator"),
+        keywords: text(b"Keywords", "pdf:Keywords"),
+        creator: text(b"Creator", "xmp:CreatorTool"),
+        producer: text(b"Producer", "pdf:Producer"),
+        creation_date: date(b"CreationDate", "xmp:CreateDate"),
+        mod_date: date(b"ModDate", "xmp:ModifyDate"),
+        page_count: document.get_pages().len() as u32,
+        pdf_version: document.version.clone(),
+        encrypted: locked || document.was_encrypted(),
+        locked,
+        xmp,
+        case_number: None,
+        first_page_text: if locked { String::new() } else { first_page_text(document) },
+    }
+}
+
+/// Metadata of a document read without parsing it whole, `encrypted` as told by its trailer.
+fn summary(metadata: PdfMetadata, encrypted: bool) -> PdfInfo {
+    let text = |value: Option<String>| value.map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+
+    PdfInfo {
+        title: text(metadata.title),
+        subject: text(metadata.subject),
+        author: text(metadata.author),
+        keywords: text(metadata.keywords),
+        creator: text(metadata.creator),
+        producer: text(metadata.producer),
+        creation_date: metadata.creation_date.as_deref().and_then(parse_pdf_date),
+        mod_date: metadata.modification_date.as_deref().and_then(parse_pdf_date),
+        page_count: metadata.page_count,
+        pdf_version: metadata.version,
+        encrypted,
+        locked: false,
+        xmp: BTreeMap::new(),
+        case_number: None,
+        first_page_text: String::new(),
+    }
+}
+
+/// Whether the last trailer of a PDF has an `Encrypt` entry, found without parsing the document.
+/// That trailer is the dictionary just before the last `startxref`, or the cross-reference stream it points to.
+fn trailer_encrypted(buffer: &[u8]) -> bool {
+    const WINDOW: usize = 4096;
+
+    let find = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).position(|w| w == needle);
+    let rfind = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).rposition(|w| w == needle);
+
+    let tail = &buffer[buffer.len().saturating_sub(WINDOW)..];
+    let Some(startxref) = rfind(tail, b"startxref") else {
+        return false;
+    };
+
+    // Only the last update section counts, earlier ones have trailers of their own
+    let section = &tail[..startxref];
+    let section = rfind(section, b"startxref").map_or(section, |previous| &section[previous..]);
+
+    let dictionary = match rfind(section, b"trailer") {
+        Some(trailer) => &section[trailer..],
+        None => {
+            let offset = std::str::from_utf8(&tail[startxref + b"startxref".len()..]).ok()
+                .and_then(|rest| rest.split_whitespace().next())
+                .and_then(|offset| offset.parse::<usize>().ok());
+            let Some(stream) = offset.and_then(|offset| buffer.get(offset..)) else {
+                return false;
+            };
+            let stream = &stream[..stream.len().min(WINDOW)];
+            &stream[..find(stream, b"stream").unwrap_or(stream.len())]
+        }
+    };
+
+    find(dictionary, b"/Encrypt").is_some()
+}
+
+/// Text of the first page with whitespace collapsed, lopdf breaks it up word by word.
+fn first_page_text(document: &Document) -> String {
+    let text = document.extract_text(&[1]).unwrap_or_default();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn info_dictionary(document: &Document) -> Option<&Dictionary> {
+    let info = document.trailer.get(b"Info").ok()?;
+    let (_, info) = document.dereference(info).ok()?;
+    info.as_dict().ok()
+}
+
+/// Simple properties of the catalog's XMP stream. Arrays (`rdf:Seq`, `rdf:Bag`, `rdf:Alt`) are joined with ", ".
+fn xmp_properties(document: &Document) -> BTreeMap<String, String> {
+    let mut properties = BTreeMap::new();
+
+    let stream = document.catalog().ok()
+        .and_then(|catalog| catalog.get(b"Metadata").ok())
+        .and_then(|metadata| document.dereference(metadata).ok())
+        .and_then(|(_, metadata)| metadata.as_stream().ok());
+    let Some(stream) = stream else {
+        return properties;
+    };
+
+    let content = stream.decompressed_content().unwrap_or_else(|_| stream.content.clone());
+    let Ok(xml) = std::str::from_utf8(&content) else {
+        return properties;
+    };
+    // Packets are wrapped in <?xpacket?> processing instructions, which the parser accepts
+    let xml = xml.trim_start_matches('\u{feff}');
+    let xml_doc = match roxmltree::Document::parse(xml) {
+        Ok(xml_doc) => xml_doc,
+        Err(e) => {
+            debug!("Ignoring malformed XMP metadata: {}", e);
+            return properties;
+        }
+    };
+
+    let descriptions = xml_doc.descendants()
+        .filter(|node| node.is_element() && node.tag_name().name() == "Description" && node.tag_name().namespace() == Some(RDF_NS));
+
+    for description in descriptions {
+        // Short form: properties as attributes of rdf:Description
+        for attribute in description.attributes() {
+            if let Some(ns) = attribute.namespace()
+                && ns != RDF_NS
+                && let Some(prefix) = description.lookup_prefix(ns)
+            {
+                properties.insert(format!("{}:{}", prefix, attribute.name()), attribute.value().trim().to_string());
+            }
+        }
+
+        // Long form: properties as child elements, holding text or an rdf array
+        for property in description.children().filter(|node| node.is_element()) {
+            let Some(prefix) = property.tag_name().namespace().and_then(|ns| property.lookup_prefix(ns)) else {
+                continue;
+            };
+
+            let items: Vec<&str> = property.descendants()
+                .filter(|node| node.is_element() && node.tag_name().name() == "li")
+                .filter_map(|item| item.text())
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .collect();
+
+            let value = if items.is_empty() {
+                property.text().map(str::trim).unwrap_or_default().to_string()
+            } else {
+                items.join(", ")
+            };
+
+            if !value.is_empty() {
+                properties.insert(format!("{}:{}", prefix, property.tag_name().name()), value);
+            }
+        }
+    }
+
+    properties
+}
+
+/// Parses a PDF date (`D:YYYYMMDDHHmmSSOHH'mm'`), where everything after the year is optional.
+/// Dates without an offset are taken as UTC.
+fn parse_pdf_date(text: &str) -> Option<DateTime<Utc>> {
+    let text: String = text.trim().trim_start_matches("D:").chars().filter(|c| *c != '\'').collect();
+
+    let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (stamp, offset) = text.split_at(digits);
+    if !(4..=14).contains(&stamp.len()) || stamp.len() % 2 != 0 {
+        return None;
+    }
+
+    const DEFAULTS: &str = "00000101000000";
+    let stamp = format!("{}{}", stamp, &DEFAULTS[stamp.len()..]);
+    let naive = NaiveDateTime::parse_from_str(&stamp, "%Y%m%d%H%M%S").ok()?;
+
+    let offset_secs = match offset.chars().next() {
+        None | Some('Z') => 0,
+        Some(sign @ ('+' | '-')) => {
+            let hhmm = &offset[1..];
+            let hours: i32 = hhmm.get(..2)?.parse().ok()?;
+            let minutes: i32 = hhmm.get(2..4).map_or(Some(0), |mm| mm.parse().ok())?;
+            let secs = hours * 3600 + minutes * 60;
+            if sign == '-' { -secs } else { secs }
+        }
+        Some(_) => return None,
+    };
+
+    let offset = FixedOffset::east_opt(offset_secs)?;
+    offset.from_local_datetime(&naive).single().map(|date| date.with_timezone(&Utc))
+}
+
+/// Parses an XMP date, ISO 8601 with optional seconds, time and offset.
+fn parse_xmp_date(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.with_timezone(&Utc));
+    }
+    if let Ok(date) = DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M%:z") {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M"))
+        .or_else(|_| NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default()))
+        .ok()
+        .map(|date| date.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, EncryptionState, EncryptionVersion, Object, Permissions, SaveOptions, Stream, StringFormat};
+    use std::io::Write;
+
+    const XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+        xmp:CreateDate="2024-01-31T12:00:00+02:00">
+      <dc:title><rdf:Alt><rdf:li xml:lang="x-default">XMP title</rdf:li></rdf:Alt></dc:title>
+      <dc:creator><rdf:Seq><rdf:li>A. Judge</rdf:li><rdf:li>B. Clerk</rdf:li></rdf:Seq></dc:creator>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    /// A one page document saying `text`, with the given Info dictionary and XMP packet.
+    fn document(text: &str, info: Option<Dictionary>, xmp: Option<&str>) -> Document {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+
+        let font_id = document.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica" });
+        let content = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![72.into(), 720.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+                Operation::new("ET", vec![]),
+            ],
+        };
+        let content_id = document.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+        });
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }));
+
+        let mut catalog = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+        if let Some(xmp) = xmp {
+            let xmp_id = document.add_object(Stream::new(dictionary! { "Type" => "Metadata", "Subtype" => "XML" }, xmp.as_bytes().to_vec()));
+            catalog.set("Metadata", xmp_id);
+        }
+        let catalog_id = document.add_object(catalog);
+        document.trailer.set("Root", catalog_id);
+
+        if let Some(info) = info {
+            let info_id = document.add_object(info);
+            document.trailer.set("Info", info_id);
+        }
+
+        document
+    }
+
+    fn saved(mut document: Document) -> tempfile::NamedTempFile {
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+        file
+    }
+
+    /// `document` encrypted with the given user password, an empty one opens it without asking.
+    fn encrypted(document: Document, user_password: &str) -> Document {
+        let mut document = document;
+        let id = Object::String(b"0123456789abcdef".to_vec(), StringFormat::Hexadecimal);
+        document.trailer.set("ID", vec![id.clone(), id]);
+
+        let version = EncryptionVersion::V2 {
+            document: &document,
+            owner_password: "owner",
+            user_password,
+            key_length: 128,
+            permissions: Permissions::all(),
+        };
+        let state = EncryptionState::try_from(version).unwrap();
+        document.encrypt(&state).unwrap();
+        document
+    }
+
+    fn utc(text: &str) -> Option<DateTime<Utc>> {
+        Some(DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc))
+    }
+
+    #[test]
+    fn pdf_dates() {
+        assert_eq!(parse_pdf_date("D:20240131120000Z"), utc("2024-01-31T12:00:00Z"));
+        assert_eq!(parse_pdf_date("D:20240131120000+02'00'"), utc("2024-01-31T10:00:00Z"));
+        assert_eq!(parse_pdf_date("D:20240131123456-05'30"), utc("2024-01-31T18:04:56Z"));
+        assert_eq!(parse_pdf_date("20240131120000"), utc("2024-01-31T12:00:00Z"));
+        assert_eq!(parse_pdf_date("D:2024"), utc("2024-01-01T00:00:00Z"));
+        assert_eq!(parse_pdf_date("D:202401"), utc("2024-01-01T00:00:00Z"));
+        assert_eq!(parse_pdf_date(" D:20240131 "), utc("2024-01-31T00:00:00Z"));
+    }
+
+    #[test]
+    fn malformed_pdf_dates() {
+        assert_eq!(parse_pdf_date(""), None);
+        assert_eq!(parse_pdf_date("D:202"), None);
+        assert_eq!(parse_pdf_date("D:20240"), None);
+        assert_eq!(parse_pdf_date("D:20241301"), None);
+        assert_eq!(parse_pdf_date("D:20240131120000X"), None);
+        assert_eq!(parse_pdf_date("D:20240131120000+2"), None);
+        assert_eq!(parse_pdf_date("January 31, 2024"), None);
+    }
+
+    #[test]
+    fn xmp_dates() {
+        assert_eq!(parse_xmp_date("2024-01-31T12:00:00+02:00"), utc("2024-01-31T10:00:00Z"));
+        assert_eq!(parse_xmp_date("2024-01-31T12:00:00.5Z"), utc("2024-01-31T12:00:00.5Z"));
+        assert_eq!(parse_xmp_date("2024-01-31T12:00+02:00"), utc("2024-01-31T10:00:00Z"));
+        assert_eq!(parse_xmp_date("2024-01-31T12:00:00"), utc("2024-01-31T12:00:00Z"));
+        assert_eq!(parse_xmp_date("2024-01-31T12:00"), utc("2024-01-31T12:00:00Z"));
+        assert_eq!(parse_xmp_date("2024-01-31"), utc("2024-01-31T00:00:00Z"));
+        assert_eq!(parse_xmp_date("31/01/2024"), None);
+    }
+
+    #[test]
+    fn info_dictionary_with_xmp_filling_in() {
+        let info = dictionary! {
+            "Title" => Object::string_literal("Judgment"),
+            "Subject" => Object::string_literal("   "),
+            "CreationDate" => Object::string_literal("D:20240131120000Z"),
+        };
+        let file = saved(document("Case C-123/24", Some(info), Some(XMP)));
+
+        let (info, document) = read(file.path(), true).unwrap();
+        assert!(document.is_some());
+        assert_eq!(info.title.as_deref(), Some("Judgment"));
+        assert_eq!(info.subject, None);
+        assert_eq!(info.author.as_deref(), Some("A. Judge, B. Clerk"));
+        assert_eq!(info.creation_date, utc("2024-01-31T12:00:00Z"));
+        assert_eq!(info.xmp.get("dc:title").map(String::as_str), Some("XMP title"));
+        assert_eq!(info.xmp.get("xmp:CreateDate").map(String::as_str), Some("2024-01-31T12:00:00+02:00"));
+        assert_eq!(info.page_count, 1);
+        assert_eq!(info.pdf_version, "1.7");
+        assert!(!info.encrypted);
+        assert!(info.first_page_text.contains("C-123/24"));
+    }
+
+    #[test]
+    fn xmp_dates_fill_in_for_the_info_dictionary() {
+        let file = saved(document("", None, Some(XMP)));
+
+        let (info, _) = read(file.path(), true).unwrap();
+        assert_eq!(info.title.as_deref(), Some("XMP title"));
+        assert_eq!(info.creation_date, utc("2024-01-31T10:00:00Z"));
+        assert_eq!(info.mod_date, None);
+    }
+
+    #[test]
+    fn summary_without_parsing_whole() {
+        let info = dictionary! {
+            "Title" => Object::string_literal("Judgment"),
+            "CreationDate" => Object::string_literal("D:20240131120000Z"),
+        };
+        let file = saved(document("Case C-123/24", Some(info), Some(XMP)));
+
+        let (info, document) = read(file.path(), false).unwrap();
+        assert!(document.is_none());
+        assert_eq!(info.title.as_deref(), Some("Judgment"));
+        assert_eq!(info.author, None);
+        assert_eq!(info.creation_date, utc("2024-01-31T12:00:00Z"));
+        assert_eq!(info.page_count, 1);
+        assert!(info.xmp.is_empty());
+        assert!(info.first_page_text.is_empty());
+    }
+
+    #[test]
+    fn summary_of_a_password_protected_document() {
+        let file = saved(encrypted(document("Case C-123/24", None, None), "secret"));
+
+        let (info, _) = read(file.path(), false).unwrap();
+        assert!(info.encrypted);
+        assert!(info.locked);
+
+        let (info, _) = read(file.path(), true).unwrap();
+        assert!(info.encrypted);
+        assert!(info.locked);
+    }
+
+    #[test]
+    fn summary_of_a_document_encrypted_without_a_user_password() {
+        let info = dictionary! { "Title" => Object::string_literal("Judgment") };
+        let file = saved(encrypted(document("Case C-123/24", Some(info), None), ""));
+
+        let (info, _) = read(file.path(), false).unwrap();
+        assert!(info.encrypted);
+        assert!(!info.locked);
+        assert_eq!(info.title.as_deref(), Some("Judgment"));
+        assert_eq!(info.page_count, 1);
+    }
+
+    #[test]
+    fn encrypt_entry_of_the_last_trailer() {
+        let mut bytes = Vec::new();
+        document("", None, None).save_to(&mut bytes).unwrap();
+        assert!(!trailer_encrypted(&bytes));
+
+        let mut bytes = Vec::new();
+        encrypted(document("", None, None), "secret").save_to(&mut bytes).unwrap();
+        assert!(trailer_encrypted(&bytes));
+
+        // Cross-reference stream instead of a trailer
+        let mut bytes = Vec::new();
+        let options = SaveOptions::builder().use_xref_streams(true).build();
+        encrypted(document("", None, None), "secret").save_with_options(&mut bytes, options).unwrap();
+        assert!(bytes.windows(7).all(|w| w != b"trailer"));
+        assert!(trailer_encrypted(&bytes));
+
+        assert!(!trailer_encrypted(b"%PDF-1.7 not a document"));
+    }
+
+    #[test]
+    fn malformed_xmp_is_ignored() {
+        let file = saved(document("", None, Some("<x:xmpmeta><unclosed>")));
+
+        let (info, _) = read(file.path(), true).unwrap();
+        assert!(info.xmp.is_empty());
+        assert_eq!(info.title, None);
+    }
+}
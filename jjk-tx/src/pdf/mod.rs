@@ -1,15 +1,51 @@
+pub mod case_number;
+pub mod metadata;
 pub mod parser;
+pub mod sanitizer;
+pub mod validator;
 
-pub use parser::PdfParser;
+pub use case_number::CaseNumberDetector;
+pub use parser::{PdfParser, SpooledPdf};
+pub use sanitizer::{SanitizeReport, Sanitizer};
+pub use validator::{PdfValidator, RejectReason, Rejection};
 
 use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// Document metadata, from the Info dictionary with the XMP stream filling in what it lacks.
+/// Text fields are `None` when neither has them, or when the PDF needs a password to be read.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfInfo {
+    pub title: Option<String>,
+    pub subject: Option<String>,
+    pub author: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    pub creation_date: Option<DateTime<Utc>>,
+    pub mod_date: Option<DateTime<Utc>>,
+    pub page_count: u32,
+    pub pdf_version: String,
+    pub encrypted: bool,
+    /// Encrypted with a user password, so nothing but the structure could be read.
+    #[serde(skip)]
+    pub locked: bool,
+    /// Every simple XMP property, keyed by its prefixed name (e.g. `dc:title`).
+    pub xmp: BTreeMap<String, String>,
+    /// Case number detected by `CaseNumberDetector`, which RX files the document under.
+    pub case_number: Option<String>,
+    /// Only kept to detect the case number.
+    #[serde(skip)]
+    pub first_page_text: String,
+}
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PdfData {
-    title: String,
-    subject: String,
-    author: String,
-    keywords: String,
+    #[serde(flatten)]
+    info: PdfInfo,
+    #[serde(with = "serde_bytes")]
     file: Vec<u8>,
-}
\ No newline at end of file
+}
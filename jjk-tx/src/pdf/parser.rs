@@ -1,63 +1,81 @@
 use crate::prelude::*;
-use super::PdfData;
+use crate::settings::PdfLimits;
+use super::{metadata, PdfData, PdfInfo, PdfValidator, RejectReason, Rejection};
+use super::validator::HEADER_WINDOW;
+use lopdf::Document;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+
+/// An upload written to a temporary file, so it never has to be held in memory.
+/// The file is deleted when this is dropped.
+pub struct SpooledPdf {
+    pub info: PdfInfo,
+    pub file: NamedTempFile,
+    /// The document as parsed for its metadata, so the sanitizer doesn't parse it again.
+    /// `None` above `limits.max_parse_bytes`.
+    pub document: Option<Document>,
+}
 
 pub struct PdfParser {}
 
 impl PdfParser {
-    pub async fn parse(
-        mut field: actix_multipart::Field,
+    /// Reads a spooled upload into memory, for the CBOR and JSON envelopes.
+    pub async fn load(
+        spooled: SpooledPdf,
     ) -> anyhow::Result<PdfData> {
+        let file = tokio::fs::read(spooled.file.path()).await?;
+
+        Ok(PdfData { info: spooled.info, file })
+    }
+
+    /// Writes the upload to a temporary file chunk by chunk, then reads its metadata from there.
+    /// Files that aren't acceptable PDFs fail with a `Rejection`, as early as it can be told.
+    pub async fn spool(
+        mut field: actix_multipart::Field,
+        limits: &PdfLimits,
+    ) -> anyhow::Result<SpooledPdf> {
         debug!("Parsing PDF...");
 
-        // Read the file content into memory
-        let mut file_bytes = Vec::new();
+        let spool = NamedTempFile::new()?;
+        let mut file = tokio::fs::File::from_std(spool.reopen()?);
+        let mut size = 0u64;
+        let mut head = Vec::with_capacity(HEADER_WINDOW);
+
         while let Some(chunk) = field.next().await {
-            let data = chunk.map_err(|_| anyhow!("Failed to load PDF into memory"))?;
-            file_bytes.extend_from_slice(&data);
+            let data = chunk.map_err(|_| anyhow!("Failed to read PDF upload"))?;
+
+            if head.len() < HEADER_WINDOW {
+                let missing = HEADER_WINDOW - head.len();
+                head.extend_from_slice(&data[..data.len().min(missing)]);
+                if head.len() == HEADER_WINDOW {
+                    PdfValidator::check_header(&head)?;
+                }
+            }
+
+            size += data.len() as u64;
+            PdfValidator::check_size(size, limits)?;
+
+            file.write_all(&data).await?;
         }
+        file.flush().await?;
 
-        // Load the PDF from the memory buffer
-        let doc = lopdf::Document::load_from(Cursor::new(&file_bytes))?;
-
-        // Most metadata is stored in the "Info" dictionary
-        let info_ref = doc.trailer.get(b"Info")?
-            .as_reference()?;
-
-        let info = doc.get_dictionary(info_ref)?;
-
-        // Helper to get metadata fields
-        let get_field = |key: &[u8]| {
-            info.get(key)
-                .ok()
-                .and_then(|obj| {
-                    match obj {
-                        lopdf::Object::String(bytes, _) => Some(bytes.as_slice()),
-                        lopdf::Object::Name(bytes) => Some(bytes.as_slice()),
-                        _ => None,
-                    }
-                })
-                .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
-                .unwrap_or_else(|| "Unknown".to_string())
-        };
-
-        let title = get_field(b"Title");
-        let subject = get_field(b"Subject");
-        let author = get_field(b"Author");
-        let keywords = get_field(b"Keywords");
+        if size == 0 {
+            return Err(Rejection::new(RejectReason::Empty, "Uploaded PDF is empty").into());
+        }
+        PdfValidator::check_header(&head)?;
+
+        let path = spool.path().to_path_buf();
+        let whole = size <= limits.max_parse_bytes;
+        let (info, document) = tokio::task::spawn_blocking(move || metadata::read(&path, whole)).await?
+            .map_err(|e| Rejection::new(RejectReason::Malformed, format!("PDF structure is invalid: {}", e)))?;
+
+        PdfValidator::check_document(&info, limits)?;
 
         debug!(
-            "PDF parsed. Title: '{}', Subject: '{}', Author: '{}', Keywords: '{}'",
-            title, subject, author, keywords
+            "PDF parsed. Title: {:?}, Author: {:?}, Pages: {}, Version: {}, Encrypted: {}, Size: {} bytes",
+            info.title, info.author, info.page_count, info.pdf_version, info.encrypted, size
         );
 
-        let data = PdfData {
-            title,
-            subject,
-            author,
-            keywords,
-            file: file_bytes,
-        };
-
-        Ok(data)
+        Ok(SpooledPdf { info, file: spool, document })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,429 @@
+use crate::prelude::*;
+use super::{RejectReason, Rejection, SpooledPdf};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::collections::BTreeSet;
+use tempfile::NamedTempFile;
+
+/// What was stripped from a PDF before transmission.
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SanitizeReport {
+    pub javascript: usize,
+    pub launch_actions: usize,
+    pub open_actions: usize,
+    /// `AA` dictionaries, the actions run on page, field and annotation events.
+    pub additional_actions: usize,
+    pub embedded_files: usize,
+    pub xfa_forms: usize,
+}
+
+impl SanitizeReport {
+    pub fn is_clean(&self) -> bool {
+        self.javascript + self.launch_actions + self.open_actions + self.additional_actions + self.embedded_files + self.xfa_forms == 0
+    }
+}
+
+pub struct Sanitizer {}
+
+impl Sanitizer {
+    /// Strips active content from a spooled PDF. A clean document is passed on untouched,
+    /// anything else is rewritten to a new temporary file. PDFs too large to be parsed whole are refused.
+    pub async fn clean(mut pdf: SpooledPdf) -> anyhow::Result<(SpooledPdf, SanitizeReport)> {
+        let Some(mut document) = pdf.document.take() else {
+            return Err(Rejection::new(RejectReason::TooLarge, "PDF is too large to be sanitized").into());
+        };
+
+        tokio::task::spawn_blocking(move || {
+            if document.is_encrypted() {
+                return Err(anyhow!("Password-protected PDFs cannot be sanitized"));
+            }
+
+            let report = Self::sanitize(&mut document);
+            if report.is_clean() {
+                return Ok((pdf, report));
+            }
+
+            let cleaned = NamedTempFile::new()?;
+            document.save(cleaned.path())?;
+            debug!("PDF sanitized: {:?}", report);
+
+            Ok((SpooledPdf { info: pdf.info, file: cleaned, document: None }, report))
+        })
+        .await?
+    }
+
+    /// Removes JavaScript, launch actions, the open action, additional actions, embedded files and XFA forms.
+    pub fn sanitize(document: &mut Document) -> SanitizeReport {
+        let mut report = SanitizeReport::default();
+
+        if let Ok(catalog) = document.catalog_mut() {
+            if catalog.remove(b"OpenAction").is_some() {
+                report.open_actions += 1;
+            }
+            // XFA forms are rendered from the AcroForm XFA entry when the catalog asks for it
+            catalog.remove(b"NeedsRendering");
+        }
+
+        let (scripts, files) = match catalog_entry_mut(document, b"Names") {
+            Some(names) => (names.remove(b"JavaScript"), names.remove(b"EmbeddedFiles")),
+            None => (None, None),
+        };
+
+        if let Some(acro_form) = catalog_entry_mut(document, b"AcroForm")
+            && acro_form.remove(b"XFA").is_some()
+        {
+            report.xfa_forms += 1;
+        }
+
+        // Every entry of the removed name trees is counted, what they list is removed with them and not counted again
+        let mut removed: BTreeSet<ObjectId> = BTreeSet::new();
+        for script in scripts.iter().flat_map(|tree| name_tree_values(document, tree)) {
+            match script.as_reference() {
+                Ok(id) if !removed.insert(id) => {}
+                _ => report.javascript += 1,
+            }
+        }
+        for spec in files.iter().flat_map(|tree| name_tree_values(document, tree)) {
+            report.embedded_files += 1;
+            if let Ok((_, spec)) = document.dereference(spec)
+                && let Some(ef) = as_dict(spec).and_then(|spec| spec.get(b"EF").ok()).and_then(|ef| ef.as_dict().ok())
+            {
+                removed.extend(ef.iter().filter_map(|(_, file)| file.as_reference().ok()));
+            }
+        }
+
+        // Actions and file streams that are objects of their own go along with every reference to them
+        for (id, object) in &document.objects {
+            if removed.contains(id) {
+                continue;
+            }
+            let Some(dict) = as_dict(object) else {
+                continue;
+            };
+
+            match action_type(dict) {
+                Some(b"JavaScript") => report.javascript += 1,
+                Some(b"Launch") => report.launch_actions += 1,
+                _ if is_embedded_file(dict) => report.embedded_files += 1,
+                _ => continue,
+            }
+            removed.insert(*id);
+        }
+
+        // Embedded file streams are only known by type when their producer bothered to set it,
+        // and their file specifications may be written inline, e.g. in a file attachment annotation
+        let mut embedded: Vec<ObjectId> = Vec::new();
+        for object in document.objects.values() {
+            embedded_file_refs(object, &mut embedded);
+        }
+        for id in embedded {
+            if removed.insert(id) {
+                report.embedded_files += 1;
+            }
+        }
+
+        for object in document.objects.values_mut() {
+            strip_inline(object, &mut report);
+        }
+
+        for id in removed {
+            document.delete_object(id);
+        }
+
+        // Whatever only the removed entries pointed to, e.g. script streams and XFA packets
+        document.prune_objects();
+
+        report
+    }
+}
+
+/// Removes `AA` dictionaries, `EF` entries and hostile actions written inline rather than as objects of their own.
+fn strip_inline(object: &mut Object, report: &mut SanitizeReport) {
+    let dict = match object {
+        Object::Dictionary(dict) => dict,
+        Object::Stream(stream) => &mut stream.dict,
+        Object::Array(items) => {
+            for item in items {
+                strip_inline(item, report);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    if dict.remove(b"AA").is_some() {
+        report.additional_actions += 1;
+    }
+    dict.remove(b"EF");
+
+    for key in [b"A".as_slice(), b"Next", b"OpenAction"] {
+        let Ok(action) = dict.get(key).and_then(Object::as_dict) else {
+            continue;
+        };
+
+        match action_type(action) {
+            Some(b"JavaScript") => report.javascript += 1,
+            Some(b"Launch") => report.launch_actions += 1,
+            _ => continue,
+        }
+        dict.remove(key);
+    }
+
+    for (_, value) in dict.iter_mut() {
+        strip_inline(value, report);
+    }
+}
+
+/// The file streams listed by `EF` entries, however deep the file specifications holding them are nested.
+fn embedded_file_refs(object: &Object, files: &mut Vec<ObjectId>) {
+    let dict = match object {
+        Object::Dictionary(dict) => dict,
+        Object::Stream(stream) => &stream.dict,
+        Object::Array(items) => {
+            for item in items {
+                embedded_file_refs(item, files);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    if let Ok(ef) = dict.get(b"EF").and_then(Object::as_dict) {
+        files.extend(ef.iter().filter_map(|(_, file)| file.as_reference().ok()));
+    }
+
+    for (_, value) in dict.iter() {
+        embedded_file_refs(value, files);
+    }
+}
+
+/// The values listed by a name tree, following its `Kids` down to the leaves.
+fn name_tree_values<'a>(document: &'a Document, root: &'a Object) -> Vec<&'a Object> {
+    let mut values = Vec::new();
+    let mut visited: BTreeSet<ObjectId> = BTreeSet::new();
+    let mut pending = vec![root];
+
+    while let Some(node) = pending.pop() {
+        // Kids may point back up the tree in a hostile PDF
+        if let Ok(id) = node.as_reference()
+            && !visited.insert(id)
+        {
+            continue;
+        }
+        let Some(node) = document.dereference(node).ok().and_then(|(_, node)| as_dict(node)) else {
+            continue;
+        };
+
+        if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+            values.extend(names.iter().skip(1).step_by(2));
+        }
+        if let Ok(kids) = node.get(b"Kids").and_then(Object::as_array) {
+            pending.extend(kids);
+        }
+    }
+
+    values
+}
+
+fn as_dict(object: &Object) -> Option<&Dictionary> {
+    match object {
+        Object::Dictionary(dict) => Some(dict),
+        Object::Stream(stream) => Some(&stream.dict),
+        _ => None,
+    }
+}
+
+fn action_type(dict: &Dictionary) -> Option<&[u8]> {
+    dict.get(b"S").and_then(Object::as_name).ok()
+}
+
+fn is_embedded_file(dict: &Dictionary) -> bool {
+    dict.get(b"Type").and_then(Object::as_name).is_ok_and(|name| name == b"EmbeddedFile")
+}
+
+/// A dictionary of the catalog, whether it is written inline or as an object of its own.
+fn catalog_entry_mut<'a>(document: &'a mut Document, key: &[u8]) -> Option<&'a mut Dictionary> {
+    let id = document.catalog().ok()?.get(key).ok()?.as_reference().ok();
+
+    match id {
+        Some(id) => document.get_object_mut(id).ok()?.as_dict_mut().ok(),
+        None => document.catalog_mut().ok()?.get_mut(key).ok()?.as_dict_mut().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+
+    /// A one page document with the given extra catalog entries, and the ids of its catalog and page.
+    fn document(catalog: Dictionary) -> (Document, ObjectId, ObjectId) {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        });
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }));
+
+        let mut entries = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+        entries.extend(&catalog);
+        let catalog_id = document.add_object(entries);
+        document.trailer.set("Root", catalog_id);
+
+        (document, catalog_id, page_id)
+    }
+
+    fn script(code: &str) -> Dictionary {
+        dictionary! { "S" => "JavaScript", "JS" => Object::string_literal(code) }
+    }
+
+    fn has_object(document: &Document, matches: impl Fn(&Dictionary) -> bool) -> bool {
+        document.objects.values().filter_map(as_dict).any(matches)
+    }
+
+    #[test]
+    fn clean_document_is_left_alone() {
+        let (mut document, _, _) = document(dictionary! {});
+        let objects = document.objects.len();
+
+        let report = Sanitizer::sanitize(&mut document);
+        assert!(report.is_clean());
+        assert_eq!(document.objects.len(), objects);
+        assert_eq!(document.get_pages().len(), 1);
+    }
+
+    #[test]
+    fn open_action_and_inline_name_tree_scripts() {
+        let (mut document, catalog_id, _) = document(dictionary! {
+            "OpenAction" => script("app.alert(1)"),
+            "Names" => dictionary! {
+                "JavaScript" => dictionary! {
+                    "Names" => vec![
+                        Object::string_literal("a"), script("one()").into(),
+                        Object::string_literal("b"), script("two()").into(),
+                    ],
+                },
+            },
+        });
+
+        let report = Sanitizer::sanitize(&mut document);
+        assert_eq!(report.open_actions, 1);
+        assert_eq!(report.javascript, 2);
+
+        let catalog = document.get_dictionary(catalog_id).unwrap();
+        assert!(!catalog.has(b"OpenAction"));
+        assert!(!catalog.get(b"Names").unwrap().as_dict().unwrap().has(b"JavaScript"));
+        assert!(!has_object(&document, |dict| action_type(dict).is_some()));
+    }
+
+    #[test]
+    fn referenced_name_tree_with_kids() {
+        let (mut document, _, _) = document(dictionary! {});
+        let shared = document.add_object(script("shared()"));
+        let leaf = document.add_object(dictionary! {
+            "Names" => vec![Object::string_literal("a"), shared.into(), Object::string_literal("b"), shared.into()],
+        });
+        let root = document.add_object(dictionary! { "Kids" => vec![leaf.into()] });
+        // A hostile tree pointing back at its root
+        document.get_dictionary_mut(leaf).unwrap().set("Kids", vec![root.into()]);
+        let names = document.add_object(dictionary! { "JavaScript" => root });
+        document.catalog_mut().unwrap().set("Names", names);
+
+        let report = Sanitizer::sanitize(&mut document);
+        assert_eq!(report.javascript, 1);
+        assert!(document.get_object(shared).is_err());
+    }
+
+    #[test]
+    fn embedded_files_counted_once() {
+        let (mut document, _, page_id) = document(dictionary! {});
+        let typed = document.add_object(Stream::new(dictionary! { "Type" => "EmbeddedFile" }, b"MZ".to_vec()));
+        let untyped = document.add_object(Stream::new(dictionary! {}, b"#!/bin/sh".to_vec()));
+        let listed = document.add_object(dictionary! { "Type" => "Filespec", "EF" => dictionary! { "F" => typed } });
+        let names = dictionary! {
+            "EmbeddedFiles" => dictionary! { "Names" => vec![Object::string_literal("a.exe"), listed.into()] },
+        };
+        document.catalog_mut().unwrap().set("Names", names);
+        // A file attachment annotation, not listed in the name tree
+        let annotation = document.add_object(dictionary! {
+            "Subtype" => "FileAttachment",
+            "FS" => dictionary! { "Type" => "Filespec", "EF" => dictionary! { "F" => untyped } },
+        });
+        document.get_dictionary_mut(page_id).unwrap().set("Annots", vec![annotation.into()]);
+
+        let report = Sanitizer::sanitize(&mut document);
+        assert_eq!(report.embedded_files, 2);
+        assert!(document.get_object(typed).is_err());
+        assert!(document.get_object(untyped).is_err());
+        assert!(!has_object(&document, |dict| dict.has(b"EF")));
+        assert!(document.get_object(annotation).is_ok());
+    }
+
+    #[test]
+    fn launch_and_additional_actions() {
+        let (mut document, _, page_id) = document(dictionary! {});
+        let launch = document.add_object(dictionary! { "S" => "Launch", "F" => Object::string_literal("cmd.exe") });
+        let link = document.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "A" => dictionary! { "S" => "URI", "URI" => Object::string_literal("https://example.org"), "Next" => script("next()") },
+        });
+        let widget = document.add_object(dictionary! { "Type" => "Annot", "Subtype" => "Widget", "A" => launch });
+        let page = document.get_dictionary_mut(page_id).unwrap();
+        page.set("AA", dictionary! { "O" => script("opened()") });
+        page.set("Annots", vec![link.into(), widget.into()]);
+
+        let report = Sanitizer::sanitize(&mut document);
+        assert_eq!(report.launch_actions, 1);
+        assert_eq!(report.additional_actions, 1);
+        assert_eq!(report.javascript, 1);
+        assert!(document.get_object(launch).is_err());
+        assert!(!document.get_dictionary(page_id).unwrap().has(b"AA"));
+
+        let link = document.get_dictionary(link).unwrap().get(b"A").unwrap().as_dict().unwrap();
+        assert!(link.has(b"URI"));
+        assert!(!link.has(b"Next"));
+    }
+
+    #[test]
+    fn xfa_forms() {
+        let (mut document, _, _) = document(dictionary! { "NeedsRendering" => true });
+        let xfa = document.add_object(Stream::new(dictionary! {}, b"<xdp:xdp/>".to_vec()));
+        let acro_form = document.add_object(dictionary! { "Fields" => vec![], "XFA" => xfa });
+        document.catalog_mut().unwrap().set("AcroForm", acro_form);
+
+        let report = Sanitizer::sanitize(&mut document);
+        assert_eq!(report.xfa_forms, 1);
+        assert!(!document.catalog().unwrap().has(b"NeedsRendering"));
+        assert!(document.get_object(xfa).is_err());
+    }
+
+    #[tokio::test]
+    async fn unparsed_pdf_is_refused() {
+        let pdf = SpooledPdf { info: Default::default(), file: NamedTempFile::new().unwrap(), document: None };
+
+        let error = Sanitizer::clean(pdf).await.err().unwrap();
+        assert_eq!(error.downcast_ref::<Rejection>().map(|rejection| rejection.reason), Some(RejectReason::TooLarge));
+    }
+
+    #[tokio::test]
+    async fn rewritten_pdf_still_loads() {
+        let (document, _, _) = document(dictionary! { "OpenAction" => script("app.alert(1)") });
+        let pdf = SpooledPdf { info: Default::default(), file: NamedTempFile::new().unwrap(), document: Some(document) };
+        let original = pdf.file.path().to_path_buf();
+
+        let (pdf, report) = Sanitizer::clean(pdf).await.unwrap();
+        assert_eq!(report.open_actions, 1);
+        assert_ne!(pdf.file.path(), original);
+
+        let cleaned = Document::load(pdf.file.path()).unwrap();
+        assert_eq!(cleaned.get_pages().len(), 1);
+        assert!(!cleaned.catalog().unwrap().has(b"OpenAction"));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::prelude::*;
+use crate::settings::PdfLimits;
+use super::PdfInfo;
+use actix_web::http::StatusCode;
+use std::fmt;
+
+/// How far into the file the `%PDF-` header may start, as tolerated by common readers.
+pub const HEADER_WINDOW: usize = 1024;
+
+/// Machine-readable reason an upload was turned away, or failed after TX took it.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    NoFile,
+    NotPdf,
+    Empty,
+    TooLarge,
+    Malformed,
+    NoPages,
+    TooManyPages,
+    Encrypted,
+    /// RX refused the file, or couldn't be reached.
+    Upstream,
+    /// TX failed to process the file, the cause is only logged.
+    Internal,
+}
+
+impl RejectReason {
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::NoFile | Self::Empty => StatusCode::BAD_REQUEST,
+            Self::NotPdf => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Malformed | Self::NoPages | Self::TooManyPages | Self::Encrypted => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Upstream => StatusCode::BAD_GATEWAY,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An upload that isn't an acceptable PDF. Travels inside `anyhow::Error` and is told apart from
+/// internal failures by downcasting.
+#[derive(Serialize, Debug)]
+pub struct Rejection {
+    pub reason: RejectReason,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new(reason: RejectReason, message: impl Into<String>) -> Self {
+        Self { reason, message: message.into() }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+pub struct PdfValidator {}
+
+impl PdfValidator {
+    /// Checks the start of the file for the `%PDF-` header, so renamed files are refused before being spooled.
+    pub fn check_header(head: &[u8]) -> Result<(), Rejection> {
+        let window = &head[..head.len().min(HEADER_WINDOW)];
+
+        if window.windows(5).any(|w| w == b"%PDF-") {
+            Ok(())
+        } else {
+            Err(Rejection::new(RejectReason::NotPdf, "File does not start with a PDF header"))
+        }
+    }
+
+    pub fn check_size(size: u64, limits: &PdfLimits) -> Result<(), Rejection> {
+        if size > limits.max_bytes {
+            return Err(Rejection::new(
+                RejectReason::TooLarge,
+                format!("PDF is larger than {} bytes", limits.max_bytes),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the structure lopdf found in the document.
+    pub fn check_document(info: &PdfInfo, limits: &PdfLimits) -> Result<(), Rejection> {
+        if info.locked {
+            return Err(Rejection::new(RejectReason::Encrypted, "PDF is password-protected"));
+        }
+
+        if info.page_count == 0 {
+            return Err(Rejection::new(RejectReason::NoPages, "PDF has no pages"));
+        }
+
+        if info.page_count > limits.max_pages {
+            return Err(Rejection::new(
+                RejectReason::TooManyPages,
+                format!("PDF has {} pages, at most {} are accepted", info.page_count, limits.max_pages),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: PdfLimits = PdfLimits { max_bytes: 1000, max_pages: 10, max_parse_bytes: 1000 };
+
+    fn reason(result: Result<(), Rejection>) -> Option<RejectReason> {
+        result.err().map(|rejection| rejection.reason)
+    }
+
+    #[test]
+    fn header_within_window() {
+        assert_eq!(reason(PdfValidator::check_header(b"%PDF-1.7\n")), None);
+
+        let mut head = vec![b' '; HEADER_WINDOW - 5];
+        head.extend_from_slice(b"%PDF-1.7");
+        assert_eq!(reason(PdfValidator::check_header(&head)), None);
+
+        let mut head = vec![b' '; HEADER_WINDOW - 4];
+        head.extend_from_slice(b"%PDF-1.7");
+        assert_eq!(reason(PdfValidator::check_header(&head)), Some(RejectReason::NotPdf));
+
+        assert_eq!(reason(PdfValidator::check_header(b"PK\x03\x04")), Some(RejectReason::NotPdf));
+        assert_eq!(reason(PdfValidator::check_header(b"")), Some(RejectReason::NotPdf));
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(reason(PdfValidator::check_size(1000, &LIMITS)), None);
+        assert_eq!(reason(PdfValidator::check_size(1001, &LIMITS)), Some(RejectReason::TooLarge));
+    }
+
+    #[test]
+    fn document() {
+        let info = |page_count, locked| PdfInfo { page_count, locked, ..Default::default() };
+
+        assert_eq!(reason(PdfValidator::check_document(&info(1, false), &LIMITS)), None);
+        assert_eq!(reason(PdfValidator::check_document(&info(10, false), &LIMITS)), None);
+        assert_eq!(reason(PdfValidator::check_document(&info(11, false), &LIMITS)), Some(RejectReason::TooManyPages));
+        assert_eq!(reason(PdfValidator::check_document(&info(0, false), &LIMITS)), Some(RejectReason::NoPages));
+        assert_eq!(reason(PdfValidator::check_document(&info(0, true), &LIMITS)), Some(RejectReason::Encrypted));
+    }
+
+    #[test]
+    fn statuses() {
+        assert_eq!(RejectReason::NotPdf.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(RejectReason::TooLarge.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(RejectReason::Encrypted.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(RejectReason::Upstream.status(), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -11,7 +11,7 @@ pub use rsa::{
 };
 pub use aes_gcm::{
     Aes256Gcm,
-    aead::{Aead, AeadCore, KeyInit},
+    aead::{Aead, AeadCore, KeyInit, Payload},
 };
 pub use std::{
     fs,
@@ -22,7 +22,7 @@ pub use actix_multipart::Multipart;
 pub use futures::{StreamExt, TryStreamExt};
 pub use serde::{Deserialize, Serialize};
 pub use reqwest;
-pub use rsa::{RsaPublicKey, Pkcs1v15Encrypt};
+pub use rsa::{RsaPublicKey, Oaep};
 pub use rand::rngs::OsRng;
 pub use base64::{Engine as _, engine::general_purpose::STANDARD as b64};
 pub use anyhow::anyhow;
@@ -1,49 +1,221 @@
 use crate::prelude::*;
 use crate::{
-    encryption::Encrypter,
-    transmission::Transmitter,
-    pdf::PdfParser,
+    encryption::{Encrypter, SenderIdentity, StreamEncrypter, received_at},
+    settings::{get_settings, TxSettings, WireFormat},
+    transmission::{RxReply, Transmitter},
+    pdf::{CaseNumberDetector, PdfParser, RejectReason, Rejection, SanitizeReport, Sanitizer, SpooledPdf},
 };
+use actix_web::http::StatusCode;
+
+#[derive(Deserialize)]
+pub struct UploadOptions {
+    /// File every PDF of the request under the case code of the first one RX accepts.
+    #[serde(default)]
+    group: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadResponse {
+    group_code: Option<String>,
+    files: Vec<FileResult>,
+}
+
+/// Outcome of one file of the upload. `status` and `message` are RX's answer once the file reached it.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileResult {
+    file_name: Option<String>,
+    pdf_id: Option<String>,
+    /// Case number found in the file, which RX files it under unless it was grouped with an earlier file.
+    case_number: Option<String>,
+    status: u16,
+    message: String,
+    /// Why the file wasn't accepted, `None` when it was.
+    reason: Option<RejectReason>,
+    /// What was stripped before sending, `None` when sanitization is off or the file never got that far.
+    sanitized: Option<SanitizeReport>,
+}
+
+impl FileResult {
+    fn rejected(file_name: Option<String>, rejection: Rejection) -> Self {
+        Self {
+            file_name,
+            pdf_id: None,
+            case_number: None,
+            status: rejection.reason.status().as_u16(),
+            message: rejection.message,
+            reason: Some(rejection.reason),
+            sanitized: None,
+        }
+    }
+}
+
+/// A PDF that reached RX.
+struct Sent {
+    pdf_id: String,
+    case_number: Option<String>,
+    reply: RxReply,
+    sanitized: Option<SanitizeReport>,
+}
 
 pub async fn upload(
+    identity: web::Data<SenderIdentity>,
+    detector: web::Data<CaseNumberDetector>,
+    options: web::Query<UploadOptions>,
     mut payload: Multipart,
 ) -> HttpResponse {
-    let result: anyhow::Result<HttpResponse> = async move {
-        // Iterate over the multipart stream (fields)
-        while let Ok(Some(field)) = payload.try_next().await {
-            match field.content_type() {
-                Some(ct) if ct.subtype() == "pdf" => {},
-                _ => return Ok(HttpResponse::BadRequest().body("File must be a .pdf")),
+    let settings = match get_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Upload failed: {}", e);
+            return HttpResponse::InternalServerError().json(Rejection::new(RejectReason::Internal, "Internal error"));
+        }
+    };
+
+    let mut group_code: Option<String> = None;
+    let mut files = Vec::new();
+
+    // Iterate over the multipart stream (fields), each file is encrypted and transmitted on its own
+    while let Ok(Some(field)) = payload.try_next().await {
+        let file_name = field.content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(str::to_string);
+
+        match field.content_type() {
+            Some(ct) if ct.subtype() == "pdf" => {},
+            _ => {
+                let rejection = Rejection::new(RejectReason::NotPdf, "File must be a .pdf");
+                files.push(FileResult::rejected(file_name, rejection));
+                continue;
             }
+        }
 
-            // Parse the PDF data
-            let msg = PdfParser::parse(field).await?; 
+        let group = if options.group { group_code.as_deref() } else { None };
 
-            // Serialize the PDF data
-            let msg_bytes = serde_json::to_vec(&msg)?;
+        let result = match transmit(field, file_name.as_deref(), &identity, &detector, &settings.tx, group).await {
+            Ok(Sent { pdf_id, case_number, reply, sanitized }) => {
+                if options.group && group_code.is_none() && reply.status.is_success() {
+                    group_code = Some(pdf_id.clone());
+                }
 
-            // Encrypt the bytes
-            // Returns immediately after processing the first file found
-            match Encrypter::perform_hybrid_encryption(&msg_bytes).await {
-                Ok((pdf_id, pkg)) => {
-                    let rx_response = Transmitter::send_encrypted_pkg(pdf_id, pkg).await?;
-                    return Ok(rx_response);
-                },
-                Err(e) => {
-                    debug!("Encryption failed: {:#}", e);
-                    return Err(e);
+                FileResult {
+                    file_name,
+                    pdf_id: Some(pdf_id),
+                    case_number,
+                    status: reply.status.as_u16(),
+                    message: reply.body,
+                    reason: (!reply.status.is_success()).then_some(RejectReason::Upstream),
+                    sanitized,
                 }
             }
-        }
+            Err(e) => match e.downcast::<Rejection>() {
+                Ok(rejection) => {
+                    info!("Upload of {:?} rejected: {}", file_name, rejection);
+                    FileResult::rejected(file_name, rejection)
+                }
+                // Failing to get a key from RX, or to reach it at all
+                Err(e) if e.is::<reqwest::Error>() => {
+                    error!("Upload of {:?} failed at RX: {}", file_name, e);
+                    FileResult::rejected(file_name, Rejection::new(RejectReason::Upstream, "RX could not be reached"))
+                }
+                Err(e) => {
+                    error!("Upload of {:?} failed: {:#}", file_name, e);
+                    FileResult::rejected(file_name, Rejection::new(RejectReason::Internal, "Internal error"))
+                }
+            },
+        };
 
-        Ok(HttpResponse::BadRequest().body("No file found in request"))
-    }.await;
+        files.push(result);
+    }
 
-    match result {
-        Ok(response) => response,
-        Err(e) => {
-            error!("Upload failed: {}", e);
-            HttpResponse::InternalServerError().body(e.to_string())
-        }
+    if files.is_empty() {
+        let rejection = Rejection::new(RejectReason::NoFile, "No file found in request");
+        return HttpResponse::build(rejection.reason.status()).json(rejection);
     }
-}
\ No newline at end of file
+
+    // A single outcome of TX's own shared by every file is reported as is, e.g. 415 when nothing was a PDF.
+    // RX's statuses are never passed on as TX's, a 401 from RX would read as the uploader being unauthorized.
+    let upstream = |file: &FileResult| file.reason == Some(RejectReason::Upstream);
+    let first = files[0].status;
+    let status = if files.iter().all(|file| file.reason.is_none()) {
+        StatusCode::OK
+    } else if files.iter().all(upstream) {
+        StatusCode::BAD_GATEWAY
+    } else if files.iter().all(|file| file.status == first && !upstream(file)) {
+        StatusCode::from_u16(first).unwrap_or(StatusCode::MULTI_STATUS)
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+
+    HttpResponse::build(status).json(UploadResponse { group_code, files })
+}
+
+/// Encrypts and sends one PDF in the configured wire format, returning its PDF ID and RX's answer.
+async fn transmit(
+    field: actix_multipart::Field,
+    file_name: Option<&str>,
+    identity: &SenderIdentity,
+    detector: &CaseNumberDetector,
+    settings: &TxSettings,
+    group: Option<&str>,
+) -> anyhow::Result<Sent> {
+    // Signed into the package, so RX can report when TX took the upload in
+    let received_at = received_at(chrono::Utc::now());
+
+    // Spool the upload to disk, so it is never held in memory when streaming
+    let mut pdf = PdfParser::spool(field, &settings.limits).await?;
+
+    pdf.info.case_number = detector.detect(file_name, &pdf.info);
+    let case_number = pdf.info.case_number.clone();
+
+    let (pdf, sanitized) = if settings.sanitize {
+        let (pdf, report) = Sanitizer::clean(pdf).await?;
+        (pdf, Some(report))
+    } else {
+        // Only the sanitizer needs the parsed document
+        pdf.document = None;
+        (pdf, None)
+    };
+
+    let (pdf_id, reply) = if settings.wire_format == WireFormat::Stream {
+        // Encrypt chunk by chunk while sending
+        let (pdf_id, body) = StreamEncrypter::encrypt(pdf, identity, group, &received_at).await
+            .inspect_err(|e| debug!("Encryption failed: {:#}", e))?;
+
+        let reply = Transmitter::send_stream(pdf_id.clone(), body).await?;
+        (pdf_id, reply)
+    } else {
+        send_envelope(pdf, identity, settings, group, &received_at).await?
+    };
+
+    Ok(Sent { pdf_id, case_number, reply, sanitized })
+}
+
+/// Sends the whole file in a single CBOR or JSON envelope, for RX instances without the stream receiver.
+async fn send_envelope(
+    pdf: SpooledPdf,
+    identity: &SenderIdentity,
+    settings: &TxSettings,
+    group: Option<&str>,
+    received_at: &str,
+) -> anyhow::Result<(String, RxReply)> {
+    // Load the PDF data
+    let msg = PdfParser::load(pdf).await?;
+    // Serialize the PDF data in the same format as the envelope
+    let msg_bytes = match settings.wire_format {
+        WireFormat::Cbor => {
+            let mut msg_bytes = Vec::new();
+            ciborium::into_writer(&msg, &mut msg_bytes)?;
+            msg_bytes
+        }
+        _ => serde_json::to_vec(&msg)?,
+    };
+
+    // Encrypt the bytes
+    let (pdf_id, pkg) = Encrypter::perform_hybrid_encryption(&msg_bytes, identity, settings.compression, group, received_at).await
+        .inspect_err(|e| debug!("Encryption failed: {:#}", e))?;
+
+    let reply = Transmitter::send_encrypted_pkg(pdf_id.clone(), pkg, settings.wire_format).await?;
+    Ok((pdf_id, reply))
+}
@@ -5,6 +5,61 @@ pub struct TxSettings {
     pub host: String,
     pub port: u16,
     pub upload_endp: String,
+    pub identity: IdentitySettings,
+    /// Key algorithm requested from RX for each case: `RSA-OAEP-256` or `X25519-HKDF-SHA256`.
+    pub key_alg: String,
+    pub wire_format: WireFormat,
+    /// Compression applied before encryption to JSON and CBOR envelopes. Streams are sent uncompressed.
+    pub compression: Compression,
+    /// Strip JavaScript, launch and open actions, embedded files and XFA forms from PDFs before sending them.
+    #[serde(default)]
+    pub sanitize: bool,
+    pub limits: PdfLimits,
+    pub case_numbers: CaseNumberSettings,
+}
+
+#[derive(Deserialize)]
+pub struct CaseNumberSettings {
+    /// Regular expressions tried in order. A `case` named group is the case number, the whole match otherwise.
+    pub patterns: Vec<String>,
+}
+
+/// Bounds on the PDFs accepted for upload.
+#[derive(Deserialize)]
+pub struct PdfLimits {
+    pub max_bytes: u64,
+    pub max_pages: u32,
+    /// Largest PDF parsed whole, for its XMP metadata, first page text and the sanitizer.
+    /// Larger ones only have their Info dictionary read and can't be sanitized.
+    pub max_parse_bytes: u64,
+}
+
+/// How packages are sent to RX.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    /// Chunked stream, neither side holds the whole file in memory.
+    Stream,
+    /// Whole file in one CBOR envelope with raw bytes.
+    Cbor,
+    /// Whole file in one JSON envelope with base64, for older RX instances.
+    Json,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Zstd,
+    Deflate,
+}
+
+#[derive(Deserialize)]
+pub struct IdentitySettings {
+    /// Name RX knows this TX by in its trusted sender registry.
+    pub id: String,
+    /// PKCS#8 PEM file holding the Ed25519 signing key, created on first start if missing.
+    pub key_path: String,
 }
 
 #[derive(Deserialize)]
@@ -3,13 +3,19 @@ pub mod transmitter;
 pub use transmitter::Transmitter;
 
 use crate::prelude::*;
-use crate::encryption::EncryptedPackage;
+use crate::encryption::{BinaryPackage, EncryptedPackage, ALG_RSA_OAEP_256};
+
+fn default_key_alg() -> String {
+    ALG_RSA_OAEP_256.to_string()
+}
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RxKeyResponse {
     pdf_id: String,
     pub_key: String,
+    #[serde(default = "default_key_alg")]
+    alg: String,
 }
 
 #[derive(Serialize)]
@@ -17,4 +23,18 @@ pub struct RxKeyResponse {
 pub struct RxPayload {
     pdf_id: String,
     pkg: EncryptedPackage,
-}
\ No newline at end of file
+}
+
+/// CBOR form of `RxPayload`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BinaryRxPayload {
+    pdf_id: String,
+    pkg: BinaryPackage,
+}
+
+/// What RX answered to a transmission.
+pub struct RxReply {
+    pub status: actix_web::http::StatusCode,
+    pub body: String,
+}
@@ -1,14 +1,20 @@
 use crate::prelude::*;
 use crate::{
-    settings::get_settings,
-    encryption::EncryptedPackage,
+    settings::{get_settings, WireFormat},
+    encryption::{
+        BinaryPackage, EncryptedPackage, RxPublicKey, SenderIdentity, key_request_message,
+        ALG_RSA_OAEP_256, ALG_X25519_HKDF_SHA256, CBOR_CONTENT_TYPE, STREAM_CONTENT_TYPE,
+        KEY_REQUEST_SENDER_HEADER, KEY_REQUEST_SIGNATURE_HEADER, KEY_REQUEST_TIMESTAMP_HEADER,
+    },
 };
-use super::{RxKeyResponse, RxPayload};
+use super::{BinaryRxPayload, RxKeyResponse, RxPayload, RxReply};
 
 pub struct Transmitter {}
 
 impl Transmitter {
-    pub async fn get_pub_key() -> anyhow::Result<(String, RsaPublicKey)>{
+    /// Fetches a key for a new PDF ID, filed under `group` on RX when given. The request is signed,
+    /// so RX files it only for the sender of the group.
+    pub async fn get_pub_key(group: Option<&str>, identity: &SenderIdentity) -> anyhow::Result<(String, RxPublicKey)>{
         let settings = get_settings()?;
 
         // Fetch public key from RX
@@ -17,37 +23,91 @@ impl Transmitter {
 
         let client = reqwest::Client::new();
 
-        let response = client.get(&rx_url)
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = identity.sign(&key_request_message(timestamp, &settings.tx.key_alg, None, group));
+
+        let mut request = client.get(&rx_url)
+            .query(&[("alg", &settings.tx.key_alg)])
+            .header(KEY_REQUEST_SENDER_HEADER, identity.id())
+            .header(KEY_REQUEST_TIMESTAMP_HEADER, timestamp.to_string())
+            .header(KEY_REQUEST_SIGNATURE_HEADER, b64.encode(signature));
+        if let Some(group) = group {
+            request = request.query(&[("group", group)]);
+        }
+
+        let response = request
             .send()
             .await?
+            .error_for_status()?
             .json::<RxKeyResponse>()
             .await?;
 
-        debug!("Got RX public key for PDF ID '{}'", response.pdf_id);
-
-        // Parse the PEM string from the JSON field
-        let pub_key = RsaPublicKey::from_public_key_pem(&response.pub_key)
-            .or_else(|_| RsaPublicKey::from_pkcs1_pem(&response.pub_key))?;
-
-        // Return tuple (PDF ID, RsaPublicKey)
+        debug!("Got RX {} public key for PDF ID '{}'", response.alg, response.pdf_id);
+
+        let pub_key = match response.alg.as_str() {
+            // Parse the PEM string from the JSON field
+            ALG_RSA_OAEP_256 => RxPublicKey::Rsa(
+                RsaPublicKey::from_public_key_pem(&response.pub_key)
+                    .or_else(|_| RsaPublicKey::from_pkcs1_pem(&response.pub_key))?
+            ),
+            // Raw 32 byte key, base64 encoded
+            ALG_X25519_HKDF_SHA256 => {
+                let bytes: [u8; 32] = b64.decode(&response.pub_key)?
+                    .try_into()
+                    .map_err(|_| anyhow!("X25519 public key must be 32 bytes"))?;
+                RxPublicKey::X25519(x25519_dalek::PublicKey::from(bytes))
+            }
+            other => return Err(anyhow!("RX issued a key with unsupported algorithm '{}'", other)),
+        };
+
+        // Return tuple (PDF ID, RxPublicKey)
         Ok((response.pdf_id, pub_key))
     }
 
-    pub async fn send_encrypted_pkg(pdf_id: String, pkg: EncryptedPackage) -> anyhow::Result<HttpResponse> {
+    pub async fn send_encrypted_pkg(pdf_id: String, pkg: BinaryPackage, format: WireFormat) -> anyhow::Result<RxReply> {
         let client = reqwest::Client::new();
         let settings = get_settings()?;
 
         let rx_url = format!("http://{}:{}/{}", settings.rx.host, settings.rx.port, settings.rx.rcv_endp);
         debug!("Sending payload to RX for PDF ID '{}'", pdf_id);
 
-        // Send PDF ID and Encrypted Package to RX
-        let payload = RxPayload { pdf_id: pdf_id.clone(), pkg };
+        // Send PDF ID and Encrypted Package to RX, as raw bytes in CBOR or base64 in JSON
+        let request = match format {
+            WireFormat::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(&BinaryRxPayload { pdf_id: pdf_id.clone(), pkg }, &mut body)?;
+                client.post(&rx_url)
+                    .header(reqwest::header::CONTENT_TYPE, CBOR_CONTENT_TYPE)
+                    .body(body)
+            }
+            _ => client.post(&rx_url)
+                .json(&RxPayload { pdf_id: pdf_id.clone(), pkg: EncryptedPackage::from(pkg) }),
+        };
+
+        let rx_response = request.send().await?;
+
+        Self::relay(&pdf_id, rx_response).await
+    }
+
+    /// Sends a package in the chunked stream format, encrypting it as RX reads the body.
+    pub async fn send_stream(pdf_id: String, body: reqwest::Body) -> anyhow::Result<RxReply> {
+        let client = reqwest::Client::new();
+        let settings = get_settings()?;
+
+        let rx_url = format!("http://{}:{}/{}", settings.rx.host, settings.rx.port, settings.rx.rcv_endp);
+        debug!("Streaming package to RX for PDF ID '{}'", pdf_id);
 
         let rx_response = client.post(&rx_url)
-            .json(&payload)
+            .header(reqwest::header::CONTENT_TYPE, STREAM_CONTENT_TYPE)
+            .body(body)
             .send()
             .await?;
 
+        Self::relay(&pdf_id, rx_response).await
+    }
+
+    /// Reads the RX response, to be passed on to the uploader.
+    async fn relay(pdf_id: &str, rx_response: reqwest::Response) -> anyhow::Result<RxReply> {
         let raw_status = rx_response.status().as_u16();
         let status = actix_web::http::StatusCode::from_u16(raw_status)
             .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
@@ -58,6 +118,6 @@ impl Transmitter {
             debug!("Payload sent to RX for PDF ID '{}'", pdf_id);
         }
 
-        Ok(HttpResponse::build(status).body(body))
+        Ok(RxReply { status, body })
     }
-}
\ No newline at end of file
+}